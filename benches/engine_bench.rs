@@ -3,6 +3,7 @@ use itertools::Itertools;
 use tinyrasputin::{
     engine::{
         showdown::{ShowdownEngine},
+        evaluator::{Evaluator},
         relations::{detect_cycles},
     },
     skeleton::cards::{CardValue, Card, CardSuit},
@@ -45,7 +46,7 @@ pub fn showdown_benchmark(c: &mut Criterion) {
 
         assert_eq!(hand, hand_all, "Shortcut said {}, while Complete said {}", hand, hand_all);
 
-        let (hand, hand_all) = (None, None);
+        let (mut hand, mut hand_all) = (None, None);
 
         group.bench_with_input(BenchmarkId::new("Shortcut No Straights", cards.iter().format(", ")), &cards, |b, cards| b.iter(|| {
             hand = Some(showdown.process_hand_no_straight(&cards));
@@ -63,6 +64,44 @@ pub fn showdown_benchmark(c: &mut Criterion) {
     }
 }
 
+pub fn evaluator_benchmark(c: &mut Criterion) {
+    let values = into_ordering!("2,3,4,5,6,7,8,9,T,J,Q,K,A");
+    let suits = vec![CardSuit::Hearts, CardSuit::Diamonds, CardSuit::Clubs, CardSuit::Spades];
+    let showdown = ShowdownEngine::new(values.clone());
+    let evaluator = Evaluator::new(values.clone());
+
+    for cards in (0..100).into_iter().map(|_| {
+        let size: usize = rand::random::<usize>() % 6 + 2;
+        (0..size).into_iter().map(|_| {
+            let value = rand::random::<usize>() % values.len();
+            let suit = rand::random::<usize>() % suits.len();
+            Card::new(suits[suit], values[value])
+        }).collect::<Vec<_>>()
+    }) {
+        let mut cards = ShowdownEngine::make_hand_unique(cards.into_iter());
+        cards.sort_by(|a, b| a.cmp(b));
+
+        let mut group = c.benchmark_group("Hand Evaluator");
+        let mask = evaluator.mask(&cards);
+
+        group.throughput(Throughput::Elements(cards.len() as u64));
+
+        group.bench_with_input(BenchmarkId::new("Showdown Engine", cards.iter().format(", ")), &cards, |b, cards| b.iter(|| {
+            showdown.process_hand(&cards)
+        }));
+
+        group.bench_with_input(BenchmarkId::new("Cards", cards.iter().format(", ")), &cards, |b, cards| b.iter(|| {
+            evaluator.evaluate_cards(&cards)
+        }));
+
+        group.bench_with_input(BenchmarkId::new("Mask", cards.iter().format(", ")), &mask, |b, mask| b.iter(|| {
+            evaluator.evaluate(*mask)
+        }));
+
+        group.finish();
+    }
+}
+
 pub fn cycles_benchmark(c: &mut Criterion) {
     let relations_to_test = [
         // Coherency benching
@@ -92,5 +131,5 @@ pub fn cycles_benchmark(c: &mut Criterion) {
     }
 }
 
-criterion_group!(benches, showdown_benchmark, cycles_benchmark);
+criterion_group!(benches, showdown_benchmark, evaluator_benchmark, cycles_benchmark);
 criterion_main!(benches);
\ No newline at end of file
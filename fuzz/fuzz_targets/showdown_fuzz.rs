@@ -4,7 +4,7 @@ use libfuzzer_sys::fuzz_target;
 fuzz_target!(|data: &[u8]| {
     use tinyrasputin::{
         into_ordering,
-        engine::showdown::ShowdownEngine,
+        engine::{showdown::ShowdownEngine, evaluator::{Evaluator, HandCategory}},
         skeleton::cards::{Card, CardValue},
     };
     use itertools::Itertools;
@@ -47,6 +47,19 @@ fuzz_target!(|data: &[u8]| {
             let best_hand_possible = showdown.process_hand_no_straight_all(&cards);
             let engine_comparison = showdown.compare_potential_hands(&best_hand_detected, &best_hand_possible);
             assert_eq!(engine_comparison, Ordering::Equal, "Detection contradiction: engine says {} is best, when {} is best", best_hand_detected, best_hand_possible);
+
+            // The bitmask evaluator has to find the same kind of hand as the engine
+            let evaluator = Evaluator::new(ordering);
+            let best_hand = showdown.process_hand(&cards);
+            let strength = evaluator.evaluate_cards(&cards);
+            assert_eq!(strength.category(), HandCategory::from(&best_hand), "Evaluator contradiction: engine says {} is best, evaluator says {}", best_hand, strength.category());
+            // and agree with compare_hands whenever the kinds differ (compare_hands doesn't look at kickers)
+            let fewer_cards = &cards[1..];
+            let fewer_hand = showdown.process_hand(fewer_cards);
+            let fewer_strength = evaluator.evaluate_cards(fewer_cards);
+            if strength.category() != fewer_strength.category() {
+                assert_eq!(strength.cmp(&fewer_strength), showdown.compare_potential_hands(&best_hand, &fewer_hand), "Evaluator contradiction: {} against {}", best_hand, fewer_hand);
+            }
         }
     }
 });
@@ -2,8 +2,9 @@
 use tinyrasputin::skeleton::{
     bot::PokerBot,
     actions::{Action, ActionType},
-    states::{STARTING_STACK, GameState, RoundState, TerminalState},
+    states::{STARTING_STACK, BIG_BLIND, GameState, RoundState, TerminalState},
     cards::{CardValue, CardHandExt},
+    clock::{Deadline, TimePressure},
 };
 use tinyrasputin::{
     engine::{
@@ -11,6 +12,10 @@ use tinyrasputin::{
         probability::ProbabilityEngine,
         relations::{generate_ordering, detect_cycles, RelationsExt, relationships},
         guess::{Guess},
+        equity::{EquityCalculator, OrderingDistribution, RelationOrderings},
+        posterior::OrderingPosterior,
+        constraints::ShowdownConstraints,
+        opponent::{OpponentModel, BettingLine, BettingStreet},
     },
     skeleton::cards::Card,
 };
@@ -26,36 +31,67 @@ use std::borrow::Borrow;
 // const SAMPLE_GUESS_THRESHOLD: u64 = 1000;
 const RAISE_HAPPY: f64 = 0.7;
 const RAISE_CAUTIOUS: f64 = 0.3;
+// How many runouts to sample when estimating equity, with an even split of the clock
+const EQUITY_SAMPLES: u32 = 400;
+// On the river every opponent hand is enumerated instead, under this many sampled orderings
+const RIVER_ORDERINGS: u32 = 4;
+// Never aim for fewer runouts than this, though we still stop short of it once the deadline passes
+const MIN_EQUITY_SAMPLES: u32 = 50;
+// Steps of the ordering posterior's chain to keep statistics from after every showdown, on top of its burn-in
+const POSTERIOR_STEPS: u32 = 200;
+// How many showdowns to wait between passing on what the opponent's betting started saying about the ordering
+const BETTING_BELIEF_INTERVAL: u32 = 25;
+// Extra equity we want on top of the pot odds, since a bet usually means a better than random hand
+const BET_RANGE_MARGIN: f64 = 0.05;
 #[cfg(not(debug_assertions))]
 const FILE_BYTE_SIZE: usize = 524288;
+// How many rounds to go between working out how big the internal state dump is
+#[cfg(not(debug_assertions))]
+const RESERVE_INTERVAL: u32 = 50;
 const DIRTY_ORDERING: AtomicOrdering = AtomicOrdering::SeqCst;
 
 #[derive(Debug)]
 pub struct TourneyV1Bot {
     ordering: [CardValue; 13],
     prob_engine: ProbabilityEngine,
-    // A relations cache, so we only recalculate when something changes
-    relations: RwLock<Vec<(CardValue, CardValue)>>,
+    // A relations cache, along with how sure of the ordering they make us, so we only recalculate when something changes
+    relations: RwLock<(Vec<(CardValue, CardValue)>, f64)>,
     relations_dirty: AtomicBool,
 
     // Learn your opponent to learn what you should do
     opponent_raise_count: u32,
     running_guess: Guess,
+    opponent: OpponentModel,
+
+    equity: EquityCalculator,
+    // The deadline get_action_within got, for get_action to size its work by
+    deadline: Option<Deadline>,
+    posterior: OrderingPosterior,
 
     // How many bytes have we output?
     byte_count: usize,
+    // How many bytes the internal state dump at the end takes, which is too expensive to work out for every line,
+    // or even every round
+    #[cfg(not(debug_assertions))]
+    bytes_reserved: usize,
 }
 
 impl Default for TourneyV1Bot {
     fn default() -> TourneyV1Bot {
         TourneyV1Bot {
-            ordering: generate_ordering(&vec![]),
+            ordering: generate_ordering(&[]).expect("no relations can't have cycles"),
             prob_engine: ProbabilityEngine::new(),
-            relations: RwLock::new(vec![]),
+            relations: RwLock::new((vec![], 0.0)),
             relations_dirty: AtomicBool::new(false),
             opponent_raise_count: 0,
             running_guess: Guess::new(),
+            opponent: OpponentModel::new(),
+            equity: EquityCalculator::new(EQUITY_SAMPLES, rand::random()),
+            deadline: None,
+            posterior: OrderingPosterior::new(rand::random()),
             byte_count: 0,
+            #[cfg(not(debug_assertions))]
+            bytes_reserved: 0,
         }
     }
 }
@@ -69,18 +105,34 @@ impl TourneyV1Bot {
         }
     }
 
-    fn relations(&self) -> Vec<(CardValue, CardValue)> {
+    fn refresh_relations(&self) {
         if self.relations_dirty.load(DIRTY_ORDERING) {
-            // Regenerate relations
+            // Regenerate relations, and count the orderings they leave (which is far too slow to do every decision)
             if let Ok(mut relations) = self.relations.write() {
-                *relations = self.prob_engine.relations();
+                let fresh = self.prob_engine.relations();
+                let confidence = 1.0 - (fresh.possibilities() as f64 / 6227020800.0);
+                *relations = (fresh, confidence);
                 self.relations_dirty.store(false, DIRTY_ORDERING);
             } else {
                 panic!("relations were not writeable")
             }
         }
+    }
+
+    fn relations(&self) -> Vec<(CardValue, CardValue)> {
+        self.refresh_relations();
         if let Ok(relations) = self.relations.read() {
-            relations.iter().copied().collect()
+            relations.0.to_vec()
+        } else {
+            panic!("relations were not readable")
+        }
+    }
+
+    // The share of all orderings our relations rule out
+    fn order_confidence(&self) -> f64 {
+        self.refresh_relations();
+        if let Ok(relations) = self.relations.read() {
+            relations.1
         } else {
             panic!("relations were not readable")
         }
@@ -88,10 +140,9 @@ impl TourneyV1Bot {
 
     #[cfg(not(debug_assertions))]
     fn debug_print(&mut self, string: String, necessity: f64) {
-        let bytes_reserved = self.internal_state().len();
         let bytes_to_take = string.len();
         // let mut rng = rand::thread_rng();
-        let bytes_remaining = FILE_BYTE_SIZE - bytes_reserved - self.byte_count - 1;
+        let bytes_remaining = FILE_BYTE_SIZE - self.bytes_reserved - self.byte_count - 1;
         if bytes_to_take as f64 / bytes_remaining as f64 <= necessity {
             self.byte_count += bytes_to_take;
             println!("{}", string);
@@ -103,25 +154,53 @@ impl TourneyV1Bot {
         println!("{}", string);
     }
 
+    // What we do when there is no time to think: take whatever is free, pay at most a big blind to see more, and
+    // give up on anything else
+    fn cheap_action(rs: &RoundState, player_index: usize) -> Action {
+        let continue_cost = rs.pips[1 - player_index] - rs.pips[player_index];
+        if (rs.legal_actions() & ActionType::CHECK) == ActionType::CHECK {
+            Action::Check
+        } else if continue_cost <= BIG_BLIND {
+            Action::Call
+        } else {
+            Action::Fold
+        }
+    }
+
     fn internal_state(&self) -> String {
         use std::fmt::Write;
 
         let mut string = String::new();
         let relations = self.relations();
-        writeln!(string, "({:.2}%)\n{}", 100.0 * (1.0 - (self.relations().possibilities() as f64 / 6227020800.0)), relations.debug_relations()).unwrap();
+        writeln!(string, "({:.2}%)\n{}", 100.0 * self.order_confidence(), relations.debug_relations()).unwrap();
         writeln!(string, "{:?}", self.running_guess).unwrap();
         writeln!(string, "{}", self.prob_engine.probabilities().into_iter().map(|((a, b), p)| format!("{} -> {} P({:.4})", a, b, p)).format("\n")).unwrap();
+        let best = self.prob_engine.best_orderings(1)[0];
+        writeln!(string, "Most likely [{}] (log likelihood {:.2})", best.ordering.iter().format(","), best.score).unwrap();
+        writeln!(string, "Posterior ({} showdowns) MAP [{}]", self.posterior.showdowns(), self.posterior.map_ordering().iter().format(",")).unwrap();
+        writeln!(string, "{}", self.posterior.relations(0.9).into_iter().map(|(a, b)| format!("{} -> {} P({:.4})", a, b, self.posterior.probability(&a, &b))).format("\n")).unwrap();
         writeln!(string, "Detected OPR {:.2}%", self.opponent_raise_count as f64 / 1000.0).unwrap();
         let ignored_rules = self.prob_engine.inconsistent_rule_names();
         for rule in ignored_rules {
             writeln!(string, "You should check rule [{}] for inconsistencies.", rule).unwrap();
         }
+        if let Some(report) = self.prob_engine.cycle_report() {
+            writeln!(string, "{}", report).unwrap();
+        }
         string
     }
 }
 
 impl PokerBot for TourneyV1Bot {
     fn handle_new_round(&mut self, gs: &GameState, rs: &RoundState, player_index: usize) {
+        // What we know only changes at the end of a round, and the dump grows slowly, so checking on it every so
+        // often is close enough. Working it out takes longer than a round's share of the clock.
+        #[cfg(not(debug_assertions))]
+        {
+            if gs.round_num % RESERVE_INTERVAL == 1 {
+                self.bytes_reserved = self.internal_state().len();
+            }
+        }
         self.debug_print(format!("Round #{} {:.0}", gs.round_num, gs.game_clock), gs.round_num as f64 / 1000.0);
         // let relations = self.relations();
         // let sample_space_size = relations.possibilities();
@@ -129,11 +208,14 @@ impl PokerBot for TourneyV1Bot {
         // In the unlikely event we actually calculate a "for certain" ordering, just keep it until we violate it enough
         if true { // sample_space_size > SAMPLE_GUESS_THRESHOLD {
             // let new_order = generate_ordering(&relations);
-            let new_order = generate_ordering(&self.relations());
-            // TODO Add relations that we are sure of
-            self.ordering = new_order;
+            // Once we have seen a showdown, trust the posterior's most probable ordering over our relations.
+            // Our relations only come from showdowns too, so until then there is nothing better than what we have.
+            if self.posterior.showdowns() > 0 {
+                self.ordering = self.posterior.map_ordering();
+            }
         }
-        // println!("Ordering: [{}]", self.ordering.iter().format(","));
+        // One snapshot per round, so the ordering we played with can be scored once the match is over
+        self.debug_print(format!("Ordering [{}]", self.ordering.iter().format(",")), gs.round_num as f64 / 1000.0);
         //println!("Round bot state: {:?}", self);
     }
 
@@ -145,8 +227,30 @@ impl PokerBot for TourneyV1Bot {
         let ref opp_cards = previous_state.hands[1 - player_index];
         let ref board_cards = previous_state.deck.0[..street as usize];
         // println!("Cards: {} {} <{}>", my_cards.print(), opp_cards.print(), board_cards.iter().format(", "));
+        // Folds still tell us how often the opponent backs up its raises
+        self.opponent.observe(BettingLine::new(ts, 1 - player_index), *opp_cards, 0.cmp(&my_delta));
         if opp_cards.is_some() {
             // We can see our opponents cards, so we got to showdown
+            self.posterior.observe_showdown(board_cards, &my_cards.unwrap(), &opp_cards.unwrap(), my_delta.cmp(&0));
+            let deadline = Deadline::learning(gs.game_clock, gs.round_num);
+            self.posterior.run_within(self.posterior.burn_in() + deadline.scale(POSTERIOR_STEPS), &deadline);
+            // Relations every explanation of the result agrees on are certain. The ones we only get by ruling out
+            // straights are nearly as good, but go under their own rule in case straights keep proving them wrong.
+            let constraints = ShowdownConstraints::new(board_cards, &my_cards.unwrap(), &opp_cards.unwrap(), my_delta.cmp(&0));
+            let definite = constraints.definite();
+            for (lower, higher) in definite.iter().copied() {
+                self.add_relationship("showdown", gs.round_num, 0.9, lower, higher);
+            }
+            for (lower, higher) in constraints.without_straights().into_iter().filter(|relation| !definite.contains(relation)) {
+                self.add_relationship("showdown (no straight)", gs.round_num, 0.6, lower, higher);
+            }
+            if self.opponent.showdowns() % BETTING_BELIEF_INTERVAL == 0 {
+                for (street, rule) in [(BettingStreet::Preflop, "betting preflop"), (BettingStreet::Postflop, "betting postflop")].iter() {
+                    for ((lower, higher), certainty) in self.opponent.belief_changes(*street) {
+                        self.add_relationship(*rule, gs.round_num, certainty, lower, higher);
+                    }
+                }
+            }
             let opp_cards = opp_cards.unwrap().0;
             let my_cards = my_cards.unwrap().0;
 
@@ -205,44 +309,6 @@ impl PokerBot for TourneyV1Bot {
                     };
 
                     self.debug_print(format!("Winner hand: {} Loser hand: {}", actual_winner, actual_loser), 0.5);
-
-                    if my_delta != 0 {
-                        match (actual_winner, actual_loser) {
-                            // Same hand type relations
-                            (Hand::Pair(winner), Hand::Pair(loser)) => self.add_relationship("pair -> pair", gs.round_num, 0.9, showdown_engine.highest_card_value(loser), showdown_engine.highest_card_value(winner)),
-                            (Hand::ThreeOfAKind(winner), Hand::ThreeOfAKind(loser)) => self.add_relationship("3k -> 3k", gs.round_num, 0.9, showdown_engine.highest_card_value(loser), showdown_engine.highest_card_value(winner)),
-                            (Hand::FourOfAKind(winner), Hand::FourOfAKind(loser)) => self.add_relationship("4k -> 4k", gs.round_num, 0.9, showdown_engine.highest_card_value(loser), showdown_engine.highest_card_value(winner)),
-                            (Hand::FullHouse(winner), Hand::FullHouse(loser)) => {
-                                let (winner_triple_value, winner_pair_value) = {
-                                    let values = ShowdownEngine::values(winner.iter());
-                                    assert!(values.len() == 2);
-                                    let counts: Vec<_> = values.iter().map(|x| ShowdownEngine::count(winner.iter().copied(), x)).collect();
-                                    // There are only 5 cards max, so one must be higher
-                                    if counts[0] > counts[1] {
-                                        (values[0], values[1])
-                                    } else {
-                                        (values[1], values[0])
-                                    }
-                                };
-                                let (loser_triple_value, loser_pair_value) = {
-                                    let values = ShowdownEngine::values(loser.iter());
-                                    assert!(values.len() == 2);
-                                    let counts: Vec<_> = values.iter().map(|x| ShowdownEngine::count(winner.iter().copied(), x)).collect();
-                                    if counts[0] > counts[1] {
-                                        (values[0], values[1])
-                                    } else {
-                                        (values[1], values[0])
-                                    }
-                                };
-                                if winner_triple_value != loser_triple_value {
-                                    self.add_relationship("fh -> fh (ltv -> wtv)", gs.round_num, 0.9, loser_triple_value, winner_triple_value);
-                                } else {
-                                    self.add_relationship("fh -> fh (lpv -> wpv)", gs.round_num, 0.9, loser_pair_value, winner_pair_value);
-                                }
-                            },
-                            (_, _) => {}
-                        }
-                    }
                 },
                 (None, Some(_)) => {
                     print_prediction(&my_hand, &opp_hand, -1);
@@ -263,14 +329,6 @@ impl PokerBot for TourneyV1Bot {
                     }
                 },
                 (None, None) => {
-                    // We only detected high cards. This relationship is very unlikely. Rating 1 / 12
-                    // let (winner, loser) = (winner.cards().iter().copied().nth(0).unwrap(), loser.cards().iter().copied().nth(0).unwrap());
-                    let (winner_hand, loser_hand) = if my_delta > 0 {
-                        (my_cards, opp_cards)
-                    } else {
-                        (opp_cards, my_cards)
-                    };
-
                     let delta = match showdown_engine.compare_potential_hands(&my_hand, &opp_hand) {
                         Ordering::Greater => 1,
                         Ordering::Less => -1,
@@ -278,32 +336,32 @@ impl PokerBot for TourneyV1Bot {
                     };
 
                     print_prediction(&my_hand, &opp_hand, delta);
-
-                    if my_delta != 0 {
-                        for winning_card in winner_hand.into_iter() {
-                            for losing_card in loser_hand.into_iter() {
-                                // self.add_relationship("hc -> hc", gs.round_num, 0.25, losing_card.value(), winning_card.value());
-                                self.add_relationship("hc -> hc", gs.round_num, 0.25, losing_card.value(), winning_card.value());
-                            }
-                        }
-                    } else { // In case of a draw, the high card is a board card, but this is very unlikely
-                        let high_card = showdown_engine.highest_card(board_cards);
-                        for card in winner_hand.into_iter().chain(loser_hand.into_iter()) {
-                            self.add_relationship("(draw) hc -> hc", gs.round_num, my_delta.signum() as f64 * 0.1, card.value(), high_card.value())
-                        }
-                    }
                 }
             }
         }
     }
 
+    fn get_action_within(&mut self, gs: &GameState, rs: &RoundState, player_index: usize, deadline: &Deadline) -> Action {
+        match deadline.pressure() {
+            TimePressure::Critical => {
+                self.debug_print(format!("Out of time in round #{} ({:.3}s left), playing it safe", gs.round_num, gs.game_clock), 0.5);
+                TourneyV1Bot::cheap_action(rs, player_index)
+            },
+            TimePressure::Short | TimePressure::Relaxed => {
+                self.deadline = Some(*deadline);
+                self.get_action(gs, rs, player_index)
+            },
+        }
+    }
+
     fn get_action(&mut self, gs: &GameState, rs: &RoundState, player_index: usize) -> Action {
         // todo!()
         let legal_actions = rs.legal_actions();
 
         let street = rs.street;
         let ref board_cards = rs.deck.0[..street as usize];
-        let ref my_cards = rs.hands[player_index].unwrap().0;
+        let my_hand = rs.hands[player_index].unwrap();
+        let ref my_cards = my_hand.0;
         let my_pip = rs.pips[player_index];
         let opp_pip = rs.pips[1 - player_index];
         let my_stack = rs.stacks[player_index];
@@ -322,7 +380,22 @@ impl PokerBot for TourneyV1Bot {
             self.debug_print(format!("Running Guess {:?}", self.running_guess), 1.0);
         }
 
-        let order_confidence = 1.0 - (self.relations().possibilities() as f64 / 6227020800.0);
+        let relations = self.relations();
+        let order_confidence = self.order_confidence();
+        let relation_orderings = RelationOrderings::new(&relations);
+        // Once we have seen a showdown, the posterior knows more about the ordering than our relations do
+        let orderings: &dyn OrderingDistribution = if self.posterior.showdowns() > 0 { &self.posterior } else { &relation_orderings };
+        // Fewer samples make for a noisier estimate, but a noisy estimate is better than losing on time
+        let deadline = self.deadline.take().unwrap_or_else(|| Deadline::new(gs.game_clock, gs.round_num));
+        self.equity.set_samples(deadline.scale(EQUITY_SAMPLES).max(MIN_EQUITY_SAMPLES));
+        let equity = if street == 5 {
+            self.equity.enumerate_within(&my_hand, board_cards, orderings, deadline.scale(RIVER_ORDERINGS), &deadline)
+        } else {
+            self.equity.equity_within(&my_hand, board_cards, orderings, &deadline)
+        };
+        // The share of the final pot we would be putting in to continue
+        let pot_odds = continue_cost as f64 / (pot_total + continue_cost) as f64;
+        self.debug_print(format!("Equity {} against pot odds {:.2}", equity, pot_odds), 0.3);
 
         // println!("Pot {} my stack {} opp stack {} CC {}", pot_total, my_stack, opp_stack, continue_cost);
         // println!("My cards [{}]", my_cards.iter().format(", "));
@@ -427,43 +500,25 @@ impl PokerBot for TourneyV1Bot {
             }
         } else {
             // Gain some data points
+            let opr = (self.opponent_raise_count as f64 / gs.round_num as f64).min(1.0);
             if gs.round_num > 100 {
-                let opr = (self.opponent_raise_count as f64 / gs.round_num as f64).min(1.0);
                 self.debug_print(format!("Opponent Raise Percent: {:.2}%", opr * 100.0), 0.1);
-                if opr > RAISE_HAPPY {
-                    // Our opponent is relatively raise happy. Them raising tells us nothing. ignore them and call their bluffs
-                    if rng.gen_bool(opr) {
-                        act(Action::Call)
-                    } else {
-                        // welp, we gotta hedge our bets somewhere
-                        act(Action::Fold)
-                    }
-                } else if opr < RAISE_CAUTIOUS {
-                    // Our oppnent is relatively raise cautious. Them raising means they got something. Be cautious.
-                    if rng.gen_bool(opr) {
-                        act(Action::Fold)
-                    } else {
-                        act(Action::Call)
-                    }
-                } else {
-                    // Our opponent is a normal goddamn person. Act normal. Flip a coin. That our their an allin bot, and we don't have enough information
-                    if rand::random() {
-                        act(Action::Fold)
-                    } else {
-                        act(Action::Call)
-                    }
-                }
+            }
+            // Raise-happy opponents tell us nothing by betting, so pay whenever the pot odds are right.
+            // Raise-cautious opponents have something when they bet, so we want more than that.
+            let margin = if gs.round_num <= 100 {
+                BET_RANGE_MARGIN
+            } else if opr > RAISE_HAPPY {
+                0.0
+            } else if opr < RAISE_CAUTIOUS {
+                2.0 * BET_RANGE_MARGIN
             } else {
-                // Assume the worst
-                if (legal_actions & ActionType::RAISE) == ActionType::RAISE {
-                    if continue_cost > 0 {
-                        Action::Raise(raise)
-                    } else {
-                        act(Action::Call)
-                    }
-                } else {
-                    act(Action::Fold)
-                }
+                BET_RANGE_MARGIN
+            };
+            if equity.equity() >= pot_odds + margin {
+                act(Action::Call)
+            } else {
+                act(Action::Fold)
             }
         }
     }
@@ -474,3 +529,76 @@ impl Drop for TourneyV1Bot {
         println!("{}", self.internal_state());
     }
 }
+
+#[test]
+fn posterior_steps_test() {
+    use tinyrasputin::{into_cards, skeleton::{cards::{CardDeck, CardHand}, states::NUM_ROUNDS}};
+    let hand = |cards: &str| { let cards = into_cards!(cards); Some(CardHand([cards[0], cards[1]])) };
+    let showdown = |board: &str, ours: &str, theirs: &str, delta: i32| TerminalState {
+        deltas: [delta, -delta],
+        previous: RoundState {
+            button: 5,
+            street: 5,
+            pips: [0, 0],
+            stacks: [STARTING_STACK - 2, STARTING_STACK - 2],
+            hands: [hand(ours), hand(theirs)],
+            deck: CardDeck(into_cards!(board)),
+            previous: None,
+        },
+    };
+    let mut bot = TourneyV1Bot::default();
+    // The last round with the whole clock left has time for every step
+    let gs = GameState { bankroll: 0, game_clock: 30.0, round_num: NUM_ROUNDS };
+    // Quad twos on the board, so the kickers decide: aces beat kings, and kings beat queens
+    for _ in 0..5 {
+        bot.handle_round_over(&gs, &showdown("2c,2d,2h,2s,9c", "Ah,Ad", "Kh,Kd", 2), 0);
+        bot.handle_round_over(&gs, &showdown("2c,2d,2h,2s,9c", "Qh,Qd", "Kc,Ks", -2), 0);
+    }
+    // Every showdown leaves the posterior with statistics to play the next round with, not just its current state
+    assert_eq!(bot.posterior.burn_in(), 0);
+    assert!(bot.posterior.probability(&CardValue::King, &CardValue::Ace) > 0.9);
+    assert!(bot.posterior.probability(&CardValue::Queen, &CardValue::King) > 0.9);
+    assert!(bot.posterior.relations(0.9).contains(&(CardValue::King, CardValue::Ace)));
+}
+
+#[test]
+fn time_pressure_test() {
+    use tinyrasputin::{into_cards, skeleton::{cards::{CardDeck, CardHand}, clock::RESERVE, states::NUM_ROUNDS}};
+    let cards = into_cards!("Ah,Kd");
+    let rs = RoundState {
+        button: 0,
+        street: 0,
+        pips: [1, 2],
+        stacks: [STARTING_STACK - 1, STARTING_STACK - 2],
+        hands: [Some(CardHand([cards[0], cards[1]])), None],
+        deck: CardDeck(vec![]),
+        previous: None,
+    };
+    let mut bot = TourneyV1Bot::default();
+    let relaxed = GameState { bankroll: 0, game_clock: 30.0, round_num: 1 };
+    bot.get_action_within(&relaxed, &rs, 0, &Deadline::new(relaxed.game_clock, relaxed.round_num));
+    assert_eq!(bot.equity.samples(), EQUITY_SAMPLES);
+    // Halfway through with an eighth of the clock left, every decision gets a quarter of the usual time
+    let short = GameState { bankroll: 0, game_clock: (30.0 - RESERVE) / 8.0 + RESERVE, round_num: NUM_ROUNDS / 2 + 1 };
+    let deadline = Deadline::new(short.game_clock, short.round_num);
+    assert_eq!(deadline.pressure(), TimePressure::Short);
+    bot.get_action_within(&short, &rs, 0, &deadline);
+    assert_eq!(bot.equity.samples(), EQUITY_SAMPLES / 4);
+    // and the next one with a relaxed clock goes back to the full amount
+    bot.get_action_within(&relaxed, &rs, 0, &Deadline::new(relaxed.game_clock, relaxed.round_num));
+    assert_eq!(bot.equity.samples(), EQUITY_SAMPLES);
+    // Out of time, we take the free look or fold to anything bigger than a big blind
+    let critical = GameState { bankroll: 0, game_clock: RESERVE, round_num: 10 };
+    assert_eq!(bot.get_action_within(&critical, &rs, 0, &Deadline::new(critical.game_clock, critical.round_num)), Action::Call);
+}
+
+#[test]
+fn match_clock_test() {
+    use tinyrasputin::skeleton::{dealer::Dealer, states::NUM_ROUNDS};
+    // A whole match, with everything the bot learns along the way, has to fit in the clock
+    let mut bot = TourneyV1Bot::default();
+    let mut opponent = crate::bots::Lesson2Bot::default();
+    let result = Dealer::new(7).play_match(&mut bot, &mut opponent, NUM_ROUNDS);
+    assert_eq!(result.rounds, NUM_ROUNDS);
+    assert!(result.game_clocks[0] > 0.0, "{:?}", result);
+}
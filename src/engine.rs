@@ -1,5 +1,12 @@
 #[allow(dead_code)]
 pub mod showdown;
+pub mod evaluator;
+pub mod equity;
+pub mod constraints;
+pub mod opponent;
+pub mod posterior;
 pub mod relations;
+pub mod ranking;
 pub mod probability;
 pub mod guess;
+pub mod scoring;
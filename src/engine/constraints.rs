@@ -0,0 +1,294 @@
+//! Turns a showdown into constraints on the value ordering. Every way the two hands could have been ranked to
+//! give the announced result is an explanation, and each explanation is a set of (lower, higher) relations that
+//! all have to hold. At least one of the explanations is true, so relations they all share are certain.
+//!
+//! Straights are the only hands that depend on which values are next to each other, which pairwise relations
+//! can't describe. Explanations where a hand made a straight carry no relations, and are marked so callers can
+//! decide how much they believe in them.
+
+use crate::skeleton::cards::{Card, CardHand, CardSuit, CardValue};
+use super::evaluator::HandCategory;
+use super::relations::detect_cycles;
+use itertools::Itertools;
+use std::cmp::Ordering;
+
+// A set of (lower, higher) relations that all hold
+type Conjunction = Vec<(CardValue, CardValue)>;
+// Sets of relations where at least one of them holds
+type Disjunction = Vec<Conjunction>;
+// The groups a made hand is compared by, most significant first: the values that can fill the group, and how
+// many of them the best five cards use
+type Levels = Vec<(Vec<CardValue>, usize)>;
+
+/// One way the showdown could have come out the way it did
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Explanation {
+    /// (lower, higher) relations that all hold under this explanation
+    pub relations: Vec<(CardValue, CardValue)>,
+    /// Whether one of the hands has to have made a straight (or straight flush)
+    pub straight: bool,
+}
+
+impl Explanation {
+    /// Whether every relation of the explanation agrees with an ordering (lowest to highest)
+    pub fn holds(&self, ordering: &[CardValue; 13]) -> bool {
+        let position = |value: &CardValue| ordering.iter().position(|v| v == value).unwrap();
+        self.relations.iter().all(|(lower, higher)| position(lower) < position(higher))
+    }
+}
+
+/// Everything a showdown tells us about the ordering
+#[derive(Debug, Clone)]
+pub struct ShowdownConstraints {
+    explanations: Vec<Explanation>,
+}
+
+impl ShowdownConstraints {
+    /// Explains a showdown between two hands (hole cards plus the board), where `result` is how the first hand
+    /// did against the second
+    pub fn new(board: &[Card], first: &CardHand, second: &CardHand, result: Ordering) -> ShowdownConstraints {
+        let first: Vec<Card> = first.0.iter().chain(board).copied().collect();
+        let second: Vec<Card> = second.0.iter().chain(board).copied().collect();
+        // Look at everything from the winner's side
+        let (winner, loser) = if result == Ordering::Less { (second, first) } else { (first, second) };
+        let tie = result == Ordering::Equal;
+
+        let mut explanations = vec![];
+        for (winner_category, winner_levels) in possible_hands(&winner) {
+            for (loser_category, loser_levels) in possible_hands(&loser) {
+                let straight = is_straight(winner_category) || is_straight(loser_category);
+                let explained = match winner_category.cmp(&loser_category) {
+                    Ordering::Greater if !tie => vec![vec![]],
+                    Ordering::Equal => match (&winner_levels, &loser_levels) {
+                        (Some(winner_levels), Some(loser_levels)) => if tie {
+                            ties(winner_levels, loser_levels)
+                        } else {
+                            wins(winner_levels, loser_levels)
+                        },
+                        // Straights, or hands where the ordering decides which groups play
+                        _ => vec![vec![]],
+                    },
+                    _ => vec![],
+                };
+                explanations.extend(explained.into_iter().map(|relations| Explanation { relations, straight }));
+            }
+        }
+        ShowdownConstraints { explanations: simplify(explanations) }
+    }
+
+    pub fn explanations(&self) -> &[Explanation] {
+        &self.explanations
+    }
+
+    /// Relations that hold however the showdown came about
+    pub fn definite(&self) -> Vec<(CardValue, CardValue)> {
+        shared(self.explanations.iter())
+    }
+
+    /// Relations that hold unless somebody made a straight. Falls back to `definite` when a straight is the only
+    /// explanation.
+    pub fn without_straights(&self) -> Vec<(CardValue, CardValue)> {
+        if self.explanations.iter().all(|explanation| explanation.straight) {
+            self.definite()
+        } else {
+            shared(self.explanations.iter().filter(|explanation| !explanation.straight))
+        }
+    }
+
+    /// Whether some explanation agrees with an ordering (lowest to highest). Explanations with straights don't
+    /// check that the straight is really there, so this errs on the side of yes.
+    pub fn explains(&self, ordering: &[CardValue; 13]) -> bool {
+        self.explanations.iter().any(|explanation| explanation.holds(ordering))
+    }
+}
+
+fn is_straight(category: HandCategory) -> bool {
+    category == HandCategory::Straight || category == HandCategory::StraightFlush
+}
+
+// Relations every explanation has in common
+fn shared<'a, I>(mut explanations: I) -> Vec<(CardValue, CardValue)> where I: Iterator<Item = &'a Explanation> {
+    let first = match explanations.next() {
+        Some(first) => first.relations.clone(),
+        None => return vec![],
+    };
+    explanations.fold(first, |shared, explanation| {
+        shared.into_iter().filter(|relation| explanation.relations.contains(relation)).collect()
+    })
+}
+
+// Every category the cards could make under some ordering, with the groups it is compared by. Straights have no
+// levels, and neither do hands where the ordering decides which groups play (two trips, or three pairs).
+fn possible_hands(cards: &[Card]) -> Vec<(HandCategory, Option<Levels>)> {
+    let values = cards.iter().map(|card| card.value()).unique().collect::<Vec<_>>();
+    let count = |value: &CardValue| cards.iter().filter(|card| card.value() == *value).count();
+    let with_count = |n: usize| values.iter().copied().filter(|value| count(value) == n).collect::<Vec<_>>();
+    let (quads, trips, pairs, singles) = (with_count(4), with_count(3), with_count(2), with_count(1));
+    let suits = [CardSuit::Spades, CardSuit::Hearts, CardSuit::Diamonds, CardSuit::Clubs];
+    let flush = suits.iter()
+        .map(|suit| cards.iter().filter(|card| card.suit() == *suit).map(|card| card.value()).collect::<Vec<_>>())
+        .find(|values| values.len() >= 5);
+
+    let base = if !quads.is_empty() {
+        let kickers = values.iter().copied().filter(|value| !quads.contains(value)).collect::<Vec<_>>();
+        (HandCategory::FourOfAKind, Some(vec![(quads, 1), (kickers, 1)]))
+    } else if trips.len() > 1 {
+        (HandCategory::FullHouse, None)
+    } else if !trips.is_empty() && !pairs.is_empty() {
+        (HandCategory::FullHouse, Some(vec![(trips, 1), (pairs, 1)]))
+    } else if let Some(ref flush) = flush {
+        (HandCategory::Flush, Some(vec![(flush.clone(), 5)]))
+    } else if !trips.is_empty() {
+        (HandCategory::ThreeOfAKind, Some(vec![(trips, 1), (singles, 2)]))
+    } else if pairs.len() > 2 {
+        (HandCategory::TwoPair, None)
+    } else if pairs.len() == 2 {
+        (HandCategory::TwoPair, Some(vec![(pairs, 2), (singles, 1)]))
+    } else if pairs.len() == 1 {
+        (HandCategory::Pair, Some(vec![(pairs, 1), (singles, 3)]))
+    } else {
+        (HandCategory::HighCard, Some(vec![(singles, 5)]))
+    };
+
+    let mut hands = vec![];
+    // Any five different values are a straight under the orderings that put them next to each other
+    if flush.is_some() {
+        hands.push((HandCategory::StraightFlush, None));
+    }
+    if values.len() >= 5 && base.0 < HandCategory::Straight {
+        hands.push((HandCategory::Straight, None));
+    }
+    hands.push(base);
+    hands
+}
+
+// Ways the winner's levels beat the loser's: tie every level before one the winner wins
+fn wins(winner: &Levels, loser: &Levels) -> Disjunction {
+    let mut explanations = vec![];
+    let mut tied: Disjunction = vec![vec![]];
+    for ((winner, k), (loser, _)) in winner.iter().zip(loser.iter()) {
+        explanations.extend(and(&tied, &top_wins(winner, loser, *k)));
+        tied = and(&tied, &top_ties(winner, loser, *k));
+    }
+    explanations
+}
+
+// Ways every level ties
+fn ties(first: &Levels, second: &Levels) -> Disjunction {
+    first.iter().zip(second.iter())
+        .fold(vec![vec![]], |tied, ((first, k), (second, _))| and(&tied, &top_ties(first, second, *k)))
+}
+
+// Ways the highest `k` winner values beat the highest `k` loser values. The highest value only one side has decides,
+// as long as fewer than `k` shared values are above it.
+fn top_wins(winner: &[CardValue], loser: &[CardValue], k: usize) -> Disjunction {
+    let common = winner.iter().copied().filter(|value| loser.contains(value)).collect::<Vec<_>>();
+    let only_loser = loser.iter().copied().filter(|value| !winner.contains(value)).collect::<Vec<_>>();
+    let below = (common.len() + 1).saturating_sub(k);
+    winner.iter().copied().filter(|value| !loser.contains(value))
+        .flat_map(|high| {
+            let beaten = only_loser.iter().map(move |low| (*low, high)).collect::<Conjunction>();
+            // `combinations(0)` gives nothing at all (not one empty combination), so no shared values is its own case
+            if below == 0 {
+                return vec![beaten];
+            }
+            common.iter().copied().combinations(below).map(|lows| {
+                beaten.iter().copied().chain(lows.into_iter().map(|low| (low, high))).collect()
+            }).collect::<Vec<_>>()
+        })
+        .collect()
+}
+
+// Ways the highest `k` values on both sides are the same: `k` shared values are above everything only one side has
+fn top_ties(first: &[CardValue], second: &[CardValue], k: usize) -> Disjunction {
+    let common = first.iter().copied().filter(|value| second.contains(value)).collect::<Vec<_>>();
+    let different = first.iter().chain(second.iter()).copied()
+        .filter(|value| !common.contains(value))
+        .collect::<Vec<_>>();
+    if different.is_empty() {
+        vec![vec![]]
+    } else if common.len() < k {
+        vec![]
+    } else {
+        common.iter().copied().combinations(k)
+            .map(|highs| different.iter().flat_map(|low| highs.iter().map(move |high| (*low, *high))).collect())
+            .collect()
+    }
+}
+
+// Both disjunctions hold. Combinations that contradict themselves are dropped.
+fn and(a: &Disjunction, b: &Disjunction) -> Disjunction {
+    a.iter().cartesian_product(b.iter())
+        .map(|(a, b)| a.iter().chain(b.iter()).copied().unique().collect::<Conjunction>())
+        .filter(|relations| detect_cycles(relations).is_empty())
+        .collect()
+}
+
+// Sorts relations, drops duplicate explanations, and drops explanations that ask for more than another one does
+fn simplify(explanations: Vec<Explanation>) -> Vec<Explanation> {
+    let explanations = explanations.into_iter()
+        .map(|Explanation { mut relations, straight }| {
+            relations.sort();
+            relations.dedup();
+            Explanation { relations, straight }
+        })
+        .unique_by(|explanation| (explanation.relations.clone(), explanation.straight))
+        .collect::<Vec<_>>();
+    let weaker = |weak: &Explanation, strong: &Explanation| {
+        weak != strong
+            && (strong.straight || !weak.straight)
+            && weak.relations.iter().all(|relation| strong.relations.contains(relation))
+    };
+    explanations.iter()
+        .filter(|explanation| !explanations.iter().any(|other| weaker(other, explanation)))
+        .cloned()
+        .collect()
+}
+
+#[test]
+fn constraints_test() {
+    use crate::into_cards;
+    use super::evaluator::Evaluator;
+    use rand::{prelude::*, rngs::StdRng};
+    use crate::skeleton::dealer::permute_values;
+    let hand = |cards: &str| { let cards = into_cards!(cards); CardHand([cards[0], cards[1]]) };
+
+    // Pair against pair, so the winner's pair is higher (unless somebody made a straight)
+    let board = into_cards!("2c,5d,9h,Js,7c");
+    let constraints = ShowdownConstraints::new(&board, &hand("Ah,Ad"), &hand("Kh,Kd"), Ordering::Greater);
+    assert!(constraints.without_straights().contains(&(CardValue::King, CardValue::Ace)));
+    assert!(constraints.explanations().iter().any(|explanation| explanation.straight));
+    assert!(constraints.definite().is_empty());
+
+    // Quads on the board make straights impossible, and the best kicker wins
+    let board = into_cards!("2c,2d,2h,2s,9c");
+    let constraints = ShowdownConstraints::new(&board, &hand("Qh,Qd"), &hand("Kc,Ks"), Ordering::Less);
+    assert!(constraints.explanations().iter().all(|explanation| !explanation.straight));
+    // The nine on the board might outkick both hands, but then it would have been a split pot
+    assert_eq!(constraints.definite(), vec![(CardValue::Nine, CardValue::King), (CardValue::Queen, CardValue::King)]);
+    let constraints = ShowdownConstraints::new(&board, &hand("Qh,Qd"), &hand("Kc,Ks"), Ordering::Equal);
+    assert_eq!(constraints.definite(), vec![(CardValue::Queen, CardValue::Nine), (CardValue::King, CardValue::Nine)]);
+
+    // Flush against flush is decided by either of the winner's hole cards, unless one was a straight flush
+    let board = into_cards!("2h,5h,9h,Jc,7s");
+    let constraints = ShowdownConstraints::new(&board, &hand("Ah,3h"), &hand("Kh,4h"), Ordering::Greater);
+    assert!(constraints.explanations().iter().any(|explanation| explanation.straight));
+    let flushes = constraints.explanations().iter().filter(|explanation| !explanation.straight).collect::<Vec<_>>();
+    assert_eq!(flushes.len(), 2);
+    assert!(flushes.iter().any(|explanation| explanation.relations == vec![(CardValue::Four, CardValue::Ace), (CardValue::King, CardValue::Ace)]));
+    assert!(constraints.without_straights().is_empty());
+
+    // Whatever the ordering, the result it gives is explained
+    let mut rng = StdRng::seed_from_u64(176);
+    let board = into_cards!("3c,8d,Th,Qs,4c");
+    let (first, second) = (hand("6h,Kd"), hand("9c,Ad"));
+    let cards = |hand: &CardHand| hand.0.iter().chain(board.iter()).copied().collect::<Vec<_>>();
+    for _ in 0..200 {
+        let ordering = permute_values(&mut rng);
+        let result = Evaluator::new(ordering).compare(&cards(&first), &cards(&second));
+        let constraints = ShowdownConstraints::new(&board, &first, &second, result);
+        assert!(constraints.explains(&ordering), "[{}] does not explain {:?}", ordering.iter().format(","), result);
+        let ordering_position = |value: &CardValue| ordering.iter().position(|v| v == value).unwrap();
+        assert!(constraints.definite().iter().all(|(lower, higher)| ordering_position(lower) < ordering_position(higher)));
+    }
+}
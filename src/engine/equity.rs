@@ -0,0 +1,363 @@
+//! Equity of a hand against a random opponent hand, either sampled (Monte Carlo) or enumerated exactly,
+//! when we aren't sure of the value ordering
+
+use crate::skeleton::{cards::{Card, CardDeck, CardHand, CardValue}, clock::Deadline};
+use super::evaluator::{Evaluator, CardMask};
+use super::relations::{OrderingGenerator, resolve_cycles};
+use rand::{prelude::*, rngs::StdRng};
+use std::cmp::Ordering;
+use std::fmt;
+
+// Generating an ordering is a lot more expensive than dealing a runout, so each sampled ordering
+// is reused for this many runouts
+const RUNOUTS_PER_ORDERING: u32 = 16;
+
+/// Something we can draw value orderings (lowest to highest) from
+pub trait OrderingDistribution {
+    fn sample_ordering(&self, rng: &mut StdRng) -> [CardValue; 13];
+}
+
+/// We know the ordering for certain
+impl OrderingDistribution for [CardValue; 13] {
+    fn sample_ordering(&self, _: &mut StdRng) -> [CardValue; 13] {
+        *self
+    }
+}
+
+/// Orderings that respect a set of (lower, higher) relations, drawn with the engine's shuffle like `OrderingGenerator` does.
+/// Relations that would close a cycle are left out, so the ones listed first win out (see `resolve_cycles`).
+pub struct RelationOrderings(OrderingGenerator);
+
+impl RelationOrderings {
+    pub fn new(relations: &[(CardValue, CardValue)]) -> RelationOrderings {
+        let generator = OrderingGenerator::try_new(relations).unwrap_or_else(|_| {
+            // Every relation is as good as the next, and resolve_cycles keeps ties in the order they came in
+            let relations = relations.iter().map(|relation| (*relation, 1.0)).collect::<Vec<_>>();
+            OrderingGenerator::try_new(&resolve_cycles(&relations).kept).expect("resolve_cycles leaves no cycles")
+        });
+        RelationOrderings(generator)
+    }
+}
+
+impl OrderingDistribution for RelationOrderings {
+    fn sample_ordering(&self, rng: &mut StdRng) -> [CardValue; 13] {
+        self.0.generate(rng)
+    }
+}
+
+/// How often our hand wins, ties and loses at showdown
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Equity {
+    pub win: f64,
+    pub tie: f64,
+    pub lose: f64,
+    pub samples: u32,
+}
+
+impl Equity {
+    /// Expected share of the pot, counting ties as half
+    pub fn equity(&self) -> f64 {
+        self.win + self.tie / 2.0
+    }
+}
+
+impl fmt::Display for Equity {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        write!(fmt, "W {:.1}% T {:.1}% L {:.1}% ({} samples)", 100.0 * self.win, 100.0 * self.tie, 100.0 * self.lose, self.samples)
+    }
+}
+
+/// Estimates equity by dealing random opponent hands and runouts.
+/// Everything is drawn from one seeded rng, so the same seed gives the same sequence of estimates.
+#[derive(Debug)]
+pub struct EquityCalculator {
+    samples: u32,
+    rng: StdRng,
+}
+
+impl EquityCalculator {
+    pub fn new(samples: u32, seed: u64) -> EquityCalculator {
+        EquityCalculator {
+            samples,
+            rng: StdRng::seed_from_u64(seed),
+        }
+    }
+
+    pub fn samples(&self) -> u32 {
+        self.samples
+    }
+
+    pub fn set_samples(&mut self, samples: u32) {
+        self.samples = samples;
+    }
+
+    /// Equity of `hand` given the visible `board` (0 to 5 cards), with orderings drawn from `orderings`
+    pub fn equity<D: OrderingDistribution + ?Sized>(&mut self, hand: &CardHand, board: &[Card], orderings: &D) -> Equity {
+        self.runouts(hand, board, orderings, None)
+    }
+
+    /// Like `equity`, but stops dealing runouts once `deadline` has passed (after at least one ordering's worth)
+    pub fn equity_within<D: OrderingDistribution + ?Sized>(&mut self, hand: &CardHand, board: &[Card], orderings: &D, deadline: &Deadline) -> Equity {
+        self.runouts(hand, board, orderings, Some(deadline))
+    }
+
+    fn runouts<D: OrderingDistribution + ?Sized>(&mut self, hand: &CardHand, board: &[Card], orderings: &D, deadline: Option<&Deadline>) -> Equity {
+        let mut deck: Vec<Card> = CardDeck::full().0.into_iter()
+            .filter(|card| !hand.0.contains(card) && !board.contains(card))
+            .collect();
+        let to_deal = 2 + 5 - board.len();
+        let (mut wins, mut ties, mut dealt_runouts) = (0, 0, 0);
+        let mut evaluator = Evaluator::new(orderings.sample_ordering(&mut self.rng));
+        for sample in 0..self.samples {
+            if sample > 0 && sample % RUNOUTS_PER_ORDERING == 0 {
+                if deadline.is_some_and(Deadline::expired) {
+                    break;
+                }
+                evaluator = Evaluator::new(orderings.sample_ordering(&mut self.rng));
+            }
+            // Only shuffle as much of the deck as we deal
+            let (dealt, _) = deck.partial_shuffle(&mut self.rng, to_deal);
+            let shared = evaluator.mask(board) | evaluator.mask(&dealt[2..]);
+            let ours = evaluator.evaluate(shared | evaluator.mask(hand.0));
+            let theirs = evaluator.evaluate(shared | evaluator.mask(&dealt[..2]));
+            match ours.cmp(&theirs) {
+                Ordering::Greater => wins += 1,
+                Ordering::Equal => ties += 1,
+                Ordering::Less => {},
+            }
+            dealt_runouts += 1;
+        }
+        Equity::from_counts(wins, ties, dealt_runouts)
+    }
+
+    /// Enumerates every opponent hand and runout exactly (see `exact_equity`) under `draws` orderings
+    /// drawn from `orderings`, and averages the results. Only cheap enough on the turn and river.
+    pub fn enumerate<D: OrderingDistribution + ?Sized>(&mut self, hand: &CardHand, board: &[Card], orderings: &D, draws: u32) -> Equity {
+        self.enumerations(hand, board, orderings, draws, None)
+    }
+
+    /// Like `enumerate`, but stops drawing orderings once `deadline` has passed (after at least one)
+    pub fn enumerate_within<D: OrderingDistribution + ?Sized>(&mut self, hand: &CardHand, board: &[Card], orderings: &D, draws: u32, deadline: &Deadline) -> Equity {
+        self.enumerations(hand, board, orderings, draws, Some(deadline))
+    }
+
+    fn enumerations<D: OrderingDistribution + ?Sized>(&mut self, hand: &CardHand, board: &[Card], orderings: &D, draws: u32, deadline: Option<&Deadline>) -> Equity {
+        let (mut win, mut tie, mut samples, mut drawn) = (0.0, 0.0, 0, 0);
+        while drawn < draws.max(1) {
+            if drawn > 0 && deadline.is_some_and(Deadline::expired) {
+                break;
+            }
+            let equity = exact_equity(hand, board, orderings.sample_ordering(&mut self.rng));
+            win += equity.win;
+            tie += equity.tie;
+            samples += equity.samples;
+            drawn += 1;
+        }
+        let (win, tie) = (win / drawn as f64, tie / drawn as f64);
+        Equity { win, tie, lose: 1.0 - win - tie, samples }
+    }
+
+    /// Equity marginalised over weighted orderings, like the ones `ProbabilityEngine::sample_orderings` gives.
+    /// Each ordering gets its own estimate (exact on the river, `samples` runouts otherwise), and the weights
+    /// should add up to one.
+    pub fn posterior_equity(&mut self, hand: &CardHand, board: &[Card], orderings: &[([CardValue; 13], f64)]) -> PosteriorEquity {
+        let equities: Vec<(Equity, f64)> = orderings.iter()
+            .map(|(ordering, weight)| (if board.len() == 5 {
+                exact_equity(hand, board, *ordering)
+            } else {
+                self.equity(hand, board, ordering)
+            }, *weight))
+            .collect();
+        let mut mean = Equity { win: 0.0, tie: 0.0, lose: 0.0, samples: 0 };
+        for (equity, weight) in &equities {
+            mean.win += weight * equity.win;
+            mean.tie += weight * equity.tie;
+            mean.lose += weight * equity.lose;
+            mean.samples += equity.samples;
+        }
+        let variance = equities.iter().map(|(equity, weight)| weight * (equity.equity() - mean.equity()).powi(2)).sum();
+        PosteriorEquity { mean, variance, orderings: orderings.len() }
+    }
+}
+
+/// Equity averaged over a weighted set of orderings, along with how much it moves between them.
+/// A high variance means the hand only looks strong under some of the orderings we believe in.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct PosteriorEquity {
+    pub mean: Equity,
+    pub variance: f64,
+    pub orderings: usize,
+}
+
+impl PosteriorEquity {
+    pub fn std_dev(&self) -> f64 {
+        self.variance.sqrt()
+    }
+}
+
+impl fmt::Display for PosteriorEquity {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        write!(fmt, "{:.1}% ± {:.1}% over {} orderings", 100.0 * self.mean.equity(), 100.0 * self.std_dev(), self.orderings)
+    }
+}
+
+impl Equity {
+    fn from_counts(wins: u32, ties: u32, total: u32) -> Equity {
+        let denominator = total.max(1) as f64;
+        Equity {
+            win: wins as f64 / denominator,
+            tie: ties as f64 / denominator,
+            lose: (total - wins - ties) as f64 / denominator,
+            samples: total,
+        }
+    }
+}
+
+/// Exact equity under a known ordering, found by going through every opponent hand and every runout
+/// left in the deck. `samples` is the number of matchups that were scored.
+/// That is 990 matchups on the river and about 45 thousand on the turn, but the flop already takes a million.
+pub fn exact_equity(hand: &CardHand, board: &[Card], ordering: [CardValue; 13]) -> Equity {
+    let evaluator = Evaluator::new(ordering);
+    let deck: Vec<CardMask> = CardDeck::full().0.iter()
+        .filter(|card| !hand.0.contains(card) && !board.contains(card))
+        .map(|card| evaluator.card_mask(card))
+        .collect();
+    let ours = evaluator.mask(hand.0) | evaluator.mask(board);
+    let (mut wins, mut ties, mut total) = (0, 0, 0);
+    // Every runout, then every opponent hand out of what is left
+    for_each_subset(&deck, 5 - board.len(), CardMask::default(), &mut |runout| {
+        let our_strength = evaluator.evaluate(ours | runout);
+        let board = evaluator.mask(board) | runout;
+        for_each_subset(&deck, 2, runout, &mut |theirs| {
+            match our_strength.cmp(&evaluator.evaluate(board | theirs)) {
+                Ordering::Greater => wins += 1,
+                Ordering::Equal => ties += 1,
+                Ordering::Less => {},
+            }
+            total += 1;
+        });
+    });
+    Equity::from_counts(wins, ties, total)
+}
+
+// Calls `f` with every way of picking `size` cards from `deck` that doesn't overlap `taken`
+fn for_each_subset<F: FnMut(CardMask)>(deck: &[CardMask], size: usize, taken: CardMask, f: &mut F) {
+    fn pick<F: FnMut(CardMask)>(deck: &[CardMask], size: usize, taken: CardMask, chosen: CardMask, f: &mut F) {
+        if size == 0 {
+            return f(chosen);
+        }
+        for (i, card) in deck.iter().enumerate() {
+            if !card.overlaps(taken) {
+                pick(&deck[i + 1..], size - 1, taken, chosen | *card, f);
+            }
+        }
+    }
+    pick(deck, size, taken, CardMask::default(), f)
+}
+
+#[test]
+fn equity_test() {
+    use crate::{into_cards, into_ordering};
+    let ordering = into_ordering!("2,3,4,5,6,7,8,9,T,J,Q,K,A");
+    let hand = |cards: &str| { let cards = into_cards!(cards); CardHand([cards[0], cards[1]]) };
+
+    // Same seed, same estimate
+    let first = EquityCalculator::new(2000, 176).equity(&hand("Ah,Ad"), &[], &ordering);
+    let second = EquityCalculator::new(2000, 176).equity(&hand("Ah,Ad"), &[], &ordering);
+    assert_eq!(first, second);
+    // Aces hold up about 85% of the time against a random hand
+    assert!((first.equity() - 0.85).abs() < 0.03, "{}", first);
+    assert!((first.win + first.tie + first.lose - 1.0).abs() < 1e-9);
+
+    // The nuts on the river can't lose
+    let nuts = EquityCalculator::new(500, 176).equity(&hand("Ah,Kh"), &into_cards!("Qh,Jh,Th,2c,3d"), &ordering);
+    assert_eq!(nuts.lose, 0.0);
+
+    // If all we know is that aces are the lowest value, pocket aces are bad
+    let relations: Vec<_> = ordering.iter().filter(|value| **value != CardValue::Ace).map(|value| (CardValue::Ace, *value)).collect();
+    let flipped = EquityCalculator::new(2000, 176).equity(&hand("Ah,Ad"), &[], &RelationOrderings::new(&relations));
+    assert!(flipped.equity() < first.equity(), "{} vs {}", flipped, first);
+
+    // A cycle loses the relation that closes it instead of taking us down
+    let cycle = RelationOrderings::new(&[(CardValue::Two, CardValue::Three), (CardValue::Three, CardValue::Four), (CardValue::Four, CardValue::Two)]);
+    let mut rng = StdRng::seed_from_u64(176);
+    for _ in 0..100 {
+        let ordering = cycle.sample_ordering(&mut rng);
+        let position = |value| ordering.iter().position(|v| *v == value).unwrap();
+        assert!(position(CardValue::Two) < position(CardValue::Three) && position(CardValue::Three) < position(CardValue::Four));
+    }
+}
+
+#[test]
+fn exact_equity_test() {
+    use crate::{into_cards, into_ordering, skeleton::clock::RESERVE};
+    let ordering = into_ordering!("2,3,4,5,6,7,8,9,T,J,Q,K,A");
+    let hand = |cards: &str| { let cards = into_cards!(cards); CardHand([cards[0], cards[1]]) };
+
+    // Every opponent hand on the river, every river card and opponent hand on the turn
+    let river = exact_equity(&hand("Ah,Kd"), &into_cards!("Ac,7d,4s,9h,2c"), ordering);
+    assert_eq!(river.samples, 45 * 44 / 2);
+    let turn = exact_equity(&hand("Ah,Kd"), &into_cards!("Ac,7d,4s,9h"), ordering);
+    assert_eq!(turn.samples, 46 * (45 * 44 / 2));
+    assert!((turn.win + turn.tie + turn.lose - 1.0).abs() < 1e-9);
+
+    // A royal flush on the board is a guaranteed split
+    let split = exact_equity(&hand("2c,3d"), &into_cards!("Ah,Kh,Qh,Jh,Th"), ordering);
+    assert_eq!(split.tie, 1.0);
+    // Quad aces with the last ace ours: only a straight flush beats it, and none is possible here
+    let quads = exact_equity(&hand("Ah,Ad"), &into_cards!("Ac,As,7d,2c,9h"), ordering);
+    assert_eq!(quads.win, 1.0);
+    // Counted by hand: 8-9 makes a straight on 5-6-7-K-K, which only the other 8-9s tie (3 * 3 combos).
+    // It loses to the full houses, a king with a five, six or seven (3 * 2 * 3 combos) or pocket fives,
+    // sixes and sevens (3 * 3 combos), and to the last pair of kings.
+    let straight = exact_equity(&hand("8c,9d"), &into_cards!("5h,6s,7c,Kd,Kh"), ordering);
+    assert_eq!(straight.samples, 990);
+    assert_eq!((straight.tie * 990.0).round() as u32, 3 * 3);
+    assert_eq!((straight.lose * 990.0).round() as u32, 3 * 2 * 3 + 3 * 3 + 1);
+
+    // Sampling should land close to the exact answer
+    let sampled = EquityCalculator::new(4000, 176).equity(&hand("Ah,Kd"), &into_cards!("Ac,7d,4s,9h"), &ordering);
+    assert!((sampled.equity() - turn.equity()).abs() < 0.03, "{} vs {}", sampled, turn);
+    // and enumerating under a single known ordering is the same as exact_equity
+    let enumerated = EquityCalculator::new(0, 176).enumerate(&hand("Ah,Kd"), &into_cards!("Ac,7d,4s,9h,2c"), &ordering, 3);
+    assert!((enumerated.win - river.win).abs() < 1e-9 && (enumerated.tie - river.tie).abs() < 1e-9);
+
+    // Out of time, we make do with a single ordering's worth of work
+    let late = Deadline::new(RESERVE, 10);
+    let hurried = EquityCalculator::new(4000, 176).equity_within(&hand("Ah,Kd"), &into_cards!("Ac,7d,4s,9h"), &ordering, &late);
+    assert_eq!(hurried.samples, RUNOUTS_PER_ORDERING);
+    let hurried = EquityCalculator::new(0, 176).enumerate_within(&hand("Ah,Kd"), &into_cards!("Ac,7d,4s,9h,2c"), &ordering, 3, &late);
+    assert_eq!(hurried.samples, river.samples);
+}
+
+#[test]
+fn posterior_equity_test() {
+    use crate::{into_cards, into_ordering};
+    use super::probability::ProbabilityEngine;
+    let ordering = into_ordering!("2,3,4,5,6,7,8,9,T,J,Q,K,A");
+    let hand = |cards: &str| { let cards = into_cards!(cards); CardHand([cards[0], cards[1]]) };
+    let mut calculator = EquityCalculator::new(200, 176);
+
+    // A single ordering we are sure of has no variance
+    let board = into_cards!("Ac,7d,4s,9h,2c");
+    let known = calculator.posterior_equity(&hand("Ah,Kd"), &board, &[(ordering, 1.0)]);
+    assert_eq!(known.mean, exact_equity(&hand("Ah,Kd"), &board, ordering));
+    assert_eq!(known.variance, 0.0);
+
+    // Once we are fairly sure that aces are the highest value, orderings agreeing with it weigh more
+    let mut engine = ProbabilityEngine::new();
+    for value in ordering.iter().filter(|value| **value != CardValue::Ace) {
+        engine.update("ace high", value, &CardValue::Ace, 0.9);
+    }
+    let mut flipped = ordering;
+    flipped.rotate_right(1);
+    assert!(engine.ordering_weight(&ordering) > engine.ordering_weight(&flipped));
+    let orderings = engine.sample_orderings(20, &mut StdRng::seed_from_u64(176));
+    assert_eq!(orderings.len(), 20);
+    assert!((orderings.iter().map(|(_, weight)| weight).sum::<f64>() - 1.0).abs() < 1e-9);
+
+    // Pocket aces are good whatever else happens, but how good depends on the rest of the ordering
+    let aces = calculator.posterior_equity(&hand("Ah,Ad"), &[], &orderings);
+    assert!(aces.mean.equity() > 0.7, "{}", aces);
+    assert!(aces.variance > 0.0, "{}", aces);
+}
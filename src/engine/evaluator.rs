@@ -0,0 +1,296 @@
+//! Allocation-free hand evaluation on 52-bit card masks
+//!
+//! Cards are placed in a mask by their rank under the value ordering, so the ordering is only looked at
+//! once per card when the mask is built. Everything after that is bit twiddling on four 13-bit suit masks.
+
+use crate::skeleton::cards::{Card, CardValue};
+use super::showdown::{Hand, PotentialHand};
+use std::borrow::Borrow;
+use std::cmp::Ordering;
+use std::fmt;
+use std::ops::BitOr;
+
+const RANK_MASK: u64 = 0x1fff;
+
+/// Set of cards, one bit per card. Bit `13 * suit + rank` is set, where rank is the position of the
+/// card's value in the ordering of the Evaluator that built the mask.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+pub struct CardMask(pub u64);
+
+impl CardMask {
+    pub fn count(&self) -> u32 {
+        self.0.count_ones()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0 == 0
+    }
+
+    pub fn overlaps(&self, other: CardMask) -> bool {
+        self.0 & other.0 != 0
+    }
+
+    // 13-bit rank mask of a single suit
+    fn suit(&self, suit: usize) -> u16 {
+        ((self.0 >> (13 * suit)) & RANK_MASK) as u16
+    }
+}
+
+impl BitOr for CardMask {
+    type Output = CardMask;
+    fn bitor(self, other: CardMask) -> CardMask {
+        CardMask(self.0 | other.0)
+    }
+}
+
+/// Hand categories, weakest to strongest
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum HandCategory {
+    HighCard,
+    Pair,
+    TwoPair,
+    ThreeOfAKind,
+    Straight,
+    Flush,
+    FullHouse,
+    FourOfAKind,
+    StraightFlush,
+    RoyalFlush,
+}
+
+impl HandCategory {
+    fn from_bits(bits: u32) -> HandCategory {
+        match bits {
+            0 => HandCategory::HighCard,
+            1 => HandCategory::Pair,
+            2 => HandCategory::TwoPair,
+            3 => HandCategory::ThreeOfAKind,
+            4 => HandCategory::Straight,
+            5 => HandCategory::Flush,
+            6 => HandCategory::FullHouse,
+            7 => HandCategory::FourOfAKind,
+            8 => HandCategory::StraightFlush,
+            _ => HandCategory::RoyalFlush,
+        }
+    }
+}
+
+impl From<&Hand> for HandCategory {
+    fn from(hand: &Hand) -> HandCategory {
+        match hand {
+            Hand::RoyalFlush(_) => HandCategory::RoyalFlush,
+            Hand::StraightFlush(_) => HandCategory::StraightFlush,
+            Hand::FourOfAKind(_) => HandCategory::FourOfAKind,
+            Hand::FullHouse(_) => HandCategory::FullHouse,
+            Hand::Flush(_) => HandCategory::Flush,
+            Hand::Straight(_) => HandCategory::Straight,
+            Hand::ThreeOfAKind(_) => HandCategory::ThreeOfAKind,
+            Hand::TwoPair(_) => HandCategory::TwoPair,
+            Hand::Pair(_) => HandCategory::Pair,
+        }
+    }
+}
+
+impl From<&PotentialHand> for HandCategory {
+    // Draws don't win anything at showdown, so they count as high card
+    fn from(hand: &PotentialHand) -> HandCategory {
+        match hand.showdown() {
+            Some(ref hand) => HandCategory::from(hand),
+            None => HandCategory::HighCard,
+        }
+    }
+}
+
+impl fmt::Display for HandCategory {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            HandCategory::HighCard => write!(fmt, "High Card"),
+            HandCategory::Pair => write!(fmt, "Pair"),
+            HandCategory::TwoPair => write!(fmt, "Two Pair"),
+            HandCategory::ThreeOfAKind => write!(fmt, "Three of a Kind"),
+            HandCategory::Straight => write!(fmt, "Straight"),
+            HandCategory::Flush => write!(fmt, "Flush"),
+            HandCategory::FullHouse => write!(fmt, "Full House"),
+            HandCategory::FourOfAKind => write!(fmt, "Four of a Kind"),
+            HandCategory::StraightFlush => write!(fmt, "Straight Flush"),
+            HandCategory::RoyalFlush => write!(fmt, "Royal Flush"),
+        }
+    }
+}
+
+/// Totally ordered strength of the best 5-card hand. The category sits in the top bits, followed by
+/// up to five 4-bit ranks (the ones that decide ties, most significant first), so comparing two
+/// strengths is comparing two integers.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct HandStrength(pub u32);
+
+impl HandStrength {
+    fn new(category: HandCategory, ranks: &[u32]) -> HandStrength {
+        let kickers = ranks.iter().take(5).enumerate().fold(0, |acc, (i, rank)| acc | (rank << (4 * (4 - i))));
+        HandStrength(((category as u32) << 20) | kickers)
+    }
+
+    pub fn category(&self) -> HandCategory {
+        HandCategory::from_bits(self.0 >> 20)
+    }
+}
+
+/// Evaluates hands under a fixed value ordering
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Evaluator {
+    // Rank (position in the ordering) of every CardValue, indexed by the value's discriminant
+    ranks: [u8; 13],
+}
+
+impl Evaluator {
+    pub fn new(ordering: [CardValue; 13]) -> Evaluator {
+        let mut ranks = [0; 13];
+        for (rank, value) in ordering.iter().enumerate() {
+            ranks[*value as usize] = rank as u8;
+        }
+        Evaluator { ranks }
+    }
+
+    pub fn rank(&self, value: CardValue) -> u8 {
+        self.ranks[value as usize]
+    }
+
+    pub fn card_mask(&self, card: &Card) -> CardMask {
+        CardMask(1 << (13 * card.suit() as u64 + self.rank(card.value()) as u64))
+    }
+
+    pub fn mask<I, C: Borrow<Card>>(&self, cards: I) -> CardMask where I: IntoIterator<Item = C> {
+        cards.into_iter().fold(CardMask::default(), |mask, card| mask | self.card_mask(card.borrow()))
+    }
+
+    /// Strength of the best 5-card hand in the cards (duplicates are ignored)
+    pub fn evaluate_cards(&self, cards: &[Card]) -> HandStrength {
+        self.evaluate(self.mask(cards))
+    }
+
+    pub fn compare(&self, a: &[Card], b: &[Card]) -> Ordering {
+        self.evaluate_cards(a).cmp(&self.evaluate_cards(b))
+    }
+
+    /// Strength of the best 5-card hand in the mask
+    pub fn evaluate(&self, mask: CardMask) -> HandStrength {
+        let suits = [mask.suit(0), mask.suit(1), mask.suit(2), mask.suit(3)];
+
+        if let Some(&flush) = suits.iter().find(|suit| suit.count_ones() >= 5) {
+            if let Some(high) = straight_high(flush) {
+                let category = if high == 12 { HandCategory::RoyalFlush } else { HandCategory::StraightFlush };
+                return HandStrength::new(category, &[high]);
+            }
+        }
+
+        // Bucket ranks by how many times they appear, highest rank first
+        let (mut quads, mut trips, mut pairs, mut singles) = ([0; 13], [0; 13], [0; 13], [0; 13]);
+        let (mut nquads, mut ntrips, mut npairs, mut nsingles) = (0, 0, 0, 0);
+        for rank in (0..13).rev() {
+            let count = suits.iter().filter(|suit| *suit & (1 << rank) != 0).count();
+            match count {
+                4 => { quads[nquads] = rank; nquads += 1; },
+                3 => { trips[ntrips] = rank; ntrips += 1; },
+                2 => { pairs[npairs] = rank; npairs += 1; },
+                1 => { singles[nsingles] = rank; nsingles += 1; },
+                _ => {},
+            }
+        }
+
+        // Highest rank that isn't part of the made hand
+        let kicker = |used: &[u32]| (0..13).rev().find(|rank| {
+            !used.contains(rank) && suits.iter().any(|suit| suit & (1 << rank) != 0)
+        });
+
+        if nquads > 0 {
+            return HandStrength::new(HandCategory::FourOfAKind, &[quads[0], kicker(&quads[..1]).unwrap_or(0)]);
+        }
+        // Two sets of trips make a full house too
+        if ntrips > 0 && (ntrips > 1 || npairs > 0) {
+            let pair = if ntrips > 1 && (npairs == 0 || trips[1] > pairs[0]) { trips[1] } else { pairs[0] };
+            return HandStrength::new(HandCategory::FullHouse, &[trips[0], pair]);
+        }
+        if let Some(&flush) = suits.iter().find(|suit| suit.count_ones() >= 5) {
+            let mut ranks = [0; 5];
+            let mut found = 0;
+            for rank in (0..13).rev() {
+                if found < 5 && flush & (1 << rank) != 0 {
+                    ranks[found] = rank;
+                    found += 1;
+                }
+            }
+            return HandStrength::new(HandCategory::Flush, &ranks);
+        }
+        if let Some(high) = straight_high(suits.iter().fold(0, |acc, suit| acc | suit)) {
+            return HandStrength::new(HandCategory::Straight, &[high]);
+        }
+        if ntrips > 0 {
+            return HandStrength::new(HandCategory::ThreeOfAKind, &[trips[0], singles[0], singles[1]][..1 + nsingles.min(2)]);
+        }
+        if npairs > 1 {
+            return HandStrength::new(HandCategory::TwoPair, &[pairs[0], pairs[1], kicker(&pairs[..2]).unwrap_or(0)]);
+        }
+        if npairs > 0 {
+            return HandStrength::new(HandCategory::Pair, &[pairs[0], singles[0], singles[1], singles[2]][..1 + nsingles.min(3)]);
+        }
+        HandStrength::new(HandCategory::HighCard, &singles[..nsingles.min(5)])
+    }
+}
+
+// Rank of the highest card of the best straight in a rank mask. The top of the ordering also plays
+// below the bottom, so a straight can wrap around like the wheel does in regular poker.
+fn straight_high(ranks: u16) -> Option<u32> {
+    let ranks = ((ranks as u32) << 1) | ((ranks as u32 >> 12) & 1);
+    let runs = ranks & (ranks >> 1) & (ranks >> 2) & (ranks >> 3) & (ranks >> 4);
+    if runs == 0 {
+        None
+    } else {
+        // Bit i of `runs` means the straight tops out at bit i + 4, which is rank i + 3
+        Some(31 - runs.leading_zeros() + 3)
+    }
+}
+
+#[test]
+fn evaluator_test() {
+    use crate::{into_cards, into_ordering};
+    let evaluator = Evaluator::new(into_ordering!("2,3,4,5,6,7,8,9,T,J,Q,K,A"));
+    let category = |cards: &str| evaluator.evaluate_cards(&into_cards!(cards)).category();
+    assert_eq!(category("Ah,Kh,Qh,Jh,Th,2c,3d"), HandCategory::RoyalFlush);
+    assert_eq!(category("Ah,2h,3h,4h,5h,Kc,Kd"), HandCategory::StraightFlush);
+    assert_eq!(category("7h,7d,7s,7c,2h,3d,4s"), HandCategory::FourOfAKind);
+    assert_eq!(category("7h,7d,7s,2c,2h,3d,3s"), HandCategory::FullHouse);
+    assert_eq!(category("7h,7d,7s,2c,2h,2d,3s"), HandCategory::FullHouse);
+    assert_eq!(category("Ah,9h,7h,4h,2h,2c,2d"), HandCategory::Flush);
+    assert_eq!(category("Ah,2c,3d,4s,5h,9c,Jd"), HandCategory::Straight);
+    assert_eq!(category("7h,7d,7s,2c,9h,3d,Js"), HandCategory::ThreeOfAKind);
+    assert_eq!(category("7h,7d,2s,2c,9h,3d,Js"), HandCategory::TwoPair);
+    assert_eq!(category("7h,7d,2s,4c,9h,3d,Js"), HandCategory::Pair);
+    assert_eq!(category("7h,8d,2s,4c,9h,3d,Js"), HandCategory::HighCard);
+    // Kickers break ties
+    assert_eq!(evaluator.compare(&into_cards!("Ah,Ad,Kc"), &into_cards!("As,Ac,Qc")), Ordering::Greater);
+    assert_eq!(evaluator.compare(&into_cards!("Ah,Ad,Kc,Ks,2c"), &into_cards!("As,Ac,Kd,Kh,2d")), Ordering::Equal);
+    // The wheel is the lowest straight
+    assert_eq!(evaluator.compare(&into_cards!("Ah,2c,3d,4s,5h"), &into_cards!("2h,3c,4d,5s,6h")), Ordering::Less);
+
+    // Under a different ordering, the top value completes the wheel instead
+    let evaluator = Evaluator::new(into_ordering!("A,K,Q,J,T,9,8,7,6,5,4,3,2"));
+    assert_eq!(evaluator.evaluate_cards(&into_cards!("2h,Ac,Kd,Qs,Jh")).category(), HandCategory::Straight);
+    assert_eq!(evaluator.compare(&into_cards!("6h,5c,4d,3s,2h"), &into_cards!("2h,Ac,Kd,Qs,Jh")), Ordering::Greater);
+    assert_eq!(evaluator.compare(&into_cards!("2h,2c"), &into_cards!("Ah,Ac")), Ordering::Greater);
+}
+
+#[test]
+fn kicker_test() {
+    use crate::{into_cards, into_ordering};
+    let evaluator = Evaluator::new(into_ordering!("2,3,4,5,6,7,8,9,T,J,Q,K,A"));
+    let strength = |cards: &str| evaluator.evaluate_cards(&into_cards!(cards));
+    assert_eq!(strength("Ah,2c,3d,4s,5h,9c,9d").category(), HandCategory::Straight);
+    // Kickers decide
+    assert!(strength("Ah,Ad,Kc,7s,5h,3c,2d") > strength("As,Ac,Qc,7d,5d,3s,2h"));
+    assert!(strength("Ah,Ad,Kc,8s,5h,3c,2d") > strength("As,Ac,Kd,7d,5d,3s,2h"));
+    assert!(strength("9h,9d,4s,4c,Ah,Kd,Ks") > strength("Kh,Kc,9s,9c,Qh,2d,3s"));
+    // but only the best five count, so this is a split pot
+    assert!(strength("Ah,Ad,Kc,Qs,Jh,3c,2d") == strength("As,Ac,Kd,Qd,Js,4s,3h"));
+    // The wheel loses to every other straight
+    assert!(strength("Ah,2c,3d,4s,5h") < strength("2h,3c,4d,5s,6h"));
+}
@@ -0,0 +1,270 @@
+//! Soft evidence about the ordering from how the opponent bets. An opponent that has worked out the ordering
+//! raises with high values more often than with low ones, and showdowns tell us which values it raised with.
+//! Whether those raises mean anything is calibrated against the showdowns too: if the opponent doesn't win more
+//! often after raising, or often gives up on hands it raised with, its raises tell us nothing.
+
+use crate::skeleton::{cards::{CardHand, CardValue}, states::{RoundState, TerminalState}};
+use crate::into_ordering;
+use std::cmp::Ordering;
+
+// Showdowns we want to have seen both after a raise and without one before we trust the calibration
+const MIN_SHOWDOWNS: u32 = 10;
+// Revealed hands a value has to have shown up in before we compare it with other values
+const MIN_HELD: u32 = 4;
+// Beliefs weaker than this aren't worth passing on
+const MIN_CERTAINTY: f64 = 0.05;
+// Betting is never proof, so no belief is stronger than this
+const MAX_CERTAINTY: f64 = 0.5;
+
+/// The streets we tell apart when looking at raises. Before the flop only the hole cards matter, afterwards the
+/// board does too.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BettingStreet {
+    Preflop,
+    Postflop,
+}
+
+/// How a player played a round, read off the round's history
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct BettingLine {
+    pub raised_preflop: bool,
+    pub raised_postflop: bool,
+    /// Whether the round ended with the player folding
+    pub folded: bool,
+}
+
+impl BettingLine {
+    /// Reads the line of player `player` out of a finished round
+    pub fn new(ts: &TerminalState, player: usize) -> BettingLine {
+        let mut line = BettingLine::default();
+        // The last state of a round that was folded is the one the folding player acted in.
+        // Showdowns reveal both hands, folds don't.
+        let last = &ts.previous;
+        line.folded = last.hands.iter().any(|hand| hand.is_none()) && last.button as usize % 2 == player;
+        let mut state: &RoundState = last;
+        while let Some(ref previous) = state.previous {
+            // `previous` is where somebody acted, and `state` is what their action led to
+            let actor = previous.button as usize % 2;
+            if actor == player && state.pips[actor] > previous.pips[1 - actor] {
+                if previous.street == 0 {
+                    line.raised_preflop = true;
+                } else {
+                    line.raised_postflop = true;
+                }
+            }
+            state = &**previous;
+        }
+        line
+    }
+
+    pub fn raised(&self, street: BettingStreet) -> bool {
+        match street {
+            BettingStreet::Preflop => self.raised_preflop,
+            BettingStreet::Postflop => self.raised_postflop,
+        }
+    }
+}
+
+/// What the opponent's betting tells us about the ordering
+#[derive(Debug, Clone, Default)]
+pub struct OpponentModel {
+    // For every value, how many revealed opponent hands held it, and how many of those the opponent raised with
+    // (preflop first, then postflop)
+    held: [[u32; 13]; 2],
+    raised_with: [[u32; 13]; 2],
+    // Showdowns after the opponent raised, and without a raise, with how many of each it won (splits count half)
+    aggressive_showdowns: u32,
+    aggressive_wins: f64,
+    passive_showdowns: u32,
+    passive_wins: f64,
+    // Rounds the opponent raised in, and how many of those it folded later
+    raised_rounds: u32,
+    raised_then_folded: u32,
+    // The certainty of (a, b) we passed on so far, indexed by the values' discriminants (preflop first, then postflop)
+    passed_on: [[[f64; 13]; 13]; 2],
+}
+
+impl OpponentModel {
+    pub fn new() -> OpponentModel {
+        OpponentModel::default()
+    }
+
+    /// Records a finished round. `hand` is the opponent's hand if it was revealed, and `result` is how the
+    /// opponent did.
+    pub fn observe(&mut self, line: BettingLine, hand: Option<CardHand>, result: Ordering) {
+        let raised = line.raised_preflop || line.raised_postflop;
+        if raised {
+            self.raised_rounds += 1;
+            if line.folded {
+                self.raised_then_folded += 1;
+            }
+        }
+        if let Some(hand) = hand {
+            let won = match result {
+                Ordering::Greater => 1.0,
+                Ordering::Equal => 0.5,
+                Ordering::Less => 0.0,
+            };
+            if raised {
+                self.aggressive_showdowns += 1;
+                self.aggressive_wins += won;
+            } else {
+                self.passive_showdowns += 1;
+                self.passive_wins += won;
+            }
+            let mut values = vec![hand.0[0].value(), hand.0[1].value()];
+            values.dedup();
+            for (index, street) in [BettingStreet::Preflop, BettingStreet::Postflop].iter().enumerate() {
+                for value in &values {
+                    self.held[index][*value as usize] += 1;
+                    if line.raised(*street) {
+                        self.raised_with[index][*value as usize] += 1;
+                    }
+                }
+            }
+        }
+    }
+
+    pub fn showdowns(&self) -> u32 {
+        self.aggressive_showdowns + self.passive_showdowns
+    }
+
+    /// How much the opponent's raises say about its cards, between 0 and 1: how much more often it wins showdowns
+    /// after raising, less the share of raises it gave up on
+    pub fn reliability(&self) -> f64 {
+        if self.aggressive_showdowns < MIN_SHOWDOWNS || self.passive_showdowns < MIN_SHOWDOWNS {
+            return 0.0;
+        }
+        let edge = self.aggressive_wins / self.aggressive_showdowns as f64 - self.passive_wins / self.passive_showdowns as f64;
+        let bluffs = self.raised_then_folded as f64 / self.raised_rounds as f64;
+        (edge * (1.0 - bluffs)).clamp(0.0, 1.0)
+    }
+
+    /// Share of the revealed hands holding `value` that the opponent raised with on `street`, if we have seen enough
+    pub fn raise_rate(&self, street: BettingStreet, value: &CardValue) -> Option<f64> {
+        let index = street as usize;
+        let held = self.held[index][*value as usize];
+        if held < MIN_HELD {
+            None
+        } else {
+            Some(self.raised_with[index][*value as usize] as f64 / held as f64)
+        }
+    }
+
+    /// (lower, higher) relations the opponent's raises on `street` point to, each with how sure we are of it.
+    /// The certainties fit `ProbabilityEngine::update`.
+    pub fn beliefs(&self, street: BettingStreet) -> Vec<((CardValue, CardValue), f64)> {
+        let reliability = self.reliability();
+        if reliability == 0.0 {
+            return vec![];
+        }
+        let values = into_ordering!("2,3,4,5,6,7,8,9,T,J,Q,K,A");
+        let mut beliefs = vec![];
+        for (i, a) in values.iter().enumerate() {
+            for b in &values[i + 1..] {
+                if let (Some(rate_a), Some(rate_b)) = (self.raise_rate(street, a), self.raise_rate(street, b)) {
+                    let certainty = (reliability * (rate_b - rate_a)).clamp(-MAX_CERTAINTY, MAX_CERTAINTY);
+                    if certainty >= MIN_CERTAINTY {
+                        beliefs.push(((*a, *b), certainty));
+                    } else if certainty <= -MIN_CERTAINTY {
+                        beliefs.push(((*b, *a), -certainty));
+                    }
+                }
+            }
+        }
+        beliefs
+    }
+
+    /// How far `beliefs(street)` moved since the last call, as (lower, higher) relations with the certainty it
+    /// takes to get there. Passing these on to `ProbabilityEngine::update` counts every bit of evidence once, however
+    /// often we ask. Changes too small to be worth passing on are kept for later.
+    pub fn belief_changes(&mut self, street: BettingStreet) -> Vec<((CardValue, CardValue), f64)> {
+        let mut current = [[0.0; 13]; 13];
+        for ((lower, higher), certainty) in self.beliefs(street) {
+            current[lower as usize][higher as usize] = certainty;
+            current[higher as usize][lower as usize] = -certainty;
+        }
+        let passed_on = &mut self.passed_on[street as usize];
+        let values = into_ordering!("2,3,4,5,6,7,8,9,T,J,Q,K,A");
+        let mut changes = vec![];
+        for (i, a) in values.iter().enumerate() {
+            for b in &values[i + 1..] {
+                let (a_index, b_index) = (*a as usize, *b as usize);
+                // A belief that flipped can take more than one call to pass on
+                let change = (current[a_index][b_index] - passed_on[a_index][b_index]).clamp(-MAX_CERTAINTY, MAX_CERTAINTY);
+                if change.abs() < MIN_CERTAINTY {
+                    continue;
+                }
+                passed_on[a_index][b_index] += change;
+                passed_on[b_index][a_index] -= change;
+                if change > 0.0 {
+                    changes.push(((*a, *b), change));
+                } else {
+                    changes.push(((*b, *a), -change));
+                }
+            }
+        }
+        changes
+    }
+}
+
+#[test]
+fn opponent_test() {
+    use crate::into_cards;
+    use crate::skeleton::{actions::Action, cards::{Card, CardDeck}, states::{StateResult, STARTING_STACK, SMALL_BLIND, BIG_BLIND}};
+    let hand = |cards: &str| { let cards = into_cards!(cards); CardHand([cards[0], cards[1]]) };
+
+    // Player 1 raises preflop, then folds to a raise on the flop
+    let start = RoundState {
+        button: 0,
+        street: 0,
+        pips: [SMALL_BLIND, BIG_BLIND],
+        stacks: [STARTING_STACK - SMALL_BLIND, STARTING_STACK - BIG_BLIND],
+        hands: [Some(hand("Ah,Ad")), None],
+        deck: CardDeck(into_cards!("2c,5d,9h")),
+        previous: None,
+    };
+    let proceed = |state: RoundState, action| match state.proceed(action) {
+        StateResult::Round(state) => state,
+        StateResult::Terminal(_) => panic!("round ended early"),
+    };
+    let state = proceed(start, Action::Call);
+    let state = proceed(state, Action::Raise(6));
+    let state = proceed(state, Action::Call);
+    let state = proceed(state, Action::Check);
+    let state = proceed(state, Action::Raise(10));
+    let ts = match state.proceed(Action::Fold) {
+        StateResult::Terminal(ts) => ts,
+        StateResult::Round(_) => panic!("fold didn't end the round"),
+    };
+    assert_eq!(BettingLine::new(&ts, 1), BettingLine { raised_preflop: true, raised_postflop: false, folded: true });
+    assert_eq!(BettingLine::new(&ts, 0), BettingLine { raised_preflop: false, raised_postflop: true, folded: false });
+
+    // An opponent that raises with aces and wins, and checks down its twos and loses
+    let mut model = OpponentModel::new();
+    let aggressive = BettingLine { raised_preflop: true, ..BettingLine::default() };
+    for _ in 0..MIN_SHOWDOWNS {
+        assert!(model.beliefs(BettingStreet::Preflop).is_empty());
+        model.observe(aggressive, Some(hand("Ah,7d")), Ordering::Greater);
+        model.observe(BettingLine::default(), Some(hand("2h,7c")), Ordering::Less);
+    }
+    assert_eq!(model.showdowns(), 2 * MIN_SHOWDOWNS);
+    assert_eq!(model.reliability(), 1.0);
+    assert_eq!(model.raise_rate(BettingStreet::Preflop, &CardValue::Seven), Some(0.5));
+    let beliefs = model.beliefs(BettingStreet::Preflop);
+    assert!(beliefs.contains(&((CardValue::Two, CardValue::Ace), MAX_CERTAINTY)));
+    assert!(beliefs.contains(&((CardValue::Seven, CardValue::Ace), MAX_CERTAINTY)));
+    assert!(model.beliefs(BettingStreet::Postflop).is_empty());
+    // Beliefs are only passed on once
+    assert_eq!(model.belief_changes(BettingStreet::Preflop), beliefs);
+    assert!(model.belief_changes(BettingStreet::Preflop).is_empty());
+
+    // Giving up on half of its raises makes them worth half as much
+    for _ in 0..MIN_SHOWDOWNS {
+        model.observe(BettingLine { folded: true, ..aggressive }, None, Ordering::Less);
+    }
+    assert_eq!(model.reliability(), 0.5);
+    // so half of what we passed on about sevens gets taken back, while twos against aces are still as sure as can be
+    let changes = model.belief_changes(BettingStreet::Preflop);
+    assert_eq!(changes, vec![((CardValue::Seven, CardValue::Two), 0.25), ((CardValue::Ace, CardValue::Seven), 0.25)]);
+}
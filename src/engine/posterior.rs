@@ -0,0 +1,285 @@
+//! Bayesian inference over the hidden value ordering. The prior is the engine's own shuffle, every showdown
+//! we see is a likelihood over the 13! orderings (did this ordering give the result the server announced?),
+//! and we walk the posterior with Metropolis-Hastings, proposing swaps of two values.
+
+use crate::skeleton::{cards::{Card, CardHand, CardValue}, clock::Deadline, dealer::{permute_values, PERMUTATION_PROBABILITY}};
+use crate::into_ordering;
+use super::evaluator::Evaluator;
+use super::equity::OrderingDistribution;
+use rand::{prelude::*, rngs::StdRng};
+use std::cmp::Ordering;
+
+// How likely we think it is that an ordering disagrees with a showdown anyway.
+// Showdowns are never wrong, but a little slack lets the chain walk out of orderings that disagree with
+// several of them instead of getting stuck.
+const MISMATCH_LIKELIHOOD: f64 = 0.01;
+// Steps taken after the first showdown before we start keeping statistics
+const BURN_IN_STEPS: u32 = 500;
+// Every showdown after that only moves the posterior a little, and the chain is already close to it
+const INCREMENTAL_BURN_IN_STEPS: u32 = 50;
+// Only every this many steps of the chain is kept as a sample to draw orderings from
+const SAMPLE_INTERVAL: u32 = 10;
+// How many of those samples we keep around (the oldest are overwritten)
+const MAX_SAMPLES: usize = 256;
+
+/// A showdown we saw, with the result from the first hand's point of view
+#[derive(Debug, Clone)]
+struct Showdown {
+    first: Vec<Card>,
+    second: Vec<Card>,
+    result: Ordering,
+}
+
+/// Posterior over the value ordering (lowest to highest) given the showdowns we have seen.
+/// Statistics are collected from the steps taken since the last showdown was added (after a burn-in), since the
+/// ones before it were drawn from a different posterior.
+#[derive(Debug, Clone)]
+pub struct OrderingPosterior {
+    showdowns: Vec<Showdown>,
+    // Where the chain currently is, and its log posterior (up to a constant)
+    state: [CardValue; 13],
+    log_posterior: f64,
+    // The most probable ordering visited so far
+    best: ([CardValue; 13], f64),
+    burn_in: u32,
+    steps: u32,
+    // before[a][b] is how many steps had `a` below `b`
+    before: [[u32; 13]; 13],
+    // positions[v][i] is how many steps had `v` in position `i`
+    positions: [[u32; 13]; 13],
+    samples: Vec<[CardValue; 13]>,
+    next_sample: u32,
+    rng: StdRng,
+}
+
+impl OrderingPosterior {
+    pub fn new(seed: u64) -> OrderingPosterior {
+        let mut rng = StdRng::seed_from_u64(seed);
+        let state = permute_values(&mut rng);
+        let log_posterior = log_prior(&state);
+        OrderingPosterior {
+            showdowns: vec![],
+            state,
+            log_posterior,
+            best: (state, log_posterior),
+            burn_in: 0,
+            steps: 0,
+            before: [[0; 13]; 13],
+            positions: [[0; 13]; 13],
+            samples: vec![],
+            next_sample: SAMPLE_INTERVAL,
+            rng,
+        }
+    }
+
+    /// Adds a showdown between two hands (hole cards plus the board), where `result` is how the first hand did
+    /// against the second
+    pub fn observe_showdown(&mut self, board: &[Card], first: &CardHand, second: &CardHand, result: Ordering) {
+        self.showdowns.push(Showdown {
+            first: first.0.iter().chain(board).copied().collect(),
+            second: second.0.iter().chain(board).copied().collect(),
+            result,
+        });
+        self.log_posterior = self.log_posterior(&self.state);
+        self.best = (self.state, self.log_posterior);
+        self.burn_in = if self.showdowns.len() == 1 { BURN_IN_STEPS } else { INCREMENTAL_BURN_IN_STEPS };
+        self.steps = 0;
+        self.before = [[0; 13]; 13];
+        self.positions = [[0; 13]; 13];
+        self.samples.clear();
+        self.next_sample = SAMPLE_INTERVAL;
+    }
+
+    pub fn showdowns(&self) -> usize {
+        self.showdowns.len()
+    }
+
+    /// Steps left to take before statistics are kept again
+    pub fn burn_in(&self) -> u32 {
+        self.burn_in
+    }
+
+    /// Takes `steps` steps of the chain. Each one swaps two values (half the time neighbouring ones, which
+    /// rarely changes the prior much), and is kept with the ratio of the new ordering's posterior to the old one
+    /// (always, if the new one is more probable).
+    pub fn run(&mut self, steps: u32) {
+        for _ in 0..steps {
+            self.step();
+        }
+    }
+
+    /// Like `run`, but stops early once `deadline` has passed. Every step looks at every showdown so far, so
+    /// they get slower as the match goes on.
+    pub fn run_within(&mut self, steps: u32, deadline: &Deadline) {
+        for _ in 0..steps {
+            if deadline.expired() {
+                break;
+            }
+            self.step();
+        }
+    }
+
+    fn step(&mut self) {
+        let i = self.rng.gen_range(0, 12);
+        let j = if self.rng.gen() { i + 1 } else { self.rng.gen_range(0, 13) };
+        self.state.swap(i, j);
+        let log_posterior = self.log_posterior(&self.state);
+        if log_posterior >= self.log_posterior || self.rng.gen_bool((log_posterior - self.log_posterior).exp()) {
+            self.log_posterior = log_posterior;
+            if log_posterior > self.best.1 {
+                self.best = (self.state, log_posterior);
+            }
+        } else {
+            self.state.swap(i, j);
+        }
+        if self.burn_in > 0 {
+            self.burn_in -= 1;
+        } else {
+            self.record();
+        }
+    }
+
+    fn record(&mut self) {
+        self.steps += 1;
+        for (i, lower) in self.state.iter().enumerate() {
+            self.positions[*lower as usize][i] += 1;
+            for higher in &self.state[i + 1..] {
+                self.before[*lower as usize][*higher as usize] += 1;
+            }
+        }
+        if self.steps >= self.next_sample {
+            self.next_sample += SAMPLE_INTERVAL;
+            if self.samples.len() < MAX_SAMPLES {
+                self.samples.push(self.state);
+            } else {
+                let index = (self.steps / SAMPLE_INTERVAL) as usize;
+                self.samples[index % MAX_SAMPLES] = self.state;
+            }
+        }
+    }
+
+    fn log_posterior(&self, ordering: &[CardValue; 13]) -> f64 {
+        log_prior(ordering) + self.count_mismatches(ordering) as f64 * MISMATCH_LIKELIHOOD.ln()
+    }
+
+    /// How many of the observed showdowns `ordering` doesn't explain
+    pub fn count_mismatches(&self, ordering: &[CardValue; 13]) -> usize {
+        let evaluator = Evaluator::new(*ordering);
+        self.showdowns.iter()
+            .filter(|showdown| evaluator.compare(&showdown.first, &showdown.second) != showdown.result)
+            .count()
+    }
+
+    /// Posterior probability that `a` is lower than `b`. Without any steps taken, we don't know either way.
+    pub fn probability(&self, a: &CardValue, b: &CardValue) -> f64 {
+        if a == b || self.steps == 0 {
+            0.5
+        } else {
+            self.before[*a as usize][*b as usize] as f64 / self.steps as f64
+        }
+    }
+
+    /// The more likely ordering of two values as (lower, higher), if one is more likely than the other
+    pub fn likely_ordering(&self, a: &CardValue, b: &CardValue) -> Option<(CardValue, CardValue)> {
+        let probability = self.probability(a, b);
+        if probability > 0.5 {
+            Some((*a, *b))
+        } else if probability < 0.5 {
+            Some((*b, *a))
+        } else {
+            None
+        }
+    }
+
+    /// Every (lower, higher) pair we are at least `confidence` sure of
+    pub fn relations(&self, confidence: f64) -> Vec<(CardValue, CardValue)> {
+        let values = into_ordering!("2,3,4,5,6,7,8,9,T,J,Q,K,A");
+        values.iter()
+            .flat_map(|a| values.iter().map(move |b| (*a, *b)))
+            .filter(|(a, b)| a != b && self.probability(a, b) >= confidence)
+            .collect()
+    }
+
+    /// The most probable ordering the chain has come across
+    pub fn map_ordering(&self) -> [CardValue; 13] {
+        self.best.0
+    }
+
+    /// Probability of `value` being in each position of the ordering, lowest first
+    pub fn position_distribution(&self, value: &CardValue) -> [f64; 13] {
+        let mut distribution = [1.0 / 13.0; 13];
+        if self.steps > 0 {
+            for (probability, count) in distribution.iter_mut().zip(self.positions[*value as usize].iter()) {
+                *probability = *count as f64 / self.steps as f64;
+            }
+        }
+        distribution
+    }
+}
+
+// Log probability of the engine shuffling the values into `ordering`. Each slot takes the value at a geometrically
+// distributed index (wrapped around) out of the values left, so picking index k out of n happens with probability
+// p (1 - p)^k / (1 - (1 - p)^n).
+fn log_prior(ordering: &[CardValue; 13]) -> f64 {
+    let mut remaining = into_ordering!("2,3,4,5,6,7,8,9,T,J,Q,K,A").to_vec();
+    let miss = 1.0 - PERMUTATION_PROBABILITY;
+    ordering.iter().map(|value| {
+        let n = remaining.len();
+        let k = remaining.iter().position(|v| v == value).unwrap();
+        remaining.remove(k);
+        (PERMUTATION_PROBABILITY / (1.0 - miss.powi(n as i32))).ln() + k as f64 * miss.ln()
+    }).sum()
+}
+
+/// Draws one of the orderings the chain visited recently (or where it is now, if it hasn't run yet)
+impl OrderingDistribution for OrderingPosterior {
+    fn sample_ordering(&self, rng: &mut StdRng) -> [CardValue; 13] {
+        self.samples.choose(rng).copied().unwrap_or(self.state)
+    }
+}
+
+#[test]
+fn posterior_test() {
+    use crate::{into_cards, into_ordering, skeleton::clock::RESERVE};
+    let ordering = into_ordering!("2,3,4,5,6,7,8,9,T,J,Q,K,A");
+    let hand = |cards: &str| { let cards = into_cards!(cards); CardHand([cards[0], cards[1]]) };
+
+    // Knowing nothing, everything is a coin toss
+    let mut posterior = OrderingPosterior::new(176);
+    assert_eq!(posterior.probability(&CardValue::Two, &CardValue::Ace), 0.5);
+    assert_eq!(posterior.position_distribution(&CardValue::Ace), [1.0 / 13.0; 13]);
+
+    // Quad twos on the board, so the best kicker wins: aces beat kings, and kings beat queens
+    let board = into_cards!("2c,2d,2h,2s,9c");
+    posterior.observe_showdown(&board, &hand("Ah,Ad"), &hand("Kh,Kd"), Ordering::Greater);
+    assert_eq!(posterior.burn_in(), BURN_IN_STEPS);
+    posterior.observe_showdown(&board, &hand("Qh,Qd"), &hand("Kc,Ks"), Ordering::Less);
+    assert_eq!(posterior.burn_in(), INCREMENTAL_BURN_IN_STEPS);
+    // Without any time left, the chain stays where it is
+    posterior.run_within(5000, &Deadline::new(RESERVE, 10));
+    assert_eq!(posterior.burn_in(), INCREMENTAL_BURN_IN_STEPS);
+    posterior.run(5000);
+    assert_eq!(posterior.showdowns(), 2);
+    assert!(posterior.probability(&CardValue::King, &CardValue::Ace) > 0.95);
+    assert!(posterior.probability(&CardValue::Queen, &CardValue::King) > 0.95);
+    assert!(posterior.probability(&CardValue::Queen, &CardValue::Ace) > 0.95);
+    assert_eq!(posterior.likely_ordering(&CardValue::Ace, &CardValue::King), Some((CardValue::King, CardValue::Ace)));
+    assert!(posterior.relations(0.95).contains(&(CardValue::Queen, CardValue::King)));
+    // The engine's shuffle mostly keeps values close to where they started, so the prior leans that way too
+    let mut reversed = ordering;
+    reversed.reverse();
+    assert!(log_prior(&ordering) > log_prior(&reversed));
+    // Queens are hardly ever on top, and the MAP ordering agrees with both showdowns
+    assert!(posterior.position_distribution(&CardValue::Queen)[12] < 0.05);
+    assert!((posterior.position_distribution(&CardValue::Queen).iter().sum::<f64>() - 1.0).abs() < 1e-9);
+    let map = Evaluator::new(posterior.map_ordering());
+    assert_eq!(map.compare(&into_cards!("Ah,Ad,2c,2d,2h,2s,9c"), &into_cards!("Kh,Kd,2c,2d,2h,2s,9c")), Ordering::Greater);
+
+    // A split pot pins nothing down between the hands, but the true ordering should still fit every showdown
+    posterior.observe_showdown(&into_cards!("Ah,Kh,Qh,Jh,Th"), &hand("2c,3d"), &hand("4c,5d"), Ordering::Equal);
+    posterior.run(1000);
+    assert_eq!(posterior.count_mismatches(&ordering), 0);
+    let mut rng = StdRng::seed_from_u64(176);
+    let sampled = posterior.sample_ordering(&mut rng);
+    assert!(posterior.samples.contains(&sampled));
+}
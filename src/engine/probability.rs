@@ -1,7 +1,8 @@
 //! Works more with detecting which order is more probable based on how many times we have seen a fact
 use crate::{
     skeleton::cards::CardValue,
-    engine::relations::{relationships, detect_cycles},
+    engine::relations::{relationships, detect_cycles, check_cycles, resolve_cycles, OrderingGenerator},
+    engine::ranking::{best_orderings, RankedOrdering},
 };
 use std::collections::HashMap;
 use itertools::Itertools;
@@ -11,10 +12,42 @@ use std::convert::AsRef;
 use std::cmp::Ordering;
 use crate::debug_println;
 use std::sync::{Arc, RwLock};
+use std::fmt;
 
 const CONFIRMATION_THRESHOLD: f64 = 0.5;
+// How many orderings to draw from each set of relations when sampling the posterior
+const ORDERINGS_PER_RELATIONS: usize = 8;
 const EPSILON: f64 = std::f64::EPSILON;
 
+/// One relation along a cycle, with the rules that told us about it
+#[derive(Debug, Clone, PartialEq)]
+pub struct CycleEdge {
+    pub lower: CardValue,
+    pub higher: CardValue,
+    pub probability: f64,
+    pub rules: Vec<(String, f64)>,
+}
+
+/// Every cycle in what we believe, and the least confident relations that have to go to break them
+#[derive(Debug, Clone, PartialEq)]
+pub struct CycleReport {
+    pub cycles: Vec<Vec<CycleEdge>>,
+    pub dropped: Vec<((CardValue, CardValue), f64)>,
+}
+
+impl fmt::Display for CycleReport {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        for cycle in &self.cycles {
+            writeln!(fmt, "Cycle {}", cycle.iter().map(|edge| edge.lower).chain(cycle.first().map(|edge| edge.lower)).format(" -> "))?;
+            for edge in cycle {
+                let rules = edge.rules.iter().map(|(name, certainty)| format!("{} ({:.2})", name, certainty)).format(", ");
+                writeln!(fmt, "    {} -> {} P({:.4}) from [{}]", edge.lower, edge.higher, edge.probability, rules)?;
+            }
+        }
+        write!(fmt, "Dropping {}", self.dropped.iter().map(|((a, b), p)| format!("{} -> {} P({:.4})", a, b, p)).format(", "))
+    }
+}
+
 #[derive(Debug, Clone)]
 pub struct ProbabilityEngine {
     seen: Arc<RwLock<HashMap<(CardValue, CardValue), (f64, usize, Vec<(String, f64)>)>>>,
@@ -39,11 +72,8 @@ impl ProbabilityEngine {
 
     // Returns whether or not we actually added the rule
     fn _update<B: AsRef<str>>(&self, name: B, a: &CardValue, b: &CardValue, certainty: f64) -> bool {
-        use rand::Rng;
         assert!(certainty > -1.0 && certainty < 1.0);
 
-        let mut rng = rand::thread_rng();
-
         if let Ok(ir) = self.inconsistent_rules.read() {
             if a != b && certainty != 0.0 && !ir.contains(name.as_ref()) {
                 let (certainty, a, b) = if a < b { (certainty, a, b) } else { (-certainty, b, a) };
@@ -51,7 +81,9 @@ impl ProbabilityEngine {
                 if let Ok(mut seen) = self.seen.write() {
                     let (probability, reps, names) = seen.entry((*a, *b)).or_insert((0.0, 0, vec![]));
                     *reps += 1;
-                    *probability += certainty.abs() * (if *reps > 1 {rng.gen_range(1, *reps)} else {1} as f64  / *reps as f64) * (certainty.signum() - *probability);
+                    // Like a running average, every piece of evidence moves us 1 / reps of the way it would on its own,
+                    // so the same evidence always leaves us with the same belief
+                    *probability += certainty.abs() * (certainty.signum() - *probability) / *reps as f64;
                     let pair = (name.as_ref().to_string(), certainty);
                     if !names.contains(&pair) {
                         names.push(pair);
@@ -70,20 +102,15 @@ impl ProbabilityEngine {
         }
     }
 
+    /// The more likely ordering of two values as (lower, higher), if we lean either way
     pub fn likely_ordering(&self, a: &CardValue, b: &CardValue) -> Option<(CardValue, CardValue)> {
-        let (scale, a, b) = if a < b { (1.0, a, b) } else { (-1.0, b, a) };
-
-        if let Ok(mut seen) = self.seen.write() {
-            let probability = seen.entry((*a, *b)).or_insert((0.0, 0, vec![])).0 * scale;
-            if probability > 0.0 {
-                Some((*a, *b))
-            } else if probability < 0.0 {
-                Some((*b, *a))
-            } else {
-                None
-            }
+        let probability = self.probability(a, b);
+        if probability > 0.0 {
+            Some((*a, *b))
+        } else if probability < 0.0 {
+            Some((*b, *a))
         } else {
-            panic!("seen is not writeable")
+            None
         }
     }
 
@@ -105,6 +132,74 @@ impl ProbabilityEngine {
         }
     }
 
+    /// How well an ordering (lowest to highest) agrees with what we have seen: the product over every pair
+    /// we have a belief about of (1 + p) / 2 when the ordering agrees with it, and (1 - p) / 2 when it doesn't
+    pub fn ordering_weight(&self, ordering: &[CardValue; 13]) -> f64 {
+        let mut position = [0; 13];
+        for (i, value) in ordering.iter().enumerate() {
+            position[*value as usize] = i;
+        }
+        self.probabilities().into_iter()
+            .map(|((a, b), p)| if position[a as usize] < position[b as usize] { (1.0 + p) / 2.0 } else { (1.0 - p) / 2.0 })
+            .product()
+    }
+
+    /// Draws `count` orderings consistent with the relations we currently believe in, each paired with its
+    /// normalised `ordering_weight`. The relations are redrawn every few orderings, so pairs we aren't sure of
+    /// show up both ways.
+    pub fn sample_orderings<R: rand::Rng + ?Sized>(&self, count: usize, rng: &mut R) -> Vec<([CardValue; 13], f64)> {
+        let mut orderings = Vec::with_capacity(count);
+        while orderings.len() < count {
+            let generator = OrderingGenerator::new(&self.relations());
+            for _ in 0..ORDERINGS_PER_RELATIONS.min(count - orderings.len()) {
+                let ordering = generator.generate(rng);
+                orderings.push((ordering, self.ordering_weight(&ordering)));
+            }
+        }
+        let total: f64 = orderings.iter().map(|(_, weight)| weight).sum();
+        for (_, weight) in orderings.iter_mut() {
+            // Every ordering disagrees with something we were certain of, so fall back to treating them equally
+            *weight = if total > 0.0 { *weight / total } else { 1.0 / count as f64 };
+        }
+        orderings
+    }
+
+    /// The `k` orderings that agree best with everything we have seen, best first. Unlike `relations`, the same
+    /// evidence always gives the same orderings.
+    pub fn best_orderings(&self, k: usize) -> Vec<RankedOrdering> {
+        // Sum the beliefs in a fixed order, so rounding can't break ties differently between calls
+        let mut probabilities = self.probabilities();
+        probabilities.sort_by_key(|(pair, _)| *pair);
+        best_orderings(&probabilities, k)
+    }
+
+    /// Every pair we have an opinion on, as (lower, higher) with how sure we are of it
+    pub fn beliefs(&self) -> Vec<((CardValue, CardValue), f64)> {
+        self.probabilities().into_iter()
+            .filter(|(_, p)| *p != 0.0)
+            .map(|((a, b), p)| if p < 0.0 { ((b, a), -p) } else { ((a, b), p) })
+            .collect()
+    }
+
+    /// Names the rules behind every cycle in what we believe, and which relations would be dropped to break them.
+    /// Nothing to report if our beliefs agree with some ordering.
+    pub fn cycle_report(&self) -> Option<CycleReport> {
+        let mut beliefs = self.beliefs();
+        beliefs.sort_by_key(|(pair, _)| *pair);
+        let relations = beliefs.iter().map(|(relation, _)| *relation).collect::<Vec<_>>();
+        let error = check_cycles(&relations).err()?;
+        let edge = |lower: CardValue, higher: CardValue| CycleEdge {
+            lower,
+            higher,
+            probability: beliefs.iter().find(|(relation, _)| *relation == (lower, higher)).map(|(_, p)| *p).unwrap_or(0.0),
+            rules: self.get_rules(&lower, &higher),
+        };
+        let cycles = error.cycles.iter()
+            .map(|cycle| cycle.windows(2).map(|pair| edge(pair[0], pair[1])).collect())
+            .collect();
+        Some(CycleReport { cycles, dropped: resolve_cycles(&beliefs).dropped })
+    }
+
     pub fn get_rules(&self, a: &CardValue, b: &CardValue) -> Vec<(String, f64)> {
         let (a, b) = if a < b { (a, b) } else { (b, a) };
         if let Ok(seen) = self.seen.read() {
@@ -355,3 +450,39 @@ impl ProbabilityEngine {
         final_.into_iter().map(|v| v.0).collect()
     }
 }
+
+#[test]
+fn update_test() {
+    // The same evidence gives the same beliefs, whichever way round it is told
+    let evidence = [(CardValue::Two, CardValue::Three, 0.9), (CardValue::Three, CardValue::Two, 0.3), (CardValue::Two, CardValue::Three, 0.6)];
+    let (mut first, mut second) = (ProbabilityEngine::new(), ProbabilityEngine::new());
+    for (a, b, certainty) in evidence.iter() {
+        first.update("first", a, b, *certainty);
+        second.update("second", b, a, -*certainty);
+    }
+    assert_eq!(first.probabilities(), second.probabilities());
+    // 0.9 up, then 0.3 of half the way down, then 0.6 of a third of the way back up
+    let expected = 0.9 - 0.3 * (1.0 + 0.9) / 2.0;
+    let expected = expected + 0.6 * (1.0 - expected) / 3.0;
+    assert!((first.probability(&CardValue::Two, &CardValue::Three) - expected).abs() < 1e-12);
+    assert_eq!(first.likely_ordering(&CardValue::Three, &CardValue::Two), Some((CardValue::Two, CardValue::Three)));
+    assert_eq!(first.likely_ordering(&CardValue::Two, &CardValue::Four), None);
+    assert_eq!(first.probabilities().len(), 1);
+}
+
+#[test]
+fn cycle_report_test() {
+    let mut engine = ProbabilityEngine::new();
+    assert!(engine.cycle_report().is_none());
+    engine.update("strong", &CardValue::Two, &CardValue::Three, 0.9);
+    engine.update("strong", &CardValue::Three, &CardValue::Four, 0.8);
+    engine.update("weak", &CardValue::Four, &CardValue::Two, 0.3);
+    let report = engine.cycle_report().unwrap();
+    assert_eq!(report.cycles.len(), 1);
+    let weak = report.cycles[0].iter().find(|edge| edge.lower == CardValue::Four).unwrap();
+    assert_eq!(weak.higher, CardValue::Two);
+    assert_eq!(weak.rules, vec![("weak".to_string(), -0.3)]);
+    assert_eq!(report.dropped.len(), 1);
+    assert_eq!((report.dropped[0].0).0, CardValue::Four);
+    assert!(report.to_string().contains("weak"));
+}
\ No newline at end of file
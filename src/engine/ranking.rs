@@ -0,0 +1,108 @@
+//! Deterministic maximum-likelihood orderings for a set of pairwise beliefs (a weighted Kemeny ranking).
+//! Every belief P(a < b) = (1 + p) / 2 contributes the log of how much the ordering agrees with it, and the best
+//! orderings are found exactly with a DP over the 2^13 sets of values that can make up the bottom of an ordering.
+
+use crate::skeleton::cards::CardValue;
+use crate::into_ordering;
+
+// Beliefs we were completely sure of would make disagreeing orderings impossible, which leaves nothing to
+// compare when the beliefs contradict each other. No pair is ever agreed with less than this.
+const MIN_AGREEMENT: f64 = 1e-6;
+
+/// An ordering (lowest to highest) with the log likelihood of the beliefs it was ranked against
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct RankedOrdering {
+    pub ordering: [CardValue; 13],
+    pub score: f64,
+}
+
+// One of the best ways to order a set of values: its score, the value on top, and which of the best orderings of
+// the rest of the set it sits on
+#[derive(Debug, Clone, Copy)]
+struct Entry {
+    score: f64,
+    top: usize,
+    below: usize,
+}
+
+/// The `k` orderings that agree best with the beliefs, best first. Beliefs are ((a, b), p) with p between -1 and 1,
+/// where positive p means a is lower than b, like `ProbabilityEngine::probabilities` gives them.
+/// Equally good orderings always come out in the same order.
+pub fn best_orderings(beliefs: &[((CardValue, CardValue), f64)], k: usize) -> Vec<RankedOrdering> {
+    // below[u][v] is the log likelihood gained by putting u below v
+    let mut below = [[0.0; 13]; 13];
+    for ((a, b), p) in beliefs {
+        let p = p.clamp(-1.0, 1.0);
+        below[*a as usize][*b as usize] += ((1.0 + p) / 2.0).max(MIN_AGREEMENT).ln();
+        below[*b as usize][*a as usize] += ((1.0 - p) / 2.0).max(MIN_AGREEMENT).ln();
+    }
+
+    let full = (1 << 13) - 1;
+    let mut best: Vec<Vec<Entry>> = vec![vec![]; 1 << 13];
+    best[0].push(Entry { score: 0.0, top: 13, below: 0 });
+    for set in 1..=full {
+        let mut candidates = vec![];
+        for top in (0..13).filter(|v| set & (1 << v) != 0) {
+            let rest = set & !(1 << top);
+            let gain: f64 = (0..13).filter(|u| rest & (1 << u) != 0).map(|u| below[u][top]).sum();
+            candidates.extend(best[rest].iter().enumerate().map(|(i, entry)| Entry { score: entry.score + gain, top, below: i }));
+        }
+        // A stable sort keeps ties in the order they were generated
+        candidates.sort_by(|a, b| b.score.partial_cmp(&a.score).unwrap());
+        candidates.truncate(k);
+        best[set] = candidates;
+    }
+
+    let values = into_ordering!("2,3,4,5,6,7,8,9,T,J,Q,K,A");
+    best[full].iter().map(|entry| {
+        let mut ordering = [CardValue::Two; 13];
+        let (mut set, mut current) = (full, *entry);
+        for slot in ordering.iter_mut().rev() {
+            *slot = values[current.top];
+            set &= !(1 << current.top);
+            current = best[set][current.below];
+        }
+        RankedOrdering { ordering, score: entry.score }
+    }).collect()
+}
+
+#[test]
+fn ranking_test() {
+    use super::relations::generate_ordering_with;
+    use rand::{SeedableRng, rngs::StdRng};
+    let values = into_ordering!("2,3,4,5,6,7,8,9,T,J,Q,K,A");
+    let position = |ordering: &[CardValue; 13], value: CardValue| ordering.iter().position(|v| *v == value).unwrap();
+
+    // A strong belief beats the two weaker ones it contradicts
+    let beliefs = vec![
+        ((CardValue::Two, CardValue::Three), 0.9),
+        ((CardValue::Three, CardValue::Four), 0.2),
+        ((CardValue::Four, CardValue::Two), 0.2),
+        ((CardValue::Ace, CardValue::King), -0.5),
+    ];
+    let ranked = best_orderings(&beliefs, 5);
+    assert_eq!(ranked.len(), 5);
+    let best = ranked[0].ordering;
+    assert!(position(&best, CardValue::Two) < position(&best, CardValue::Three));
+    assert!(position(&best, CardValue::King) < position(&best, CardValue::Ace));
+    assert!(ranked.windows(2).all(|pair| pair[0].score >= pair[1].score));
+    assert!(ranked.iter().enumerate().all(|(i, a)| ranked[i + 1..].iter().all(|b| a.ordering != b.ordering)));
+    // Same beliefs, same answer
+    assert_eq!(best_orderings(&beliefs, 5), ranked);
+
+    // The score is the log likelihood, and nothing we draw does better
+    let score = |ordering: &[CardValue; 13]| beliefs.iter()
+        .map(|((a, b), p)| if position(ordering, *a) < position(ordering, *b) { (1.0 + p) / 2.0 } else { (1.0 - p) / 2.0 })
+        .map(|likelihood: f64| likelihood.ln())
+        .sum::<f64>();
+    assert!((score(&best) - ranked[0].score).abs() < 1e-9);
+    let mut rng = StdRng::seed_from_u64(176);
+    for _ in 0..100 {
+        assert!(score(&generate_ordering_with(&[], &mut rng).unwrap()) <= ranked[0].score + 1e-9);
+    }
+
+    // Without any beliefs, every ordering is as good as any other
+    let ranked = best_orderings(&[], 1);
+    assert_eq!(ranked[0].score, 0.0);
+    assert!(values.iter().all(|value| ranked[0].ordering.contains(value)));
+}
@@ -2,6 +2,8 @@ use crate::skeleton::cards::{CardValue};
 use crate::into_ordering;
 use itertools::Itertools;
 use std::collections::HashSet;
+use std::error::Error;
+use std::fmt;
 // use crate::debug_println;
 
 trait CloneableIterator: Iterator + Clone {}
@@ -84,6 +86,55 @@ pub fn detect_cycles(relations: &[(CardValue, CardValue)]) -> Vec<Vec<CardValue>
     cycles
 }
 
+/// Relations that can't all hold, because some of them go around in cycles
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CycleError {
+    /// Every cycle, as the values along it with the first one repeated at the end
+    pub cycles: Vec<Vec<CardValue>>,
+}
+
+impl fmt::Display for CycleError {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        write!(fmt, "Detected cycles\n{}", self.cycles.iter().map(|cycle| cycle.iter().format(" -> ")).format("\n"))
+    }
+}
+
+impl Error for CycleError {}
+
+/// Checks relations for cycles. Only looks for the cycles themselves when there are some to report.
+pub fn check_cycles(relations: &[(CardValue, CardValue)]) -> Result<(), CycleError> {
+    if RelationGraph::from_relations(relations).has_cycle() {
+        Err(CycleError { cycles: detect_cycles(relations) })
+    } else {
+        Ok(())
+    }
+}
+
+/// What `resolve_cycles` kept and what it had to drop
+#[derive(Debug, Clone, PartialEq)]
+pub struct Resolution {
+    pub kept: Vec<(CardValue, CardValue)>,
+    pub dropped: Vec<((CardValue, CardValue), f64)>,
+}
+
+/// Breaks every cycle in a set of relations, each paired with how confident we are of it. Relations are taken
+/// from the most confident down, and the ones that would close a cycle with relations already taken are dropped,
+/// so a relation only goes when everything it contradicts is more certain.
+pub fn resolve_cycles(relations: &[((CardValue, CardValue), f64)]) -> Resolution {
+    let mut sorted = relations.to_vec();
+    sorted.sort_by(|(_, a), (_, b)| b.partial_cmp(a).unwrap());
+    let mut graph = RelationGraph::new();
+    let mut dropped = vec![];
+    for ((lower, higher), confidence) in sorted {
+        if lower == higher || graph.reaches(higher, lower) {
+            dropped.push(((lower, higher), confidence));
+        } else {
+            graph.add(lower, higher);
+        }
+    }
+    Resolution { kept: graph.relations(), dropped }
+}
+
 pub trait RelationsExt {
     fn debug_relations(&self) -> String;
     fn remove_redundancies(&self) -> Vec<(CardValue, CardValue)>;
@@ -108,21 +159,9 @@ impl RelationsExt for [(CardValue, CardValue)] {
         simplify(self)
     }
 
+    // Every ordering that respects the relations, so none at all if they have cycles
     fn possibilities(&self) -> u64 {
-        assert!(detect_cycles(self).len() == 0);
-        let ordering = into_ordering!("2,3,4,5,6,7,8,9,T,J,Q,K,A");
-        ordering.iter().fold((1, vec![]), |(count, mut seen), cv| {
-            // We don't know the relative ordering of these relationships, so just always count them
-            let possible: Vec<_> = ordering.iter().filter(|value| {
-                let (mut pre, mut post, _) = relationships(&self, value);
-                pre.all(|x| seen.contains(&x)) && !post.any(|x| seen.contains(&x))
-            }).cloned().collect();
-            let value = possible.len().saturating_sub(seen.len());
-            let value = if value == 0 { 1 } else { value };
-            // debug_println!("Possible Len: {} ({}) [{}]", possible.len(), seen.len(), value);
-            seen.push(*cv);
-            (count * value as u64, seen)
-        }).0
+        LinearExtensions::new(self).count()
     }
 }
 
@@ -163,50 +202,272 @@ fn remove_redundancies(relations: &[(CardValue, CardValue)]) -> Vec<(CardValue,
     new
 }
 
-pub fn generate_ordering(relations: &[(CardValue, CardValue)]) -> [CardValue; 13] {
-    let cycles = detect_cycles(relations);
-    assert!(cycles.len() == 0, "Detected cycles\n{}", cycles.into_iter().map(|x| x.into_iter().format(" -> ")).format("\n"));
-    let original = into_ordering!("2,3,4,5,6,7,8,9,T,J,Q,K,A").to_vec();
-    let mut new = [CardValue::Two; 13];
-    let gen_index = || -> usize {
-        use rand::prelude::*;
-        let mut rng = rand::thread_rng();
-        for i in 0..13 {
-            if rng.gen_bool(0.25) {
-                return i
+/// A set of relations as a 13×13 bit matrix, indexed by the values' discriminants. The transitive closure is kept
+/// up to date as edges are added, so reachability is a single bit test and cycles show up on the diagonal.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct RelationGraph {
+    // Bit b of edges[a] is set for every relation (a, b)
+    edges: [u16; 13],
+    // Bit b of reach[a] is set when b can be reached from a through one or more relations
+    reach: [u16; 13],
+}
+
+impl RelationGraph {
+    pub fn new() -> RelationGraph {
+        RelationGraph::default()
+    }
+
+    pub fn from_relations(relations: &[(CardValue, CardValue)]) -> RelationGraph {
+        let mut graph = RelationGraph::new();
+        for (lower, higher) in relations {
+            graph.add(*lower, *higher);
+        }
+        graph
+    }
+
+    /// Every relation that was added, in value order
+    pub fn relations(&self) -> Vec<(CardValue, CardValue)> {
+        let values = into_ordering!("2,3,4,5,6,7,8,9,T,J,Q,K,A");
+        values.iter()
+            .flat_map(|a| values.iter().map(move |b| (*a, *b)))
+            .filter(|(a, b)| self.contains(*a, *b))
+            .collect()
+    }
+
+    /// Adds the relation `lower -> higher`. Returns whether it wasn't there already.
+    pub fn add(&mut self, lower: CardValue, higher: CardValue) -> bool {
+        let (a, b) = (lower as usize, higher as usize);
+        if self.edges[a] & (1 << b) != 0 {
+            return false;
+        }
+        self.edges[a] |= 1 << b;
+        // Everything that reaches `lower` (and `lower` itself) now reaches `higher` and everything after it
+        let gained = (1 << b) | self.reach[b];
+        for x in 0..13 {
+            if x == a || self.reach[x] & (1 << a) != 0 {
+                self.reach[x] |= gained;
             }
         }
-        0 // The most likely result. In case we fail every time, just given the most given result
-    };
-    let mut original: Vec<_> = original.into_iter().map(|v| (v, relationships(relations, &v)))
-        .map(|(v, (pre, post, viol))| {
-            assert!(viol.clone().count() == 0, "Something is both pre and post: {}", viol.format(", "));
-            (v, (pre, post))
-        })
-        .collect();
-    // debug_println!("Generation Rules:\n{}", relations.debug_relations());
-    for index in 0..13 {
-        let valid = original
-            .iter()
-            .filter_map(|(v, (pre, post))| {
-                if (pre.clone().all(|x| new[..index].contains(&x)) && post.clone().all(|x| !new[..index].contains(&x))) && !new[..index].contains(v) {
-                    Some(*v)
-                } else {
-                    None
+        true
+    }
+
+    /// Removes the relation `lower -> higher`. Returns whether it was there.
+    pub fn remove(&mut self, lower: CardValue, higher: CardValue) -> bool {
+        let (a, b) = (lower as usize, higher as usize);
+        if self.edges[a] & (1 << b) == 0 {
+            return false;
+        }
+        self.edges[a] &= !(1 << b);
+        // Paths can't be taken apart one edge at a time, so rebuild the closure
+        self.reach = self.edges;
+        for k in 0..13 {
+            for x in 0..13 {
+                if self.reach[x] & (1 << k) != 0 {
+                    self.reach[x] |= self.reach[k];
                 }
+            }
+        }
+        true
+    }
+
+    /// Whether `lower -> higher` was added directly
+    pub fn contains(&self, lower: CardValue, higher: CardValue) -> bool {
+        self.edges[lower as usize] & (1 << higher as usize) != 0
+    }
+
+    /// Whether `lower` has to come before `higher`, directly or through other relations
+    pub fn reaches(&self, lower: CardValue, higher: CardValue) -> bool {
+        self.reach[lower as usize] & (1 << higher as usize) != 0
+    }
+
+    /// Bit mask of the values that have to come after `value`
+    pub fn after(&self, value: CardValue) -> u16 {
+        self.reach[value as usize]
+    }
+
+    /// Bit mask of the values that have to come before `value`
+    pub fn before(&self, value: CardValue) -> u16 {
+        (0..13).filter(|x| self.reach[*x] & (1 << value as usize) != 0).fold(0, |mask, x| mask | (1 << x))
+    }
+
+    /// Whether `value` is part of a cycle
+    pub fn in_cycle(&self, value: CardValue) -> bool {
+        self.reaches(value, value)
+    }
+
+    pub fn has_cycle(&self) -> bool {
+        (0..13).any(|x| self.reach[x] & (1 << x) != 0)
+    }
+
+    pub fn len(&self) -> usize {
+        self.edges.iter().map(|edges| edges.count_ones() as usize).sum()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.edges.iter().all(|edges| *edges == 0)
+    }
+}
+
+impl From<&[(CardValue, CardValue)]> for RelationGraph {
+    fn from(relations: &[(CardValue, CardValue)]) -> RelationGraph {
+        RelationGraph::from_relations(relations)
+    }
+}
+
+impl RelationsExt for RelationGraph {
+    fn debug_relations(&self) -> String {
+        let values = into_ordering!("2,3,4,5,6,7,8,9,T,J,Q,K,A");
+        let in_mask = |mask: u16| values.iter().copied().filter(|v| mask & (1 << *v as usize) != 0).collect::<Vec<_>>();
+        format!("{}", values.iter().map(|v| {
+            let pre = (0..13).filter(|x| self.edges[*x] & (1 << *v as usize) != 0).fold(0, |mask, x| mask | (1 << x));
+            format!("|{}|[{}][{}]", v, in_mask(pre).iter().format(""), in_mask(self.edges[*v as usize]).iter().format(""))
+        }).format("\n"))
+    }
+
+    fn remove_redundancies(&self) -> Vec<(CardValue, CardValue)> {
+        self.relations()
+    }
+
+    // An edge is redundant when another edge out of the same value leads to its end anyway
+    fn simplify(&self) -> Vec<(CardValue, CardValue)> {
+        self.relations().into_iter()
+            .filter(|(a, b)| {
+                let others = self.edges[*a as usize] & !(1 << *b as usize);
+                !(0..13).any(|c| others & (1 << c) != 0 && self.reach[c] & (1 << *b as usize) != 0)
             })
-            .collect::<Vec<_>>();
-        // debug_println!("Valid: [{}]", valid.iter().format(", "));
-        // let selected = rand::random::<usize>() % valid.len();
-        let selected = gen_index() % valid.len();
-        let value = valid[selected];
-        let original_pos = original.iter().position(|(v, _)| v == &value).unwrap();
-        let (_, (pre, post)) = original.remove(original_pos);
-        drop(pre);
-        drop(post);
-        new[index] = value;
+            .collect()
+    }
+
+    fn possibilities(&self) -> u64 {
+        LinearExtensions::from_graph(self).count()
+    }
+}
+
+/// Every ordering that respects a set of relations (its linear extensions). `ways[placed]` is how many ways there
+/// are to finish an ordering whose lowest values are the set `placed`, so counting is a DP over all 2^13 sets, and
+/// drawing from the same table gives every ordering the same chance.
+#[derive(Debug, Clone)]
+pub struct LinearExtensions {
+    // Bit j of before[i] is set when value j has to come before value i
+    before: [u16; 13],
+    ways: Vec<u64>,
+}
+
+impl LinearExtensions {
+    pub fn new(relations: &[(CardValue, CardValue)]) -> LinearExtensions {
+        LinearExtensions::from_graph(&RelationGraph::from_relations(relations))
+    }
+
+    pub fn from_graph(graph: &RelationGraph) -> LinearExtensions {
+        let mut before = [0; 13];
+        for (i, value) in into_ordering!("2,3,4,5,6,7,8,9,T,J,Q,K,A").iter().enumerate() {
+            before[i] = graph.before(*value);
+        }
+        let full = (1 << 13) - 1;
+        let mut ways = vec![0; 1 << 13];
+        ways[full] = 1;
+        for placed in (0..full).rev() {
+            // Values in a cycle come before themselves, so they can never be placed and nothing gets counted
+            ways[placed] = (0..13)
+                .filter(|i| placed & (1 << i) == 0 && before[*i] as usize & !placed == 0)
+                .map(|i| ways[placed | (1 << i)])
+                .sum();
+        }
+        LinearExtensions { before, ways }
+    }
+
+    /// How many orderings respect the relations (none, if they have cycles)
+    pub fn count(&self) -> u64 {
+        self.ways[0]
+    }
+
+    /// Draws one of the orderings, all of them being equally likely. Every slot from the bottom takes a value
+    /// with a chance proportional to how many orderings start that way.
+    pub fn sample<R: rand::Rng + ?Sized>(&self, rng: &mut R) -> [CardValue; 13] {
+        assert!(self.count() > 0, "No ordering respects relations with cycles");
+        let values = into_ordering!("2,3,4,5,6,7,8,9,T,J,Q,K,A");
+        let mut ordering = [CardValue::Two; 13];
+        let mut placed: usize = 0;
+        for slot in ordering.iter_mut() {
+            let mut pick = rng.gen_range(0, self.ways[placed]);
+            let index = (0..13)
+                .filter(|i| placed & (1 << i) == 0 && self.before[*i] as usize & !placed == 0)
+                .find(|i| {
+                    let ways = self.ways[placed | (1 << i)];
+                    if pick < ways {
+                        true
+                    } else {
+                        pick -= ways;
+                        false
+                    }
+                })
+                .unwrap();
+            placed |= 1 << index;
+            *slot = values[index];
+        }
+        ordering
+    }
+}
+
+/// Draws an ordering that respects the relations, every such ordering being equally likely
+pub fn generate_ordering(relations: &[(CardValue, CardValue)]) -> Result<[CardValue; 13], CycleError> {
+    generate_ordering_with(relations, &mut rand::thread_rng())
+}
+
+/// Same as `generate_ordering`, but draws from the given rng so results can be reproduced
+pub fn generate_ordering_with<R: rand::Rng + ?Sized>(relations: &[(CardValue, CardValue)], rng: &mut R) -> Result<[CardValue; 13], CycleError> {
+    check_cycles(relations)?;
+    Ok(LinearExtensions::new(relations).sample(rng))
+}
+
+/// Generates orderings that respect a set of relations, favouring the ones the engine's shuffle is likely to give.
+/// The relations are checked and indexed once, so drawing many orderings from the same relations is cheap.
+#[derive(Debug, Clone)]
+pub struct OrderingGenerator {
+    values: [CardValue; 13],
+    // Bit j of before[i] is set when values[j] has to come before values[i]
+    before: [u16; 13],
+}
+
+impl OrderingGenerator {
+    /// Panics if the relations have cycles
+    pub fn new(relations: &[(CardValue, CardValue)]) -> OrderingGenerator {
+        OrderingGenerator::try_new(relations).unwrap_or_else(|error| panic!("{}", error))
+    }
+
+    pub fn try_new(relations: &[(CardValue, CardValue)]) -> Result<OrderingGenerator, CycleError> {
+        check_cycles(relations)?;
+        let values = into_ordering!("2,3,4,5,6,7,8,9,T,J,Q,K,A");
+        let index = |value: &CardValue| values.iter().position(|v| v == value).unwrap();
+        let mut before = [0; 13];
+        for (lower, higher) in relations.iter() {
+            before[index(higher)] |= 1 << index(lower);
+        }
+        Ok(OrderingGenerator { values, before })
+    }
+
+    /// Fills the ordering from the bottom. Every slot takes a geometrically distributed pick out of
+    /// the values whose lower values are all placed already, just like the engine permutes values.
+    pub fn generate<R: rand::Rng + ?Sized>(&self, rng: &mut R) -> [CardValue; 13] {
+        let mut gen_index = || -> usize {
+            for i in 0..13 {
+                if rng.gen_bool(0.25) {
+                    return i
+                }
+            }
+            0 // The most likely result. In case we fail every time, just given the most given result
+        };
+        let mut new = [CardValue::Two; 13];
+        let mut placed: u16 = 0;
+        for slot in new.iter_mut() {
+            let valid = (0..13).filter(|&i| placed & (1 << i) == 0 && self.before[i] & !placed == 0);
+            let selected = gen_index() % valid.clone().count();
+            let index = valid.clone().nth(selected).unwrap();
+            placed |= 1 << index;
+            *slot = self.values[index];
+        }
+        new
     }
-    new
 }
 
 // test for correctness
@@ -248,6 +509,77 @@ fn relations_ext_test() {
     let test2 = vec![(CardValue::Two, CardValue::Ace)];
     let test3 = vec![(CardValue::Two, CardValue::Ace), (CardValue::Three, CardValue::Ace)];
     assert_eq!(test.possibilities(), 13 * 12 * 11 * 10 * 9 * 8 * 7 * 6 * 5 * 4  * 3 * 2);
-    assert_eq!(test2.possibilities(), 12 * 12 * 11 * 10 * 9 * 8 * 7 * 6 * 5 * 4  * 3 * 2);
-    assert_eq!(test3.possibilities(), 12 * 11 * 11 * 10 * 9 * 8 * 7 * 6 * 5 * 4  * 3 * 2);
+    // Two comes before Ace in half of the orderings, and Ace is last of three values in a third of them
+    assert_eq!(test2.possibilities(), 13 * 12 * 11 * 10 * 9 * 8 * 7 * 6 * 5 * 4  * 3);
+    assert_eq!(test3.possibilities(), 13 * 12 * 11 * 10 * 9 * 8 * 7 * 6 * 5 * 4  * 2);
+    let cycle = vec![(CardValue::Two, CardValue::Ace), (CardValue::Ace, CardValue::Two)];
+    assert_eq!(cycle.possibilities(), 0);
+}
+
+#[test]
+fn relation_graph_test() {
+    let relations = vec![(CardValue::Two, CardValue::Three), (CardValue::Three, CardValue::Four), (CardValue::Two, CardValue::Four)];
+    let mut graph = RelationGraph::from_relations(&relations);
+    assert_eq!(graph.relations(), relations.remove_redundancies().into_iter().sorted().collect::<Vec<_>>());
+    assert!(graph.reaches(CardValue::Two, CardValue::Four));
+    assert!(!graph.reaches(CardValue::Four, CardValue::Two));
+    assert_eq!(graph.before(CardValue::Four), (1 << CardValue::Two as usize) | (1 << CardValue::Three as usize));
+    assert_eq!(graph.simplify(), vec![(CardValue::Two, CardValue::Three), (CardValue::Three, CardValue::Four)]);
+    assert!(!graph.add(CardValue::Two, CardValue::Three));
+    assert_eq!(graph.len(), 3);
+
+    // Closing the loop shows up right away, and taking the edge out again undoes it
+    assert!(graph.add(CardValue::Four, CardValue::Two));
+    assert!(graph.has_cycle());
+    assert!(graph.in_cycle(CardValue::Three));
+    assert!(!graph.in_cycle(CardValue::Ace));
+    assert!(graph.remove(CardValue::Four, CardValue::Two));
+    assert!(!graph.has_cycle());
+    assert!(!graph.remove(CardValue::Four, CardValue::Two));
+    assert!(graph.remove(CardValue::Three, CardValue::Four));
+    assert!(graph.reaches(CardValue::Two, CardValue::Four));
+    assert!(!graph.reaches(CardValue::Three, CardValue::Four));
+    assert_eq!(RelationGraph::from(&relations[..]).possibilities(), relations.possibilities());
+}
+
+#[test]
+fn linear_extensions_test() {
+    use rand::{SeedableRng, rngs::StdRng};
+    // Everything but the ace is in a chain, so the ace can go in any of 13 places
+    let values = into_ordering!("2,3,4,5,6,7,8,9,T,J,Q,K,A");
+    let relations = values[..12].windows(2).map(|pair| (pair[0], pair[1])).collect::<Vec<_>>();
+    let extensions = LinearExtensions::new(&relations);
+    assert_eq!(extensions.count(), 13);
+    let mut rng = StdRng::seed_from_u64(176);
+    let mut positions = [0; 13];
+    for _ in 0..13000 {
+        let ordering = extensions.sample(&mut rng);
+        let position = |value: CardValue| ordering.iter().position(|v| *v == value).unwrap();
+        assert!(relations.iter().all(|(lower, higher)| position(*lower) < position(*higher)));
+        positions[position(CardValue::Ace)] += 1;
+    }
+    assert!(positions.iter().all(|count| *count > 800 && *count < 1200), "Ace positions {:?}", positions);
+}
+
+#[test]
+fn cycle_resolution_test() {
+    let relations = vec![
+        ((CardValue::Two, CardValue::Three), 0.9),
+        ((CardValue::Three, CardValue::Four), 0.8),
+        ((CardValue::Four, CardValue::Two), 0.3),
+        ((CardValue::Four, CardValue::Five), 0.1),
+    ];
+    let plain = relations.iter().map(|(relation, _)| *relation).collect::<Vec<_>>();
+    let error = check_cycles(&plain).unwrap_err();
+    assert_eq!(error.cycles.len(), 1);
+    assert!(error.to_string().starts_with("Detected cycles\n"));
+    assert_eq!(generate_ordering(&plain), Err(error));
+    assert!(OrderingGenerator::try_new(&plain).is_err());
+
+    // The least confident relation of the cycle goes, and nothing else
+    let resolution = resolve_cycles(&relations);
+    assert_eq!(resolution.dropped, vec![((CardValue::Four, CardValue::Two), 0.3)]);
+    assert_eq!(resolution.kept.len(), 3);
+    assert!(check_cycles(&resolution.kept).is_ok());
+    assert!(generate_ordering(&resolution.kept).is_ok());
 }
\ No newline at end of file
@@ -0,0 +1,239 @@
+//! Scores the orderings a bot believed in during a match. Every showdown of a finished match is known, so the
+//! ordering they point to is about as close to the truth as we can get, and each snapshot the bot logged is
+//! compared with it: by Kendall tau distance (how many pairs of values the two orderings disagree on) and by how
+//! many of the relations the showdowns prove the snapshot breaks.
+
+use crate::skeleton::{cards::CardValue, gamelog::GameLog};
+use super::constraints::ShowdownConstraints;
+use super::posterior::OrderingPosterior;
+use std::fmt::Write as FmtWrite;
+use std::io::{self, BufRead};
+use itertools::Itertools;
+
+/// Steps of the posterior's chain to take once every showdown of a match is in
+pub const DEDUCTION_STEPS: u32 = 20000;
+// How wide the chart gets, in characters, for orderings that disagree on every pair
+const CHART_WIDTH: usize = 60;
+
+/// The ordering (lowest to highest) the showdowns of a match point to
+#[derive(Debug, Clone)]
+pub struct TrueOrdering {
+    pub ordering: [CardValue; 13],
+    pub showdowns: usize,
+    /// How many showdowns the ordering doesn't explain. Anything but 0 means the chain needs more steps.
+    pub mismatches: usize,
+    /// (lower, higher) relations every explanation of some showdown agrees on
+    pub relations: Vec<(CardValue, CardValue)>,
+}
+
+/// Deduces the most likely ordering from every showdown in `game`
+pub fn deduce_ordering(game: &GameLog, seed: u64, steps: u32) -> TrueOrdering {
+    let mut posterior = OrderingPosterior::new(seed);
+    let mut relations = vec![];
+    for round in game.rounds.iter().filter(|round| round.showdown) {
+        let last = &round.terminal.previous;
+        let (first, second) = (last.hands[0].unwrap(), last.hands[1].unwrap());
+        let result = round.terminal.deltas[0].cmp(&0);
+        posterior.observe_showdown(&last.deck.0, &first, &second, result);
+        relations.extend(ShowdownConstraints::new(&last.deck.0, &first, &second, result).definite());
+    }
+    posterior.run(steps);
+    let ordering = posterior.map_ordering();
+    TrueOrdering {
+        ordering,
+        showdowns: posterior.showdowns(),
+        mismatches: posterior.count_mismatches(&ordering),
+        relations: relations.into_iter().unique().collect(),
+    }
+}
+
+/// How many pairs of values `a` and `b` order differently, from 0 (the same ordering) to 78 (reversed)
+pub fn kendall_tau(a: &[CardValue; 13], b: &[CardValue; 13]) -> u32 {
+    let mut positions = [0; 13];
+    for (i, value) in b.iter().enumerate() {
+        positions[*value as usize] = i;
+    }
+    let mut distance = 0;
+    for (i, lower) in a.iter().enumerate() {
+        for higher in &a[i + 1..] {
+            if positions[*higher as usize] < positions[*lower as usize] {
+                distance += 1;
+            }
+        }
+    }
+    distance
+}
+
+/// How many of the (lower, higher) `relations` `ordering` breaks
+pub fn violations(ordering: &[CardValue; 13], relations: &[(CardValue, CardValue)]) -> usize {
+    let position = |value: &CardValue| ordering.iter().position(|v| v == value).unwrap();
+    relations.iter().filter(|(lower, higher)| position(higher) < position(lower)).count()
+}
+
+/// An ordering the bot logged during a match
+#[derive(Debug, Clone, PartialEq)]
+pub struct Snapshot {
+    pub round_num: u32,
+    /// What the bot called it, like `Ordering` or `Posterior MAP`
+    pub label: String,
+    pub ordering: [CardValue; 13],
+}
+
+// Reads the ordering out of `[2,3,...,A]`, if there are exactly the 13 values in it
+fn parse_ordering(s: &str) -> Option<[CardValue; 13]> {
+    let values = s.split(',').map(|value| value.trim().parse::<CardValue>().ok()).collect::<Option<Vec<_>>>()?;
+    if values.len() != 13 || values.iter().unique().count() != 13 {
+        return None;
+    }
+    let mut ordering = [CardValue::Two; 13];
+    ordering.copy_from_slice(&values);
+    Some(ordering)
+}
+
+/// Reads every ordering out of what a bot printed during a match. Lines starting with `Round #<n>` tell which
+/// round we are in, and any other line with a whole ordering in brackets (like `Ordering [2,3,...,A]`, or the
+/// `Most likely` and `Posterior MAP` lines of an internal state dump) is a snapshot, labelled by what comes before
+/// the brackets.
+pub fn read_snapshots<R: BufRead>(reader: R) -> io::Result<Vec<Snapshot>> {
+    let mut round_num = 0;
+    let mut snapshots = vec![];
+    for line in reader.lines() {
+        let line = line?;
+        let line = line.trim();
+        if let Some(header) = line.strip_prefix("Round #") {
+            let digits: String = header.chars().take_while(|c| c.is_ascii_digit()).collect();
+            round_num = digits.parse().unwrap_or(round_num);
+            continue;
+        }
+        if let (Some(open), Some(close)) = (line.find('['), line.find(']')) {
+            if let Some(ordering) = line.get(open + 1..close).and_then(parse_ordering) {
+                // Leave out counts like `(12 showdowns)`, so the same kind of snapshot always has the same label
+                let label = line[..open].split_whitespace().filter(|word| !word.starts_with('(') && !word.ends_with(')')).join(" ");
+                snapshots.push(Snapshot { round_num, label, ordering });
+            }
+        }
+    }
+    Ok(snapshots)
+}
+
+/// How far a snapshot was from the truth
+#[derive(Debug, Clone, PartialEq)]
+pub struct SnapshotScore {
+    pub round_num: u32,
+    pub label: String,
+    pub kendall_tau: u32,
+    pub violations: usize,
+}
+
+pub fn score(snapshots: &[Snapshot], truth: &TrueOrdering) -> Vec<SnapshotScore> {
+    snapshots.iter().map(|snapshot| SnapshotScore {
+        round_num: snapshot.round_num,
+        label: snapshot.label.clone(),
+        kendall_tau: kendall_tau(&snapshot.ordering, &truth.ordering),
+        violations: violations(&snapshot.ordering, &truth.relations),
+    }).collect()
+}
+
+/// Charts how the scores converge over the match, one line per snapshot with a bar as long as its distance
+pub fn chart(scores: &[SnapshotScore]) -> String {
+    let label_width = scores.iter().map(|score| score.label.len()).max().unwrap_or(0).max("Snapshot".len());
+    let mut chart = String::new();
+    writeln!(chart, "{:>5}  {:<width$}  {:>3}  {:>10}", "Round", "Snapshot", "Tau", "Violations", width = label_width).unwrap();
+    for score in scores {
+        let bar = "#".repeat(score.kendall_tau as usize * CHART_WIDTH / 78);
+        writeln!(chart, "{:>5}  {:<width$}  {:>3}  {:>10}  |{}", score.round_num, score.label, score.kendall_tau, score.violations, bar, width = label_width).unwrap();
+    }
+    chart
+}
+
+#[test]
+fn scoring_test() {
+    use crate::into_ordering;
+    let ordering = into_ordering!("2,3,4,5,6,7,8,9,T,J,Q,K,A");
+    let mut reversed = ordering;
+    reversed.reverse();
+    let mut swapped = ordering;
+    swapped.swap(0, 1);
+    assert_eq!(kendall_tau(&ordering, &ordering), 0);
+    assert_eq!(kendall_tau(&ordering, &reversed), 78);
+    assert_eq!(kendall_tau(&swapped, &ordering), 1);
+    assert_eq!(violations(&swapped, &[(CardValue::Two, CardValue::Three), (CardValue::Two, CardValue::Ace)]), 1);
+
+    // Quad twos on the board, so the kickers decide: aces beat kings, and kings beat queens
+    let log = "6.176 MIT Pokerbots - A vs B
+Round #1, A (0), B (0)
+A posts the blind of 1
+B posts the blind of 2
+A dealt [Ah Ad]
+B dealt [Kh Kd]
+A calls
+B checks
+Flop [2c 2d 2h], A (2), B (2)
+B checks
+A checks
+Turn [2c 2d 2h 2s], A (2), B (2)
+B checks
+A checks
+River [2c 2d 2h 2s 9c], A (2), B (2)
+B checks
+A checks
+A shows [Ah Ad]
+B shows [Kh Kd]
+A awarded 2
+B awarded -2
+Round #2, B (-2), A (2)
+B posts the blind of 1
+A posts the blind of 2
+B dealt [Qh Qd]
+A dealt [Kc Ks]
+B folds
+B awarded -1
+A awarded 1
+Round #3, A (3), B (-3)
+A posts the blind of 1
+B posts the blind of 2
+A dealt [Qh Qd]
+B dealt [Kc Ks]
+A calls
+B checks
+Flop [2c 2d 2h], A (2), B (2)
+B checks
+A checks
+Turn [2c 2d 2h 2s], A (2), B (2)
+B checks
+A checks
+River [2c 2d 2h 2s 9c], A (2), B (2)
+B checks
+A checks
+A shows [Qh Qd]
+B shows [Kc Ks]
+A awarded -2
+B awarded 2
+";
+    let game = GameLog::parse(log.as_bytes()).unwrap();
+    let truth = deduce_ordering(&game, 176, DEDUCTION_STEPS);
+    assert_eq!((truth.showdowns, truth.mismatches), (2, 0));
+    assert!(truth.relations.contains(&(CardValue::King, CardValue::Ace)));
+    assert!(truth.relations.contains(&(CardValue::Queen, CardValue::King)));
+    assert_eq!(violations(&truth.ordering, &truth.relations), 0);
+
+    // Snapshots from the bot's output, with the truth being the plain ordering
+    let output = "Round #1 30
+Ordering [2,3,4,5,6,7,8,9,T,J,Q,K,A]
+|2|[][3]
+Round #2 29
+Ordering [2,3,4,5,6,7,8,9,T,J,Q,A,K]
+Equity 0.5 against pot odds 0.25
+Most likely [A,K,Q,J,T,9,8,7,6,5,4,3,2] (log likelihood -3.00)
+Posterior (12 showdowns) MAP [2,3,4,5,6,7,8,9,T,J,K,Q,A]
+";
+    let snapshots = read_snapshots(output.as_bytes()).unwrap();
+    assert_eq!(snapshots.iter().map(|snapshot| (snapshot.round_num, snapshot.label.as_str())).collect::<Vec<_>>(),
+        [(1, "Ordering"), (2, "Ordering"), (2, "Most likely"), (2, "Posterior MAP")]);
+    let truth = TrueOrdering { ordering, showdowns: 2, mismatches: 0, relations: vec![(CardValue::King, CardValue::Ace), (CardValue::Queen, CardValue::King)] };
+    let scores = score(&snapshots, &truth);
+    assert_eq!(scores.iter().map(|score| (score.kendall_tau, score.violations)).collect::<Vec<_>>(), [(0, 0), (1, 1), (78, 2), (1, 1)]);
+    let chart = chart(&scores);
+    assert_eq!(chart.lines().count(), 5);
+    assert!(chart.lines().nth(3).unwrap().ends_with(&format!("|{}", "#".repeat(CHART_WIDTH))));
+}
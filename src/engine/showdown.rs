@@ -10,6 +10,7 @@ use std::fmt;
 use itertools::Itertools;
 use std::collections::HashSet;
 use std::borrow::Borrow;
+use super::evaluator::{Evaluator, HandStrength};
 // use crate::debug_println;
 
 #[macro_export]
@@ -710,6 +711,13 @@ impl ShowdownEngine {
         }
     }
 
+    /// Strength of the best five cards, kickers included. Unlike `compare_hands`, comparing these
+    /// settles a showdown exactly.
+    pub fn rank_hand(&self, hand: &[Card]) -> HandStrength {
+        Evaluator::new(self.ordering).evaluate_cards(hand)
+    }
+
+    /// Compares the hands themselves, so kickers outside the hand are never looked at (see `rank_hand`)
     pub fn compare_hands(&self, a: &Hand, b: &Hand) -> Ordering {
         let resolve_conflict = |a: &HashSet<Card>, b: &HashSet<Card>| {
             let ahc = self.highest_card_value(a.iter());
@@ -2,23 +2,24 @@ mod bots;
 
 use clap::{App, Arg, SubCommand, AppSettings};
 use bots::*;
-use tinyrasputin::{debug_println, skeleton::{runner::Runner, bot::PokerBot}};
+use tinyrasputin::{debug_println, skeleton::{runner::Runner, bot::PokerBot, dealer::Dealer, server::Server, states::NUM_ROUNDS, history::{self, HistoryLog, DEFAULT_MAX_BYTES}, replay, gamelog::{self, GameLog}}, engine::scoring};
 use std::net::Ipv4Addr;
 use std::path::Path;
 use std::convert::AsRef;
+use std::io::{BufRead, BufReader};
+use std::fs::File;
 
 const THREAD_COUNT: usize = 1;
 
 #[cfg(debug_assertions)]
 fn analyze_mode<P: AsRef<Path>>(path: P) -> std::io::Result<()> {
-    use std::io::{BufReader, BufRead};
-    use std::fs::File;
     use itertools::Itertools;
     use std::borrow::Borrow;
     use std::collections::HashSet;
     use tinyrasputin::{
         engine::{
             relations::{RelationsExt},
+            probability::ProbabilityEngine,
             showdown::{ShowdownEngine, PotentialHand, Hand}
         },
         skeleton::cards::{CardValue, Card},
@@ -130,6 +131,22 @@ fn analyze_mode<P: AsRef<Path>>(path: P) -> std::io::Result<()> {
                     println!("")
                 }
             },
+            "cycles" => {
+                // Beliefs look like `a b certainty rule name`, the way ProbabilityEngine::update takes them
+                let count: usize = lines.remove(0).trim().parse::<usize>().unwrap_or(0);
+                let mut engine = ProbabilityEngine::new();
+                for belief in lines.drain(0..count) {
+                    let parts: Vec<_> = belief.trim().splitn(4, ' ').collect();
+                    assert!(parts.len() == 4, "Invalid belief {}", belief.trim());
+                    let a = parts[0].parse::<CardValue>().unwrap();
+                    let b = parts[1].parse::<CardValue>().unwrap();
+                    engine.update(parts[3], &a, &b, parts[2].parse::<f64>().unwrap());
+                }
+                match engine.cycle_report() {
+                    Some(report) => println!("{}", report),
+                    None => println!("No cycles"),
+                }
+            },
             c => println!("Invalid command {}", c)
         }
     }
@@ -141,6 +158,18 @@ fn analyze_mode<P: AsRef<Path>>(_: P) -> std::io::Result<()> {
     panic!("Analyze mode is disabled for release!");
 }
 
+// Change the bot type here, and as long as it implements Default, it'll be built
+fn create_bot(version: &str) -> Box<dyn PokerBot + Send + Sync> {
+    match version {
+        "test" => Box::new(TestBot::default()),
+        "l1" => Box::new(Lesson1Bot::default()),
+        "l2" => Box::new(Lesson2Bot::default()),
+        "tourney" => Box::new(TourneyV1Bot::default()),
+        "empty" => Box::new(EmptyBot::default()),
+        _ => panic!("Invalid bot version: {}", version)
+    }
+}
+
 fn main() -> std::io::Result<()> {
     // read in arguments
     let matches = App::new("TinyRasputin - A Rust PokerBot")
@@ -171,10 +200,116 @@ fn main() -> std::io::Result<()> {
                             .value_name("BOT_VERSION")
                             .help("Selects which bot version to use [can be: test, l1, l2, tourney]")
                             .takes_value(true))
+                        .arg(Arg::with_name("history")
+                            .long("history")
+                            .value_name("PATH")
+                            .help("Records every round played as a hand history file")
+                            .takes_value(true))
+                        .arg(Arg::with_name("sequential")
+                            .long("sequential")
+                            .help("Handles every clause in order on one thread, so the same match always plays out the same way"))
+                        .arg(Arg::with_name("history-cap")
+                            .long("history-cap")
+                            .value_name("BYTES")
+                            .help("Largest the hand history file is allowed to get")
+                            .takes_value(true))
                         .arg(Arg::with_name("port")
                             .help("Port to connect to")
                             .required(true)
                             .index(1)))
+                    .subcommand(SubCommand::with_name("match")
+                        .about("Play two bots included in this package against each other locally")
+                        .version(env!("CARGO_PKG_VERSION"))
+                        .author("Jengamon <uokwo@mit.edu>")
+                        .arg(Arg::with_name("seed")
+                            .short("s")
+                            .long("seed")
+                            .value_name("SEED")
+                            .help("Seeds the deck and value permutation so a match can be replayed")
+                            .takes_value(true))
+                        .arg(Arg::with_name("rounds")
+                            .short("r")
+                            .long("rounds")
+                            .value_name("ROUNDS")
+                            .help("Number of rounds to play (defaults to a full match)")
+                            .takes_value(true))
+                        .arg(Arg::with_name("first")
+                            .help("First bot version [can be: test, l1, l2, tourney, empty]")
+                            .required(true)
+                            .index(1))
+                        .arg(Arg::with_name("second")
+                            .help("Second bot version [can be: test, l1, l2, tourney, empty]")
+                            .required(true)
+                            .index(2)))
+                    .subcommand(SubCommand::with_name("replay")
+                        .about("Replay a hand history or engine game log through a bot and report where it would have played differently")
+                        .version(env!("CARGO_PKG_VERSION"))
+                        .author("Jengamon <uokwo@mit.edu>")
+                        .arg(Arg::with_name("bot")
+                            .short("b")
+                            .long("bot")
+                            .value_name("BOT_VERSION")
+                            .help("Selects which bot version to replay with [can be: test, l1, l2, tourney, empty]")
+                            .takes_value(true))
+                        .arg(Arg::with_name("player")
+                            .short("p")
+                            .long("player")
+                            .value_name("NAME")
+                            .help("Whose seat to replay from a game log (defaults to the first player)")
+                            .takes_value(true))
+                        .arg(Arg::with_name("path")
+                            .help("Hand history or game log to replay")
+                            .required(true)
+                            .index(1)))
+                    .subcommand(SubCommand::with_name("score")
+                        .about("Deduce the true ordering from an engine game log and score the orderings a bot logged during the match")
+                        .version(env!("CARGO_PKG_VERSION"))
+                        .author("Jengamon <uokwo@mit.edu>")
+                        .arg(Arg::with_name("seed")
+                            .short("s")
+                            .long("seed")
+                            .value_name("SEED")
+                            .help("Seeds the chain used to deduce the ordering")
+                            .takes_value(true))
+                        .arg(Arg::with_name("steps")
+                            .long("steps")
+                            .value_name("STEPS")
+                            .help("Steps of the chain to take once every showdown is in")
+                            .takes_value(true))
+                        .arg(Arg::with_name("gamelog")
+                            .help("Game log of the match")
+                            .required(true)
+                            .index(1))
+                        .arg(Arg::with_name("output")
+                            .help("What the bot printed during the match")
+                            .required(true)
+                            .index(2)))
+                    .subcommand(SubCommand::with_name("server")
+                        .about("Deal a match between two bots connecting over the engine's socket protocol")
+                        .version(env!("CARGO_PKG_VERSION"))
+                        .author("Jengamon <uokwo@mit.edu>")
+                        .arg(Arg::with_name("host")
+                            .short("o")
+                            .long("host")
+                            .value_name("HOST_ADDR")
+                            .help("Listens on specified host (IPv4 only)")
+                            .takes_value(true))
+                        .arg(Arg::with_name("seed")
+                            .short("s")
+                            .long("seed")
+                            .value_name("SEED")
+                            .help("Seeds the deck and value permutation so a match can be replayed")
+                            .takes_value(true))
+                        .arg(Arg::with_name("rounds")
+                            .short("r")
+                            .long("rounds")
+                            .value_name("ROUNDS")
+                            .help("Number of rounds to play (defaults to a full match)")
+                            .takes_value(true))
+                        .arg(Arg::with_name("port")
+                            .help("Port to listen on")
+                            .required(true)
+                            .index(1)))
                     .get_matches();
 
     if let Some(matches) = matches.subcommand_matches("analyze") {
@@ -185,16 +320,64 @@ fn main() -> std::io::Result<()> {
         let botv = matches.value_of("bot").unwrap_or("tourney");
         debug_println!("Connecting to {}:{}...", host, port);
         debug_println!("Attempting to run bot version {}...", botv);
-        // Change the bot type here, and as long as it implements Default, it'll be built
-        let bot: Box<dyn PokerBot + Send + Sync> = match botv {
-            "test" => Box::new(TestBot::default()),
-            "l1" => Box::new(Lesson1Bot::default()),
-            "l2" => Box::new(Lesson2Bot::default()),
-            "tourney" => Box::new(TourneyV1Bot::default()),
-            "empty" => Box::new(EmptyBot::default()),
-            _ => panic!("Invalid bot version: {}", botv)
+        let history_cap = matches.value_of("history-cap").map(|x| x.parse::<usize>().expect("Expected positive integer for history cap")).unwrap_or(DEFAULT_MAX_BYTES);
+        let history = matches.value_of("history").map(|path| HistoryLog::create(path, history_cap)).transpose()?;
+        let bot = create_bot(botv);
+        let addr = (host.parse::<Ipv4Addr>().expect("Expected IPv4 address for host"), port);
+        let result = if matches.is_present("sequential") {
+            Runner::run_bot_sequential(bot, addr, history)
+        } else {
+            Runner::run_bot_with_history(bot, addr, THREAD_COUNT, history)
+        };
+        result.map_err(|e| std::io::Error::new(std::io::ErrorKind::Other, e))
+    } else if let Some(matches) = matches.subcommand_matches("match") {
+        let seed = matches.value_of("seed").map(|x| x.parse::<u64>().expect("Expected positive integer for seed")).unwrap_or_else(rand::random);
+        let rounds = matches.value_of("rounds").map(|x| x.parse::<u32>().expect("Expected positive integer for round count")).unwrap_or(NUM_ROUNDS);
+        let (firstv, secondv) = (matches.value_of("first").unwrap(), matches.value_of("second").unwrap());
+        let (mut first, mut second) = (create_bot(firstv), create_bot(secondv));
+        println!("Playing {} against {} for {} rounds with seed {}", firstv, secondv, rounds, seed);
+        let result = Dealer::new(seed).play_match(&mut first, &mut second, rounds);
+        println!("Ordering: {}", result.ordering.iter().map(|x| x.to_string()).collect::<String>());
+        println!("{}: {} ({:.3}s left)", firstv, result.bankrolls[0], result.game_clocks[0]);
+        println!("{}: {} ({:.3}s left)", secondv, result.bankrolls[1], result.game_clocks[1]);
+        Ok(())
+    } else if let Some(matches) = matches.subcommand_matches("replay") {
+        let botv = matches.value_of("bot").unwrap_or("tourney");
+        let mut reader = BufReader::new(File::open(matches.value_of("path").unwrap())?);
+        // Game logs from the engine start with their header, anything else is one of our hand histories
+        let rounds = if reader.fill_buf()?.starts_with(gamelog::LOG_HEADER.as_bytes()) {
+            let game = GameLog::parse(reader).map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidData, e))?;
+            let player = matches.value_of("player").map(|name| game.player(name).unwrap_or_else(|| panic!("No player named {} in the game log", name))).unwrap_or(0);
+            game.histories(player)
+        } else {
+            history::read_histories(reader)?
         };
-        Runner::run_bot(bot, (host.parse::<Ipv4Addr>().expect("Expected IPv4 address for host"), port), THREAD_COUNT)
+        let mut bot = create_bot(botv);
+        println!("Replaying {} rounds through {}", rounds.len(), botv);
+        println!("{}", replay::replay(&mut bot, &rounds));
+        Ok(())
+    } else if let Some(matches) = matches.subcommand_matches("score") {
+        let seed = matches.value_of("seed").map(|x| x.parse::<u64>().expect("Expected positive integer for seed")).unwrap_or_else(rand::random);
+        let steps = matches.value_of("steps").map(|x| x.parse::<u32>().expect("Expected positive integer for step count")).unwrap_or(scoring::DEDUCTION_STEPS);
+        let game = GameLog::parse(BufReader::new(File::open(matches.value_of("gamelog").unwrap())?)).map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidData, e))?;
+        let snapshots = scoring::read_snapshots(BufReader::new(File::open(matches.value_of("output").unwrap())?))?;
+        let truth = scoring::deduce_ordering(&game, seed, steps);
+        println!("Ordering: {} ({} showdowns, {} unexplained, {} relations)", truth.ordering.iter().map(|x| x.to_string()).collect::<String>(),
+            truth.showdowns, truth.mismatches, truth.relations.len());
+        print!("{}", scoring::chart(&scoring::score(&snapshots, &truth)));
+        Ok(())
+    } else if let Some(matches) = matches.subcommand_matches("server") {
+        let host = matches.value_of("host").unwrap_or("127.0.0.1");
+        let port = matches.value_of("port").map(|x| x.parse::<u16>().expect("Expected integer for port number")).unwrap();
+        let seed = matches.value_of("seed").map(|x| x.parse::<u64>().expect("Expected positive integer for seed")).unwrap_or_else(rand::random);
+        let rounds = matches.value_of("rounds").map(|x| x.parse::<u32>().expect("Expected positive integer for round count")).unwrap_or(NUM_ROUNDS);
+        let server = Server::bind((host.parse::<Ipv4Addr>().expect("Expected IPv4 address for host"), port), seed)?;
+        println!("Waiting for two bots on {} ({} rounds, seed {})", server.local_addr()?, rounds, seed);
+        let result = server.run(rounds)?;
+        println!("Ordering: {}", result.ordering.iter().map(|x| x.to_string()).collect::<String>());
+        println!("Player 1: {} ({:.3}s left)", result.bankrolls[0], result.game_clocks[0]);
+        println!("Player 2: {} ({:.3}s left)", result.bankrolls[1], result.game_clocks[1]);
+        Ok(())
     } else {
         unreachable!()
     }
@@ -4,4 +4,12 @@ pub mod bot;
 pub mod runner;
 pub mod states;
 pub mod cards;
+pub mod dealer;
+pub mod server;
+pub mod history;
+pub mod replay;
+pub mod gamelog;
+pub mod protocol;
+pub mod transport;
+pub mod clock;
 mod thread_pool;
\ No newline at end of file
@@ -1,7 +1,10 @@
 use bitflags::bitflags;
+#[cfg(feature = "serde")]
+use serde::{Serialize, Deserialize};
 
 bitflags! {
     #[derive(Default)]
+    #[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
     pub struct ActionType: u16 {
         const FOLD = (1 << 0);
         const CALL = (1 << 1);
@@ -11,6 +14,7 @@ bitflags! {
 }
 
 #[derive(Debug, Copy, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub enum Action {
     Fold, Call, Check, Raise(u32)
 }
@@ -1,8 +1,63 @@
 use super::actions::Action;
+#[cfg(test)]
+use super::actions::ActionType;
 use super::states::{GameState, RoundState, TerminalState};
+use super::clock::Deadline;
 
 pub trait PokerBot {
     fn handle_new_round(&mut self, gs: &GameState, rs: &RoundState, player_index: usize);
     fn handle_round_over(&mut self, gs: &GameState, ts: &TerminalState, player_index: usize);
     fn get_action(&mut self, gs: &GameState, rs: &RoundState, player_index: usize) -> Action;
+    /// Asks for an action that should be ready by `deadline`. Bots that have expensive estimates to make can
+    /// override this to make them cheaper when time is short.
+    fn get_action_within(&mut self, gs: &GameState, rs: &RoundState, player_index: usize, deadline: &Deadline) -> Action {
+        self.get_action(gs, rs, player_index)
+    }
+}
+
+impl<B: PokerBot + ?Sized> PokerBot for Box<B> {
+    fn handle_new_round(&mut self, gs: &GameState, rs: &RoundState, player_index: usize) {
+        (**self).handle_new_round(gs, rs, player_index)
+    }
+
+    fn handle_round_over(&mut self, gs: &GameState, ts: &TerminalState, player_index: usize) {
+        (**self).handle_round_over(gs, ts, player_index)
+    }
+
+    fn get_action(&mut self, gs: &GameState, rs: &RoundState, player_index: usize) -> Action {
+        (**self).get_action(gs, rs, player_index)
+    }
+
+    fn get_action_within(&mut self, gs: &GameState, rs: &RoundState, player_index: usize, deadline: &Deadline) -> Action {
+        (**self).get_action_within(gs, rs, player_index, deadline)
+    }
+}
+
+/// Checks or calls everything, so plenty of rounds go to showdown, and remembers what it was told and asked
+#[cfg(test)]
+#[derive(Debug, Default)]
+pub(super) struct CallingStation {
+    /// Round number and player index of every round we were dealt into
+    pub(super) rounds: Vec<(u32, usize)>,
+    /// Round number, street and player index of every action we were asked for
+    pub(super) decisions: Vec<(u32, u32, usize)>,
+    pub(super) deltas: Vec<i32>,
+}
+
+#[cfg(test)]
+impl PokerBot for CallingStation {
+    fn handle_new_round(&mut self, gs: &GameState, rs: &RoundState, player_index: usize) {
+        // Nobody gets to see the opponent's hand before the round is over
+        assert!(rs.hands[player_index].is_some() && rs.hands[1 - player_index].is_none());
+        self.rounds.push((gs.round_num, player_index));
+    }
+
+    fn handle_round_over(&mut self, _: &GameState, ts: &TerminalState, player_index: usize) {
+        self.deltas.push(ts.deltas[player_index]);
+    }
+
+    fn get_action(&mut self, gs: &GameState, rs: &RoundState, player_index: usize) -> Action {
+        self.decisions.push((gs.round_num, rs.street, player_index));
+        if (rs.legal_actions() & ActionType::CHECK) == ActionType::CHECK { Action::Check } else { Action::Call }
+    }
 }
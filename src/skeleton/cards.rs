@@ -1,14 +1,18 @@
 //! Conversion utilities for cards to and from standard format strings
-
-// TODO Maybe implement serde?
+//!
+//! With the `serde` feature, a card serializes as its format string ("Ah") in human-readable formats like JSON,
+//! and as a single byte in binary formats.
 
 use std::fmt;
 use std::str::FromStr;
 use std::error::Error;
 use itertools::Itertools;
+#[cfg(feature = "serde")]
+use serde::{Serialize, Serializer, Deserialize, Deserializer, de};
 
 /// Encodes card suit
 #[derive(Debug, Copy, Clone, Hash, Eq, PartialEq, PartialOrd, Ord)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub enum CardSuit {
     Spades,
     Hearts,
@@ -51,6 +55,7 @@ impl fmt::Display for CardSuit {
 
 /// Encodes card value
 #[derive(Debug, Copy, Clone, Hash, Eq, PartialEq, PartialOrd, Ord)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub enum CardValue {
     Two,
     Three,
@@ -161,10 +166,56 @@ impl fmt::Display for Card {
     }
 }
 
+#[cfg(feature = "serde")]
+impl Serialize for Card {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        if serializer.is_human_readable() {
+            serializer.collect_str(self)
+        } else {
+            serializer.serialize_u8(self.value as u8 * 4 + self.suit as u8)
+        }
+    }
+}
+
+#[cfg(feature = "serde")]
+impl<'de> Deserialize<'de> for Card {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        if deserializer.is_human_readable() {
+            String::deserialize(deserializer)?.parse::<Card>().map_err(de::Error::custom)
+        } else {
+            let byte = u8::deserialize(deserializer)?;
+            let values = [
+                CardValue::Two, CardValue::Three, CardValue::Four, CardValue::Five, CardValue::Six,
+                CardValue::Seven, CardValue::Eight, CardValue::Nine, CardValue::Ten, CardValue::Jack,
+                CardValue::Queen, CardValue::King, CardValue::Ace
+            ];
+            let suits = [CardSuit::Spades, CardSuit::Hearts, CardSuit::Diamonds, CardSuit::Clubs];
+            match values.get(byte as usize / 4) {
+                Some(value) => Ok(Card::new(suits[byte as usize % 4], *value)),
+                None => Err(de::Error::custom(format!("Invalid card byte: {}", byte))),
+            }
+        }
+    }
+}
+
 /// Wraps a deck and makes it printable
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub struct CardDeck(pub Vec<Card>);
 
+impl CardDeck {
+    /// Builds an unshuffled deck of all 52 cards
+    pub fn full() -> CardDeck {
+        let suits = [CardSuit::Spades, CardSuit::Hearts, CardSuit::Diamonds, CardSuit::Clubs];
+        let values = [
+            CardValue::Two, CardValue::Three, CardValue::Four, CardValue::Five, CardValue::Six,
+            CardValue::Seven, CardValue::Eight, CardValue::Nine, CardValue::Ten, CardValue::Jack,
+            CardValue::Queen, CardValue::King, CardValue::Ace
+        ];
+        CardDeck(suits.iter().flat_map(|suit| values.iter().map(move |value| Card::new(*suit, *value))).collect())
+    }
+}
+
 impl fmt::Display for CardDeck {
     fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
         if self.0.is_empty() {
@@ -176,7 +227,8 @@ impl fmt::Display for CardDeck {
 }
 
 /// Wraps a hand and makes it printable
-#[derive(Debug, Clone, Copy)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub struct CardHand(pub [Card; 2]);
 
 impl fmt::Display for CardHand {
@@ -204,7 +256,7 @@ macro_rules! into_cards {
 }
 
 /// Describes various errors that can occur in conversion to Card{Suit, Hand} from strings
-#[derive(Debug)]
+#[derive(Debug, PartialEq)]
 pub enum CardConversionError {
     InvalidSuit(char),
     InvalidValue(char),
@@ -227,4 +279,20 @@ impl fmt::Display for CardConversionError {
     }
 }
 
+#[cfg(feature = "serde")]
+#[test]
+fn card_serde_test() {
+    let deck = CardDeck::full();
+    let json = serde_json::to_string(&deck).unwrap();
+    assert!(json.starts_with(r#"["2s","3s","4s""#));
+    let bytes = bincode::serialize(&deck).unwrap();
+    // A length, then one byte per card
+    assert_eq!(bytes.len(), 8 + 52);
+    for deck in vec![serde_json::from_str::<CardDeck>(&json).unwrap(), bincode::deserialize::<CardDeck>(&bytes).unwrap()] {
+        assert_eq!(deck.0, CardDeck::full().0);
+    }
+    assert!(serde_json::from_str::<Card>(r#""Xh""#).is_err());
+    assert!(bincode::deserialize::<Card>(&[52]).is_err());
+}
+
 
@@ -0,0 +1,198 @@
+//! Managing the game clock. The engine only gives us `STARTING_GAME_CLOCK` seconds of thinking for the whole match,
+//! and only counts the time between sending us a line and getting our answer. Running out of it forfeits every round
+//! left, so what is left of the clock is split evenly over the rounds still to play, and every decision (and the
+//! learning at the end of the round) gets a `Deadline` out of its round's share. Bots can look at how much they got,
+//! and do less work when time runs short.
+
+use super::states::{NUM_ROUNDS, STARTING_GAME_CLOCK};
+use std::time::{Duration, Instant};
+
+/// Seconds of the clock we never hand out, so the Runner and the connection always have some time to work with
+pub const RESERVE: f32 = 1.0;
+// Decisions we budget for in a round, one for every street
+const DECISIONS_PER_ROUND: f32 = 4.0;
+// Share of a round's time kept for learning from how it ended, with the rest going to its decisions
+const LEARNING_SHARE: f32 = 0.5;
+// Below these shares of an even split of the whole clock, time is short or critical
+const SHORT_SHARE: f32 = 0.5;
+const CRITICAL_SHARE: f32 = 0.05;
+
+/// How much time a decision has compared to what an even split of the whole clock gives it
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum TimePressure {
+    /// At least half of the usual budget, so there is no need to cut corners
+    Relaxed,
+    /// Less than half of it, so expensive estimates should be made cheaper
+    Short,
+    /// Barely anything left, so only the cheapest policy is safe
+    Critical,
+}
+
+/// How long a decision may take
+#[derive(Debug, Clone, Copy)]
+pub struct Deadline {
+    start: Instant,
+    budget: Duration,
+    share: f32,
+}
+
+impl Deadline {
+    /// The share of `clock` seconds one decision in round `round_num` gets
+    pub fn new(clock: f32, round_num: u32) -> Deadline {
+        let split = (1.0 - LEARNING_SHARE) / DECISIONS_PER_ROUND;
+        Deadline::out_of(round_budget(clock, round_num) * split, round_budget(STARTING_GAME_CLOCK, 1) * split)
+    }
+
+    /// The share of `clock` seconds learning from how round `round_num` ended gets
+    pub fn learning(clock: f32, round_num: u32) -> Deadline {
+        Deadline::out_of(round_budget(clock, round_num) * LEARNING_SHARE, round_budget(STARTING_GAME_CLOCK, 1) * LEARNING_SHARE)
+    }
+
+    fn out_of(budget: f32, nominal: f32) -> Deadline {
+        Deadline {
+            start: Instant::now(),
+            budget: Duration::from_secs_f32(budget),
+            share: budget / nominal,
+        }
+    }
+
+    pub fn budget(&self) -> Duration {
+        self.budget
+    }
+
+    /// How much of the budget is left
+    pub fn remaining(&self) -> Duration {
+        self.budget.checked_sub(self.start.elapsed()).unwrap_or_default()
+    }
+
+    pub fn expired(&self) -> bool {
+        self.start.elapsed() >= self.budget
+    }
+
+    /// The budget compared to an even split of the whole clock. Above 1 when earlier decisions were quick.
+    pub fn share(&self) -> f32 {
+        self.share
+    }
+
+    pub fn pressure(&self) -> TimePressure {
+        if self.share >= SHORT_SHARE {
+            TimePressure::Relaxed
+        } else if self.share >= CRITICAL_SHARE {
+            TimePressure::Short
+        } else {
+            TimePressure::Critical
+        }
+    }
+
+    /// Scales down the amount of work (like a sample count) `full` is with an even split of the clock, to fit
+    /// this budget. Never scales up, and never goes below 1.
+    pub fn scale(&self, full: u32) -> u32 {
+        ((full as f32 * self.share.min(1.0)).round() as u32).max(1)
+    }
+}
+
+/// Seconds of `clock` round `round_num` gets, which is an even split of everything but the reserve over the rounds
+/// still to play (this one included)
+pub fn round_budget(clock: f32, round_num: u32) -> f32 {
+    let rounds_left = (NUM_ROUNDS + 1).saturating_sub(round_num).max(1);
+    (clock - RESERVE).max(0.0) / rounds_left as f32
+}
+
+/// Keeps track of our game clock between the engine's updates. The engine stops our clock when we answer it, and
+/// starts it again with every line it sends us, so while we owe it an answer we can count it down ourselves.
+#[derive(Debug, Clone, Copy)]
+pub struct TimeBank {
+    clock: f32,
+    // When the engine last sent us something we haven't answered
+    waiting_since: Option<Instant>,
+}
+
+impl TimeBank {
+    pub fn new() -> TimeBank {
+        TimeBank {
+            clock: STARTING_GAME_CLOCK,
+            waiting_since: None,
+        }
+    }
+
+    /// The engine told us how much time we have left, and is waiting on our answer
+    pub fn update(&mut self, clock: f32) {
+        self.clock = clock;
+        self.waiting_since = Some(Instant::now());
+    }
+
+    /// The engine got our answer, so our clock stopped
+    pub fn answered(&mut self) {
+        if let Some(since) = self.waiting_since.take() {
+            self.clock -= since.elapsed().as_secs_f32();
+        }
+    }
+
+    /// Seconds we have left, as far as we can tell
+    pub fn left(&self) -> f32 {
+        let elapsed = self.waiting_since.map(|since| since.elapsed().as_secs_f32()).unwrap_or(0.0);
+        (self.clock - elapsed).max(0.0)
+    }
+
+    /// Whether we kept the engine waiting for longer than we had, so it has given up on us
+    pub fn is_spent(&self) -> bool {
+        self.waiting_since.is_some() && self.left() <= 0.0
+    }
+
+    /// A deadline for a decision in round `round_num`, out of what we have left right now
+    pub fn deadline(&self, round_num: u32) -> Deadline {
+        Deadline::new(self.left(), round_num)
+    }
+}
+
+impl Default for TimeBank {
+    fn default() -> TimeBank {
+        TimeBank::new()
+    }
+}
+
+#[test]
+fn clock_test() {
+    // Every round gets the same share of a clock nobody has touched
+    assert!((round_budget(STARTING_GAME_CLOCK, 1) - round_budget(STARTING_GAME_CLOCK - 0.029, 2)).abs() < 1e-6);
+    assert_eq!(round_budget(RESERVE / 2.0, 1), 0.0);
+    assert_eq!(round_budget(10.0 + RESERVE, NUM_ROUNDS), 10.0);
+    assert_eq!(round_budget(10.0 + RESERVE, NUM_ROUNDS + 5), 10.0);
+
+    let deadline = Deadline::new(STARTING_GAME_CLOCK, 1);
+    assert!((deadline.share() - 1.0).abs() < 1e-6);
+    assert_eq!(deadline.pressure(), TimePressure::Relaxed);
+    assert_eq!(deadline.scale(400), 400);
+    // Time we saved early on is there to spend later, but we never do more than the full amount of work
+    let deadline = Deadline::new(STARTING_GAME_CLOCK, NUM_ROUNDS / 2);
+    assert!(deadline.share() > 1.9);
+    assert_eq!(deadline.scale(400), 400);
+    // Halfway through with an eighth of the clock left
+    let deadline = Deadline::new((STARTING_GAME_CLOCK - RESERVE) / 8.0 + RESERVE, NUM_ROUNDS / 2 + 1);
+    assert_eq!(deadline.pressure(), TimePressure::Short);
+    assert_eq!(deadline.scale(400), 100);
+    // Learning gets as much time as all of the round's decisions together
+    let learning = Deadline::learning(STARTING_GAME_CLOCK, 1);
+    assert!((learning.share() - 1.0).abs() < 1e-6);
+    let decision = Deadline::new(STARTING_GAME_CLOCK, 1);
+    assert!((learning.budget().as_secs_f32() - decision.budget().as_secs_f32() * DECISIONS_PER_ROUND).abs() < 1e-6);
+    let deadline = Deadline::new(RESERVE, 10);
+    assert_eq!(deadline.pressure(), TimePressure::Critical);
+    assert_eq!(deadline.scale(400), 1);
+    assert!(deadline.expired());
+    assert_eq!(deadline.remaining(), Duration::from_secs(0));
+
+    let mut bank = TimeBank::new();
+    assert_eq!(bank.left(), STARTING_GAME_CLOCK);
+    assert!(!bank.is_spent());
+    bank.update(0.0);
+    assert!(bank.is_spent());
+    bank.update(12.5);
+    assert!(bank.left() <= 12.5 && !bank.is_spent());
+    bank.answered();
+    let left = bank.left();
+    assert!(left <= 12.5 && left > 12.0);
+    // Our clock doesn't run while the engine is the one thinking
+    assert_eq!(bank.left(), left);
+    assert!(!bank.is_spent());
+}
@@ -0,0 +1,285 @@
+//! An in-process dealer, so two PokerBots (or any other Seat) can play a match without the engine server
+
+use super::bot::PokerBot;
+use super::actions::{Action, ActionType};
+use super::states::{SMALL_BLIND, BIG_BLIND, STARTING_STACK, STARTING_GAME_CLOCK, GameState, RoundState, TerminalState, StateResult};
+use super::cards::{Card, CardDeck, CardHand, CardValue};
+use super::clock::Deadline;
+use crate::engine::showdown::ShowdownEngine;
+use crate::{debug_println, into_ordering};
+use rand::{prelude::*, rngs::StdRng};
+use std::time::Instant;
+#[cfg(feature = "debug_print")]
+use itertools::Itertools;
+
+// Same geometric parameter the engine uses when it permutes the card values
+pub const PERMUTATION_PROBABILITY: f64 = 0.25;
+
+/// Anything that can sit at the dealer's table. Every PokerBot is a Seat, and the server
+/// implements it for bots connected over a socket.
+///
+/// The dealer only asks a seat for input (`get_action` and `handle_round_over`) while it still
+/// has time on its game clock, but always tells it what happened.
+pub trait Seat {
+    /// A new round was dealt. `rs` only shows this seat's own hand.
+    fn handle_new_round(&mut self, gs: &GameState, rs: &RoundState, player_index: usize);
+    /// The round is over. The opponent's hand is only visible if the round went to showdown.
+    fn handle_round_over(&mut self, gs: &GameState, ts: &TerminalState, player_index: usize);
+    fn get_action(&mut self, gs: &GameState, rs: &RoundState, player_index: usize) -> Action;
+    /// An action (already made legal) was taken by either seat
+    fn observe_action(&mut self, _action: Action) {}
+    /// New board cards were turned
+    fn observe_board(&mut self, _board: &CardDeck) {}
+    /// The match is over
+    fn handle_match_over(&mut self) {}
+    /// Whether the seat can still respond. A seat that can't gets its game clock zeroed.
+    fn is_responsive(&self) -> bool { true }
+}
+
+impl<B: PokerBot + ?Sized> Seat for B {
+    fn handle_new_round(&mut self, gs: &GameState, rs: &RoundState, player_index: usize) {
+        PokerBot::handle_new_round(self, gs, rs, player_index)
+    }
+
+    fn handle_round_over(&mut self, gs: &GameState, ts: &TerminalState, player_index: usize) {
+        PokerBot::handle_round_over(self, gs, ts, player_index)
+    }
+
+    fn get_action(&mut self, gs: &GameState, rs: &RoundState, player_index: usize) -> Action {
+        PokerBot::get_action_within(self, gs, rs, player_index, &Deadline::new(gs.game_clock, gs.round_num))
+    }
+}
+
+/// Final standings of a local match. Everything is indexed by bot, not by seat.
+#[derive(Debug, Clone, Copy)]
+pub struct MatchResult {
+    pub seed: u64,
+    pub rounds: u32,
+    pub ordering: [CardValue; 13],
+    pub bankrolls: [i64; 2],
+    pub game_clocks: [f32; 2],
+}
+
+/// Deals and settles rounds between two bots, playing the role of the engine.
+/// Everything random (value permutation, shuffles) comes from the seed, so a match can be replayed
+/// as long as the bots themselves are deterministic.
+pub struct Dealer {
+    seed: u64,
+    rng: StdRng,
+    ordering: [CardValue; 13],
+}
+
+impl Dealer {
+    pub fn new(seed: u64) -> Dealer {
+        let mut rng = StdRng::seed_from_u64(seed);
+        let ordering = permute_values(&mut rng);
+        debug_println!("[Dealer] Secret ordering is {}", ordering.iter().format(" -> "));
+        Dealer {
+            seed,
+            rng,
+            ordering,
+        }
+    }
+
+    pub fn seed(&self) -> u64 {
+        self.seed
+    }
+
+    /// The secret value ordering (lowest to highest) used for every showdown
+    pub fn ordering(&self) -> [CardValue; 13] {
+        self.ordering
+    }
+
+    /// Plays `rounds` rounds between the seats, swapping the button every round like the engine does
+    pub fn play_match(&mut self, first: &mut dyn Seat, second: &mut dyn Seat, rounds: u32) -> MatchResult {
+        let mut games = [GameState { bankroll: 0, game_clock: STARTING_GAME_CLOCK, round_num: 1 }; 2];
+        for round_num in 1..=rounds {
+            // The first bot is the small blind on odd rounds
+            if round_num % 2 == 1 {
+                self.play_round([&mut *first, &mut *second], &mut games);
+            } else {
+                games.swap(0, 1);
+                self.play_round([&mut *second, &mut *first], &mut games);
+                games.swap(0, 1);
+            }
+            debug_println!("[Dealer] Round #{} over, bankrolls [{}, {}]", round_num, games[0].bankroll, games[1].bankroll);
+        }
+        first.handle_match_over();
+        second.handle_match_over();
+        MatchResult {
+            seed: self.seed,
+            rounds,
+            ordering: self.ordering,
+            bankrolls: [games[0].bankroll, games[1].bankroll],
+            game_clocks: [games[0].game_clock, games[1].game_clock],
+        }
+    }
+
+    /// Plays a single round. Seat 0 is the small blind.
+    fn play_round(&mut self, mut seats: [&mut (dyn Seat + '_); 2], games: &mut [GameState; 2]) -> TerminalState {
+        let mut deck = CardDeck::full().0;
+        deck.shuffle(&mut self.rng);
+        let hands = [CardHand([deck[0], deck[1]]), CardHand([deck[2], deck[3]])];
+        let board = &deck[4..9];
+
+        let mut round_state = RoundState {
+            button: 0,
+            street: 0,
+            pips: [SMALL_BLIND, BIG_BLIND],
+            stacks: [STARTING_STACK - SMALL_BLIND, STARTING_STACK - BIG_BLIND],
+            hands: [Some(hands[0]), Some(hands[1])],
+            deck: CardDeck(vec![]),
+            previous: None,
+        };
+
+        // The engine counts the time from sending the new round until it hears back, like for any other clause
+        for (seat, bot) in seats.iter_mut().enumerate() {
+            let start = Instant::now();
+            bot.handle_new_round(&games[seat], &view(&round_state, seat, false), seat);
+            charge(&**bot, &mut games[seat], start);
+        }
+
+        let (terminal_state, last_action) = loop {
+            let active = round_state.button as usize % 2;
+            let action = query(&mut *seats[active], &mut games[active], &round_state, active);
+            for bot in seats.iter_mut() {
+                bot.observe_action(action);
+            }
+            match round_state.proceed(action) {
+                StateResult::Round(next) => {
+                    let street = next.deck.0.len();
+                    round_state = deal_street(next, board);
+                    if round_state.deck.0.len() != street {
+                        for bot in seats.iter_mut() {
+                            bot.observe_board(&round_state.deck);
+                        }
+                    }
+                },
+                StateResult::Terminal(terminal) => break (terminal, action),
+            }
+        };
+
+        // A round that didn't end with a fold made it to showdown
+        let showdown = last_action != Action::Fold;
+        let terminal_state = if showdown {
+            let settled = terminal_state.previous.showdown(&ShowdownEngine::new(self.ordering));
+            debug_println!("[Dealer] Showdown {} vs {} gives deltas {:?}", hands[0], hands[1], settled.deltas);
+            settled
+        } else {
+            terminal_state
+        };
+
+        for (seat, bot) in seats.iter_mut().enumerate() {
+            games[seat].bankroll += terminal_state.deltas[seat] as i64;
+            let seen = TerminalState {
+                deltas: terminal_state.deltas,
+                previous: view(&terminal_state.previous, seat, showdown),
+            };
+            if games[seat].game_clock > 0.0 {
+                let start = Instant::now();
+                bot.handle_round_over(&games[seat], &seen, seat);
+                charge(&**bot, &mut games[seat], start);
+            }
+            games[seat].round_num += 1;
+        }
+
+        terminal_state
+    }
+}
+
+/// Draws a value ordering (lowest to highest) the same way the engine does:
+/// each slot takes a geometrically distributed pick from the values that are still left.
+pub fn permute_values<R: Rng>(rng: &mut R) -> [CardValue; 13] {
+    let mut remaining = into_ordering!("2,3,4,5,6,7,8,9,T,J,Q,K,A").to_vec();
+    let mut ordering = [CardValue::Two; 13];
+    for slot in ordering.iter_mut() {
+        let mut index = 0;
+        while !rng.gen_bool(PERMUTATION_PROBABILITY) {
+            index += 1;
+        }
+        *slot = remaining.remove(index % remaining.len());
+    }
+    ordering
+}
+
+// Asks a seat for an action, charging it for the time it took, and replaces illegal actions
+// the same way the engine does (check if possible, otherwise fold).
+// A seat that is out of time isn't asked at all and is treated as having sent an illegal action.
+fn query(bot: &mut dyn Seat, game: &mut GameState, round_state: &RoundState, seat: usize) -> Action {
+    let action = if game.game_clock > 0.0 {
+        let start = Instant::now();
+        let action = bot.get_action(game, &view(round_state, seat, false), seat);
+        charge(bot, game, start);
+        action
+    } else {
+        Action::Fold
+    };
+
+    if round_state.is_legal(action) {
+        action
+    } else {
+        debug_println!("[Dealer] Seat {} attempted illegal {:?}", seat, action);
+        if (round_state.legal_actions() & ActionType::CHECK) == ActionType::CHECK {
+            Action::Check
+        } else {
+            Action::Fold
+        }
+    }
+}
+
+// Takes the time since `start` off a seat's game clock, or all of it if the seat stopped responding
+fn charge(bot: &dyn Seat, game: &mut GameState, start: Instant) {
+    game.game_clock -= start.elapsed().as_secs_f32();
+    if !bot.is_responsive() || game.game_clock < 0.0 {
+        game.game_clock = 0.0;
+    }
+}
+
+// A new street always starts with the big blind to act and nothing in front of anyone
+// (preflop can never look like this because of the blinds), so that is when we turn cards.
+fn deal_street(round_state: RoundState, board: &[Card]) -> RoundState {
+    if round_state.button == 1 && round_state.pips == [0, 0] && round_state.deck.0.len() < board.len() {
+        let street = if round_state.deck.0.is_empty() { 3 } else { round_state.deck.0.len() + 1 };
+        RoundState {
+            button: round_state.button,
+            street: street as u32,
+            pips: round_state.pips,
+            stacks: round_state.stacks,
+            hands: round_state.hands,
+            deck: CardDeck(board[..street].to_vec()),
+            previous: round_state.previous,
+        }
+    } else {
+        round_state
+    }
+}
+
+// What a seat is allowed to see of the round. The opponent's hand is only shown
+// on the final state of a round that went to showdown, just like the O clause does.
+fn view(round_state: &RoundState, seat: usize, reveal: bool) -> RoundState {
+    let mut hands = round_state.hands;
+    if !reveal {
+        hands[1 - seat] = None;
+    }
+    RoundState {
+        button: round_state.button,
+        street: round_state.street,
+        pips: round_state.pips,
+        stacks: round_state.stacks,
+        hands,
+        deck: round_state.deck.clone(),
+        previous: round_state.previous.as_ref().map(|previous| Box::new(view(previous, seat, false))),
+    }
+}
+
+#[test]
+fn dealer_replay_test() {
+    use super::bot::CallingStation;
+    let (mut a, mut b) = (CallingStation::default(), CallingStation::default());
+    let first = Dealer::new(176).play_match(&mut a, &mut b, 200);
+    let second = Dealer::new(176).play_match(&mut a, &mut b, 200);
+    // Chips only ever move between the two bots
+    assert_eq!(first.bankrolls[0], -first.bankrolls[1]);
+    assert_eq!(first.bankrolls, second.bankrolls);
+    assert_eq!(first.ordering, second.ordering);
+}
@@ -0,0 +1,487 @@
+//! Reads the game logs the engine writes for every match, and rebuilds every round as a RoundState chain.
+//!
+//! A game log starts with `6.176 MIT Pokerbots - <player> vs <player>`, and then every round looks like
+//!
+//! ```text
+//! Round #2, B (-2), A (2)
+//! B posts the blind of 1
+//! A posts the blind of 2
+//! B dealt [Ah Kd]
+//! A dealt [2c 7d]
+//! B calls
+//! A checks
+//! Flop [2c 5d 9h], B (2), A (2)
+//! A bets 4
+//! B raises to 12
+//! A folds
+//! B awarded 6
+//! A awarded -6
+//! ```
+//!
+//! where the player named first in the `Round` line is the small blind. Rounds that make it to showdown have both
+//! players `shows` their hand before the awards, and the log ends with a `Final` line of bankrolls.
+
+use super::actions::Action;
+use super::cards::{Card, CardHand, CardDeck, CardConversionError};
+use super::history::{HandHistory, HistoryEvent};
+use super::states::{SMALL_BLIND, BIG_BLIND, STARTING_STACK, STARTING_GAME_CLOCK, RoundState, TerminalState, StateResult};
+use std::error::Error;
+use std::fmt;
+use std::io::{self, BufRead};
+use std::str::FromStr;
+
+/// Start of the first line of every game log
+pub const LOG_HEADER: &str = "6.176 MIT Pokerbots - ";
+
+/// One round of a game log
+#[derive(Debug)]
+pub struct LoggedRound {
+    pub round_num: u32,
+    /// Which player (by their place in the header) sat in each seat. Seat 0 is the small blind.
+    pub seats: [usize; 2],
+    /// Both seats' bankrolls before the round
+    pub bankrolls: [i64; 2],
+    /// How the round ended, with both hands and the awarded deltas (by seat)
+    pub terminal: TerminalState,
+    /// Whether the hands were shown
+    pub showdown: bool,
+    /// Every action and street, in order
+    pub events: Vec<HistoryEvent>,
+}
+
+impl LoggedRound {
+    /// The round as the player in `seat` saw it. Game logs don't record clocks, so the clock is always full.
+    pub fn history(&self, seat: usize) -> HandHistory {
+        let hands = self.terminal.previous.hands;
+        HandHistory {
+            round_num: self.round_num,
+            player_index: seat,
+            game_clock: STARTING_GAME_CLOCK,
+            hand: hands[seat].expect("logged rounds know both hands"),
+            events: self.events.clone(),
+            opponent_hand: if self.showdown { hands[1 - seat] } else { None },
+            delta: self.terminal.deltas[seat],
+        }
+    }
+}
+
+/// A whole match
+#[derive(Debug)]
+pub struct GameLog {
+    /// The players in the order the header names them
+    pub players: [String; 2],
+    pub rounds: Vec<LoggedRound>,
+    /// Bankrolls by player, if the match got to the end
+    pub final_bankrolls: Option<[i64; 2]>,
+}
+
+impl GameLog {
+    /// Reads a whole game log
+    pub fn parse<R: BufRead>(reader: R) -> Result<GameLog, GameLogError> {
+        let mut parser: Option<Parser> = None;
+        let mut last = 0;
+        for (index, line) in reader.lines().enumerate() {
+            last = index + 1;
+            let error = |kind| GameLogError { line: index + 1, kind };
+            let line = line.map_err(|e| error(GameLogErrorKind::Io(e)))?;
+            let line = line.trim();
+            match parser {
+                Some(ref mut parser) => parser.line(line).map_err(error)?,
+                None if line.is_empty() => {},
+                None => parser = Some(Parser::new(line).map_err(error)?),
+            }
+        }
+        let error = |kind| GameLogError { line: last, kind };
+        let parser = parser.ok_or_else(|| error(GameLogErrorKind::MissingHeader))?;
+        if let Some(round) = parser.round {
+            return Err(error(GameLogErrorKind::IncompleteRound(round.round_num)));
+        }
+        Ok(GameLog { players: parser.players, rounds: parser.rounds, final_bankrolls: parser.final_bankrolls })
+    }
+
+    /// Which player in the header has `name`
+    pub fn player(&self, name: &str) -> Option<usize> {
+        self.players.iter().position(|player| player == name)
+    }
+
+    /// Every round, as `player` saw it
+    pub fn histories(&self, player: usize) -> Vec<HandHistory> {
+        self.rounds.iter().map(|round| {
+            let seat = round.seats.iter().position(|p| *p == player).unwrap();
+            round.history(seat)
+        }).collect()
+    }
+}
+
+/// Describes what can be wrong with a game log
+#[derive(Debug)]
+pub enum GameLogErrorKind {
+    Io(io::Error),
+    MissingHeader,
+    UnknownLine(String),
+    UnknownPlayer(String),
+    InvalidNumber(String),
+    Card(CardConversionError),
+    /// A line that only makes sense during a round came between rounds
+    OutsideRound(String),
+    /// A line that only makes sense before or after the betting came in the middle of it
+    OutOfPlace(String),
+    OutOfTurn(String),
+    IllegalAction(Action),
+    MismatchedHand(CardHand),
+    MismatchedAward(i32),
+    IncompleteRound(u32),
+}
+
+/// An error in a game log, with the (1-based) line it was found on
+#[derive(Debug)]
+pub struct GameLogError {
+    pub line: usize,
+    pub kind: GameLogErrorKind,
+}
+
+impl Error for GameLogError {}
+
+impl fmt::Display for GameLogError {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        write!(fmt, "Line {}: ", self.line)?;
+        match self.kind {
+            GameLogErrorKind::Io(ref e) => write!(fmt, "Could not read log: {}", e),
+            GameLogErrorKind::MissingHeader => write!(fmt, "Not a game log, expected a line starting with {:?}", LOG_HEADER),
+            GameLogErrorKind::UnknownLine(ref line) => write!(fmt, "Unknown line: {}", line),
+            GameLogErrorKind::UnknownPlayer(ref name) => write!(fmt, "Unknown player: {}", name),
+            GameLogErrorKind::InvalidNumber(ref number) => write!(fmt, "Invalid number: {}", number),
+            GameLogErrorKind::Card(ref e) => write!(fmt, "Invalid card: {}", e),
+            GameLogErrorKind::OutsideRound(ref line) => write!(fmt, "Outside of a round: {}", line),
+            GameLogErrorKind::OutOfPlace(ref line) => write!(fmt, "Out of place: {}", line),
+            GameLogErrorKind::OutOfTurn(ref line) => write!(fmt, "Player acted out of turn: {}", line),
+            GameLogErrorKind::IllegalAction(action) => write!(fmt, "Illegal action: {:?}", action),
+            GameLogErrorKind::MismatchedHand(hand) => write!(fmt, "Shown hand {} is not the one dealt", hand),
+            GameLogErrorKind::MismatchedAward(delta) => write!(fmt, "Award of {} doesn't match the round", delta),
+            GameLogErrorKind::IncompleteRound(round_num) => write!(fmt, "Round #{} never ended", round_num),
+        }
+    }
+}
+
+impl From<CardConversionError> for GameLogErrorKind {
+    fn from(e: CardConversionError) -> GameLogErrorKind {
+        GameLogErrorKind::Card(e)
+    }
+}
+
+fn parse_number<T: FromStr>(s: &str) -> Result<T, GameLogErrorKind> {
+    s.trim().parse::<T>().map_err(|_| GameLogErrorKind::InvalidNumber(s.to_string()))
+}
+
+// Cards are logged like [Ah Kd]
+fn parse_cards(s: &str) -> Result<Vec<Card>, GameLogErrorKind> {
+    let s = s.trim();
+    if !s.starts_with('[') || !s.ends_with(']') {
+        return Err(GameLogErrorKind::UnknownLine(s.to_string()));
+    }
+    Ok(s[1..s.len() - 1].split_whitespace().map(|card| card.parse::<Card>()).collect::<Result<Vec<_>, _>>()?)
+}
+
+fn parse_hand(s: &str) -> Result<CardHand, GameLogErrorKind> {
+    match parse_cards(s)?.as_slice() {
+        [first, second] => Ok(CardHand([*first, *second])),
+        _ => Err(GameLogErrorKind::UnknownLine(s.to_string())),
+    }
+}
+
+// The round being read, until both players have been awarded their deltas
+struct RoundBuilder {
+    round_num: u32,
+    seats: [usize; 2],
+    bankrolls: [i64; 2],
+    hands: [Option<CardHand>; 2],
+    state: Option<RoundState>,
+    terminal: Option<TerminalState>,
+    events: Vec<HistoryEvent>,
+    shown: bool,
+    awarded: [Option<i32>; 2],
+}
+
+struct Parser {
+    players: [String; 2],
+    rounds: Vec<LoggedRound>,
+    round: Option<RoundBuilder>,
+    final_bankrolls: Option<[i64; 2]>,
+}
+
+impl Parser {
+    fn new(header: &str) -> Result<Parser, GameLogErrorKind> {
+        if !header.starts_with(LOG_HEADER) {
+            return Err(GameLogErrorKind::MissingHeader);
+        }
+        let mut names = header[LOG_HEADER.len()..].splitn(2, " vs ");
+        match (names.next(), names.next()) {
+            (Some(first), Some(second)) => Ok(Parser {
+                players: [first.trim().to_string(), second.trim().to_string()],
+                rounds: vec![],
+                round: None,
+                final_bankrolls: None,
+            }),
+            _ => Err(GameLogErrorKind::MissingHeader),
+        }
+    }
+
+    // Who a line is about, and the rest of it. The longest name wins, in case one name starts with the other.
+    fn player<'a>(&self, line: &'a str) -> Option<(usize, &'a str)> {
+        (0..2).filter(|player| {
+            let name = &self.players[*player];
+            line.starts_with(name.as_str()) && line[name.len()..].starts_with(' ')
+        }).max_by_key(|player| self.players[*player].len())
+            .map(|player| (player, line[self.players[player].len()..].trim()))
+    }
+
+    // Reads statuses like `A (12), B (-12)` into the players they name and their values, in order
+    fn statuses(&self, s: &str) -> Result<[(usize, i64); 2], GameLogErrorKind> {
+        let mut statuses = [(0, 0); 2];
+        let mut parts = s.split(", ");
+        for status in statuses.iter_mut() {
+            let part = parts.next().ok_or_else(|| GameLogErrorKind::UnknownLine(s.to_string()))?;
+            let open = part.rfind(" (").filter(|_| part.ends_with(')')).ok_or_else(|| GameLogErrorKind::UnknownLine(s.to_string()))?;
+            let name = &part[..open];
+            let player = self.players.iter().position(|player| player == name).ok_or_else(|| GameLogErrorKind::UnknownPlayer(name.to_string()))?;
+            *status = (player, parse_number(&part[open + 2..part.len() - 1])?);
+        }
+        if parts.next().is_some() || statuses[0].0 == statuses[1].0 {
+            return Err(GameLogErrorKind::UnknownLine(s.to_string()));
+        }
+        Ok(statuses)
+    }
+
+    fn line(&mut self, line: &str) -> Result<(), GameLogErrorKind> {
+        if line.is_empty() {
+            return Ok(());
+        }
+        if let Some(header) = line.strip_prefix("Round #") {
+            if let Some(ref round) = self.round {
+                return Err(GameLogErrorKind::IncompleteRound(round.round_num));
+            }
+            let mut parts = header.splitn(2, ", ");
+            let round_num = parse_number(parts.next().unwrap())?;
+            let statuses = self.statuses(parts.next().ok_or_else(|| GameLogErrorKind::UnknownLine(line.to_string()))?)?;
+            self.round = Some(RoundBuilder {
+                round_num,
+                seats: [statuses[0].0, statuses[1].0],
+                bankrolls: [statuses[0].1, statuses[1].1],
+                hands: [None, None],
+                state: None,
+                terminal: None,
+                events: vec![],
+                shown: false,
+                awarded: [None, None],
+            });
+            return Ok(());
+        }
+        if let Some(statuses) = line.strip_prefix("Final, ") {
+            if let Some(ref round) = self.round {
+                return Err(GameLogErrorKind::IncompleteRound(round.round_num));
+            }
+            let statuses = self.statuses(statuses)?;
+            let mut bankrolls = [0; 2];
+            for (player, bankroll) in statuses.iter() {
+                bankrolls[*player] = *bankroll;
+            }
+            self.final_bankrolls = Some(bankrolls);
+            return Ok(());
+        }
+        for (street, name) in ["Flop ", "Turn ", "River "].iter().enumerate() {
+            if line.starts_with(name) {
+                let round = self.round.as_mut().ok_or_else(|| GameLogErrorKind::OutsideRound(line.to_string()))?;
+                let end = line.find(']').ok_or_else(|| GameLogErrorKind::UnknownLine(line.to_string()))?;
+                let board = parse_cards(&line[name.len()..=end])?;
+                if board.len() != street + 3 {
+                    return Err(GameLogErrorKind::UnknownLine(line.to_string()));
+                }
+                // The engine turns the cards once the street before is over, just like it sends the B clause
+                let rs = round.state.take().ok_or_else(|| GameLogErrorKind::OutOfPlace(line.to_string()))?;
+                let deck = CardDeck(board);
+                round.events.push(HistoryEvent::Board(deck.clone()));
+                round.state = Some(RoundState {
+                    button: rs.button,
+                    street: deck.0.len() as u32,
+                    pips: rs.pips,
+                    stacks: rs.stacks,
+                    hands: rs.hands,
+                    deck,
+                    previous: rs.previous,
+                });
+                return Ok(());
+            }
+        }
+
+        let (player, rest) = self.player(line).ok_or_else(|| GameLogErrorKind::UnknownLine(line.to_string()))?;
+        let round = self.round.as_mut().ok_or_else(|| GameLogErrorKind::OutsideRound(line.to_string()))?;
+        let seat = round.seats.iter().position(|p| *p == player).unwrap();
+        let action = if rest == "folds" {
+            Action::Fold
+        } else if rest == "calls" {
+            Action::Call
+        } else if rest == "checks" {
+            Action::Check
+        } else if let Some(amount) = rest.strip_prefix("bets ") {
+            Action::Raise(parse_number(amount)?)
+        } else if let Some(amount) = rest.strip_prefix("raises to ") {
+            Action::Raise(parse_number(amount)?)
+        } else if let Some(blind) = rest.strip_prefix("posts the blind of ") {
+            let blind: u32 = parse_number(blind)?;
+            if round.state.is_some() || blind != [SMALL_BLIND, BIG_BLIND][seat] {
+                return Err(GameLogErrorKind::OutOfPlace(line.to_string()));
+            }
+            return Ok(());
+        } else if let Some(hand) = rest.strip_prefix("dealt ") {
+            if round.state.is_some() {
+                return Err(GameLogErrorKind::OutOfPlace(line.to_string()));
+            }
+            round.hands[seat] = Some(parse_hand(hand)?);
+            return Ok(());
+        } else if let Some(hand) = rest.strip_prefix("shows ") {
+            let hand = parse_hand(hand)?;
+            if round.terminal.is_none() {
+                return Err(GameLogErrorKind::OutOfPlace(line.to_string()));
+            }
+            if round.hands[seat].map(|dealt| dealt.0) != Some(hand.0) {
+                return Err(GameLogErrorKind::MismatchedHand(hand));
+            }
+            round.shown = true;
+            return Ok(());
+        } else if let Some(award) = rest.strip_prefix("awarded ") {
+            if round.terminal.is_none() {
+                return Err(GameLogErrorKind::OutOfPlace(line.to_string()));
+            }
+            round.awarded[seat] = Some(parse_number(award)?);
+            if let [Some(first), Some(second)] = round.awarded {
+                let round = self.round.take().unwrap();
+                let mut terminal = round.terminal.unwrap();
+                // Folds settle themselves, showdowns need the ordering, which only the engine knows
+                if first + second != 0 || (!round.shown && terminal.deltas != [first, second]) {
+                    return Err(GameLogErrorKind::MismatchedAward(first));
+                }
+                terminal.deltas = [first, second];
+                self.rounds.push(LoggedRound {
+                    round_num: round.round_num,
+                    seats: round.seats,
+                    bankrolls: round.bankrolls,
+                    terminal,
+                    showdown: round.shown,
+                    events: round.events,
+                });
+            }
+            return Ok(());
+        } else {
+            return Err(GameLogErrorKind::UnknownLine(line.to_string()));
+        };
+
+        // Betting starts once both hands are dealt
+        if round.state.is_none() && round.terminal.is_none() {
+            if round.hands.iter().any(|hand| hand.is_none()) {
+                return Err(GameLogErrorKind::OutOfPlace(line.to_string()));
+            }
+            round.state = Some(RoundState {
+                button: 0,
+                street: 0,
+                pips: [SMALL_BLIND, BIG_BLIND],
+                stacks: [STARTING_STACK - SMALL_BLIND, STARTING_STACK - BIG_BLIND],
+                hands: round.hands,
+                deck: CardDeck(vec![]),
+                previous: None,
+            });
+        }
+        let rs = round.state.take().ok_or_else(|| GameLogErrorKind::OutOfPlace(line.to_string()))?;
+        if rs.button as usize % 2 != seat {
+            return Err(GameLogErrorKind::OutOfTurn(line.to_string()));
+        }
+        if !rs.is_legal(action) {
+            return Err(GameLogErrorKind::IllegalAction(action));
+        }
+        let result = rs.proceed(action);
+        round.events.push(HistoryEvent::action(&rs, action, &result));
+        match result {
+            StateResult::Round(state) => round.state = Some(state),
+            StateResult::Terminal(terminal) => round.terminal = Some(terminal),
+        }
+        Ok(())
+    }
+}
+
+#[test]
+fn game_log_test() {
+    use crate::into_cards;
+    let log = "6.176 MIT Pokerbots - A vs B
+
+Round #1, A (0), B (0)
+A posts the blind of 1
+B posts the blind of 2
+A dealt [Ah Kd]
+B dealt [2c 7d]
+A folds
+A awarded -1
+B awarded 1
+
+Round #2, B (1), A (-1)
+B posts the blind of 1
+A posts the blind of 2
+B dealt [Ah Kd]
+A dealt [2c 7d]
+B calls
+A checks
+Flop [2c 5d 9h], B (2), A (2)
+A bets 4
+B raises to 12
+A calls
+Turn [2c 5d 9h Js], B (14), A (14)
+A checks
+B checks
+River [2c 5d 9h Js 3s], B (14), A (14)
+A checks
+B checks
+B shows [Ah Kd]
+A shows [2c 7d]
+B awarded -14
+A awarded 14
+
+Final, A (15), B (-15)
+";
+    let game = GameLog::parse(log.as_bytes()).unwrap();
+    assert_eq!(game.players, ["A".to_string(), "B".to_string()]);
+    assert_eq!(game.final_bankrolls, Some([15, -15]));
+    assert_eq!(game.rounds.len(), 2);
+    let (folded, shown) = (&game.rounds[0], &game.rounds[1]);
+    assert_eq!((folded.seats, folded.showdown, folded.terminal.deltas), ([0, 1], false, [-1, 1]));
+    assert_eq!((shown.seats, shown.bankrolls, shown.showdown, shown.terminal.deltas), ([1, 0], [1, -1], true, [-14, 14]));
+    // The chain goes all the way back to the blinds
+    let last = &shown.terminal.previous;
+    assert_eq!((last.street, last.stacks), (5, [186, 186]));
+    assert_eq!(last.deck.0, into_cards!("2c,5d,9h,Js,3s"));
+    let mut depth = 0;
+    let mut state = last;
+    while let Some(ref previous) = state.previous {
+        state = &**previous;
+        depth += 1;
+    }
+    assert_eq!((depth, state.pips, state.button), (9, [SMALL_BLIND, BIG_BLIND], 0));
+
+    // Player A was the big blind in the second round, and saw B's hand at showdown
+    let histories = game.histories(0);
+    assert_eq!(histories[0].to_string(), "round=1 player=0 clock=30 hand=Ah,Kd 0:F/1,2/199,198 opponent=- delta=-1");
+    assert_eq!(histories[1].player_index, 1);
+    assert_eq!(histories[1].opponent_hand.map(|hand| hand.0), Some([into_cards!("Ah")[0], into_cards!("Kd")[0]]));
+    assert_eq!(histories[1].boards().len(), 3);
+
+    // Errors point at the line they were found on
+    let error = |log: &str| GameLog::parse(log.as_bytes()).unwrap_err();
+    assert!(matches!(error("hello"), GameLogError { line: 1, kind: GameLogErrorKind::MissingHeader }));
+    let broken = log.replace("B raises to 12", "B raises to 2");
+    assert!(matches!(error(&broken), GameLogError { line: 21, kind: GameLogErrorKind::IllegalAction(Action::Raise(2)) }));
+    let broken = log.replace("A dealt [2c 7d]", "A dealt [2c 7x]");
+    assert!(matches!(error(&broken), GameLogError { line: 16, kind: GameLogErrorKind::Card(_) }));
+    let broken = log.replace("A checks\nB checks\nB shows", "B checks\nA checks\nB shows");
+    assert!(matches!(error(&broken), GameLogError { line: 27, kind: GameLogErrorKind::OutOfTurn(_) }));
+    let broken = log.replace("A awarded -1", "A awarded -2").replace("B awarded 1\n", "B awarded 2\n");
+    assert!(matches!(error(&broken), GameLogError { line: 10, kind: GameLogErrorKind::MismatchedAward(_) }));
+    assert!(matches!(error(&log[..log.find("B awarded -14").unwrap()]), GameLogError { kind: GameLogErrorKind::IncompleteRound(2), .. }));
+    assert_eq!(format!("{}", error("hello")), format!("Line 1: Not a game log, expected a line starting with {:?}", LOG_HEADER));
+}
@@ -0,0 +1,393 @@
+//! Structured hand histories, so what happened in a match can be reconstructed afterwards
+//!
+//! A history file is line-delimited. Lines starting with `#` are comments (the first line names the format), and
+//! every other line is one round, made of space-separated tokens in this order:
+//!
+//! - `round=<n>` the round number
+//! - `player=<0|1>` our player index
+//! - `clock=<seconds>` our game clock when the round was dealt
+//! - `hand=<card>,<card>` our hole cards
+//! - then everything that happened, in order:
+//!   - `<player>:<action>/<pip>,<pip>/<stack>,<stack>` an action (`F`, `C`, `K` or `R<amount>`, like the engine
+//!     sends them) with both players' pips and stacks right after it
+//!   - `board=<card>,...` the whole board, every time a street is dealt
+//! - `opponent=<card>,<card>` the opponent's hand if it was revealed, `opponent=-` otherwise
+//! - `delta=<chips>` what we won or lost
+//!
+//! For example
+//!
+//! ```text
+//! # tinyrasputin hand history v1
+//! round=3 player=1 clock=29.87 hand=Ah,Kd 0:C/2,2/198,198 1:R6/2,6/198,194 0:C/6,6/194,194 board=2c,5d,9h 1:K/0,0/194,194 0:K/0,0/194,194 board=2c,5d,9h,Js 1:K/0,0/194,194 0:R10/10,0/184,194 1:F/10,0/184,194 opponent=- delta=-6
+//! ```
+
+use super::actions::Action;
+use super::cards::{Card, CardDeck, CardHand, CardConversionError};
+use super::protocol::Clause;
+use super::states::{GameState, RoundState, TerminalState, StateResult};
+use std::error::Error;
+use std::fmt;
+use std::fs::File;
+use std::io::{self, BufRead, Write};
+use std::path::Path;
+use std::str::FromStr;
+use itertools::Itertools;
+
+/// First line of every history file
+pub const HISTORY_HEADER: &str = "# tinyrasputin hand history v1";
+/// How big a history file gets by default
+pub const DEFAULT_MAX_BYTES: usize = 524288;
+
+/// Something that happened during a round
+#[derive(Debug, Clone)]
+pub enum HistoryEvent {
+    /// An action, with both players' pips and stacks right after it
+    Action { player: usize, action: Action, pips: [u32; 2], stacks: [u32; 2] },
+    /// A street was dealt, and this is the board now
+    Board(CardDeck),
+}
+
+impl HistoryEvent {
+    /// The event for `action` being taken in `rs`, which led to `result`
+    pub fn action(rs: &RoundState, action: Action, result: &StateResult) -> HistoryEvent {
+        let stacks = match result {
+            StateResult::Round(state) => state.stacks,
+            StateResult::Terminal(state) => state.previous.stacks,
+        };
+        // Whatever left a stack went into the pot this street, even if the action ended it
+        let mut pips = rs.pips;
+        for (pip, (before, after)) in pips.iter_mut().zip(rs.stacks.iter().zip(stacks.iter())) {
+            *pip += before - after;
+        }
+        HistoryEvent::Action { player: rs.button as usize % 2, action, pips, stacks }
+    }
+}
+
+/// Everything we saw of one round
+#[derive(Debug, Clone)]
+pub struct HandHistory {
+    pub round_num: u32,
+    pub player_index: usize,
+    /// Our game clock when the round was dealt
+    pub game_clock: f32,
+    pub hand: CardHand,
+    pub events: Vec<HistoryEvent>,
+    pub opponent_hand: Option<CardHand>,
+    pub delta: i32,
+}
+
+impl HandHistory {
+    /// Starts the history of a round that was just dealt. Nothing is known about how it ends yet.
+    pub fn new(gs: &GameState, rs: &RoundState, player_index: usize) -> HandHistory {
+        HandHistory {
+            round_num: gs.round_num,
+            player_index,
+            game_clock: gs.game_clock,
+            hand: rs.hands[player_index].expect("our hand has to be dealt"),
+            events: vec![],
+            opponent_hand: None,
+            delta: 0,
+        }
+    }
+
+    /// Records `action` being taken in `rs`, which led to `result`
+    pub fn record_action(&mut self, rs: &RoundState, action: Action, result: &StateResult) {
+        self.events.push(HistoryEvent::action(rs, action, result));
+    }
+
+    pub fn record_board(&mut self, deck: &CardDeck) {
+        self.events.push(HistoryEvent::Board(deck.clone()));
+    }
+
+    /// Records how the round ended
+    pub fn finish(&mut self, ts: &TerminalState) {
+        self.opponent_hand = ts.previous.hands[1 - self.player_index];
+        self.delta = ts.deltas[self.player_index];
+    }
+
+    /// The boards of every street we saw, flop first
+    pub fn boards(&self) -> Vec<&CardDeck> {
+        self.events.iter().filter_map(|event| match event {
+            HistoryEvent::Board(deck) => Some(deck),
+            _ => None,
+        }).collect()
+    }
+}
+
+impl fmt::Display for HandHistory {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        write!(fmt, "round={} player={} clock={} hand={},{}", self.round_num, self.player_index, self.game_clock, self.hand.0[0], self.hand.0[1])?;
+        for event in self.events.iter() {
+            match event {
+                HistoryEvent::Action { player, action, pips, stacks } => {
+                    write!(fmt, " {}:{}/{},{}/{},{}", player, Clause::from(*action), pips[0], pips[1], stacks[0], stacks[1])?
+                },
+                HistoryEvent::Board(deck) => write!(fmt, " board={}", deck.0.iter().format(","))?,
+            }
+        }
+        match self.opponent_hand {
+            Some(hand) => write!(fmt, " opponent={},{}", hand.0[0], hand.0[1])?,
+            None => write!(fmt, " opponent=-")?,
+        }
+        write!(fmt, " delta={}", self.delta)
+    }
+}
+
+/// Describes what can be wrong with a line of a history file
+#[derive(Debug)]
+pub enum HistoryParseError {
+    MissingField(&'static str),
+    UnknownField(String),
+    InvalidField(String),
+    Card(CardConversionError),
+}
+
+impl Error for HistoryParseError {}
+
+impl fmt::Display for HistoryParseError {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            HistoryParseError::MissingField(field) => write!(fmt, "Missing field: {}", field),
+            HistoryParseError::UnknownField(token) => write!(fmt, "Unknown field: {}", token),
+            HistoryParseError::InvalidField(token) => write!(fmt, "Invalid field: {}", token),
+            HistoryParseError::Card(e) => write!(fmt, "Invalid card: {}", e),
+        }
+    }
+}
+
+impl From<CardConversionError> for HistoryParseError {
+    fn from(e: CardConversionError) -> HistoryParseError {
+        HistoryParseError::Card(e)
+    }
+}
+
+fn parse_cards(s: &str) -> Result<Vec<Card>, HistoryParseError> {
+    Ok(s.split(',').map(|card| card.parse::<Card>()).collect::<Result<Vec<_>, _>>()?)
+}
+
+fn parse_hand(s: &str) -> Result<CardHand, HistoryParseError> {
+    match parse_cards(s)?.as_slice() {
+        [first, second] => Ok(CardHand([*first, *second])),
+        _ => Err(HistoryParseError::InvalidField(s.to_string())),
+    }
+}
+
+fn parse_pair(s: &str) -> Option<[u32; 2]> {
+    let mut parts = s.splitn(2, ',');
+    Some([parts.next()?.parse().ok()?, parts.next()?.parse().ok()?])
+}
+
+fn parse_action(token: &str) -> Option<HistoryEvent> {
+    let mut parts = token.splitn(2, ':');
+    let player = parts.next()?.parse::<usize>().ok().filter(|player| *player < 2)?;
+    let mut parts = parts.next()?.split('/');
+    let action = parts.next()?.parse::<Clause>().ok()?.action()?;
+    let pips = parse_pair(parts.next()?)?;
+    let stacks = parse_pair(parts.next()?)?;
+    if parts.next().is_some() {
+        return None;
+    }
+    Some(HistoryEvent::Action { player, action, pips, stacks })
+}
+
+impl FromStr for HandHistory {
+    type Err = HistoryParseError;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = |token: &str| HistoryParseError::InvalidField(token.to_string());
+        let (mut round_num, mut player_index, mut game_clock, mut hand, mut opponent_hand, mut delta) = (None, None, None, None, None, None);
+        let mut events = vec![];
+        for token in s.split_whitespace() {
+            if token.contains(':') {
+                events.push(parse_action(token).ok_or_else(|| invalid(token))?);
+                continue;
+            }
+            let mut parts = token.splitn(2, '=');
+            let (key, value) = (parts.next().unwrap(), parts.next().ok_or_else(|| invalid(token))?);
+            match key {
+                "round" => round_num = Some(value.parse::<u32>().map_err(|_| invalid(token))?),
+                "player" => player_index = Some(value.parse::<usize>().ok().filter(|player| *player < 2).ok_or_else(|| invalid(token))?),
+                "clock" => game_clock = Some(value.parse::<f32>().map_err(|_| invalid(token))?),
+                "hand" => hand = Some(parse_hand(value)?),
+                "board" => events.push(HistoryEvent::Board(CardDeck(parse_cards(value)?))),
+                "opponent" => opponent_hand = Some(if value == "-" { None } else { Some(parse_hand(value)?) }),
+                "delta" => delta = Some(value.parse::<i32>().map_err(|_| invalid(token))?),
+                _ => return Err(HistoryParseError::UnknownField(token.to_string())),
+            }
+        }
+        Ok(HandHistory {
+            round_num: round_num.ok_or(HistoryParseError::MissingField("round"))?,
+            player_index: player_index.ok_or(HistoryParseError::MissingField("player"))?,
+            game_clock: game_clock.ok_or(HistoryParseError::MissingField("clock"))?,
+            hand: hand.ok_or(HistoryParseError::MissingField("hand"))?,
+            events,
+            opponent_hand: opponent_hand.ok_or(HistoryParseError::MissingField("opponent"))?,
+            delta: delta.ok_or(HistoryParseError::MissingField("delta"))?,
+        })
+    }
+}
+
+/// Reads every round out of a history file, skipping comments and blank lines
+pub fn read_histories<R: BufRead>(reader: R) -> io::Result<Vec<HandHistory>> {
+    let mut histories = vec![];
+    for (number, line) in reader.lines().enumerate() {
+        let line = line?;
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let history = line.parse::<HandHistory>()
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, format!("Line {}: {}", number + 1, e)))?;
+        histories.push(history);
+    }
+    Ok(histories)
+}
+
+/// Writes hand histories as rounds finish, and never lets the output grow past `max_bytes`.
+/// Once a round doesn't fit, nothing more is written, so the log always holds the first rounds of the match.
+#[derive(Debug)]
+pub struct HistoryLog<W: Write> {
+    writer: W,
+    max_bytes: usize,
+    bytes_written: usize,
+    full: bool,
+    current: Option<HandHistory>,
+}
+
+impl HistoryLog<File> {
+    /// Creates (or truncates) a history file at `path`
+    pub fn create<P: AsRef<Path>>(path: P, max_bytes: usize) -> io::Result<HistoryLog<File>> {
+        HistoryLog::new(File::create(path)?, max_bytes)
+    }
+}
+
+impl<W: Write> HistoryLog<W> {
+    pub fn new(writer: W, max_bytes: usize) -> io::Result<HistoryLog<W>> {
+        let mut log = HistoryLog { writer, max_bytes, bytes_written: 0, full: false, current: None };
+        log.write_line(HISTORY_HEADER)?;
+        Ok(log)
+    }
+
+    /// A round was dealt
+    pub fn start_round(&mut self, gs: &GameState, rs: &RoundState, player_index: usize) {
+        self.current = Some(HandHistory::new(gs, rs, player_index));
+    }
+
+    pub fn record_action(&mut self, rs: &RoundState, action: Action, result: &StateResult) {
+        if let Some(ref mut history) = self.current {
+            history.record_action(rs, action, result);
+        }
+    }
+
+    pub fn record_board(&mut self, deck: &CardDeck) {
+        if let Some(ref mut history) = self.current {
+            history.record_board(deck);
+        }
+    }
+
+    /// The round is over, so its history is written out
+    pub fn finish_round(&mut self, ts: &TerminalState) -> io::Result<()> {
+        if let Some(mut history) = self.current.take() {
+            history.finish(ts);
+            self.write(&history)?;
+        }
+        Ok(())
+    }
+
+    /// Writes a finished history. Returns whether it fit.
+    pub fn write(&mut self, history: &HandHistory) -> io::Result<bool> {
+        self.write_line(&history.to_string())
+    }
+
+    pub fn bytes_written(&self) -> usize {
+        self.bytes_written
+    }
+
+    /// Whether a round didn't fit, so nothing else will be written
+    pub fn is_full(&self) -> bool {
+        self.full
+    }
+
+    pub fn into_inner(self) -> W {
+        self.writer
+    }
+
+    fn write_line(&mut self, line: &str) -> io::Result<bool> {
+        // The newline counts too
+        if self.full || self.bytes_written + line.len() + 1 > self.max_bytes {
+            self.full = true;
+            return Ok(false);
+        }
+        self.writer.write_all(format!("{}\n", line).as_bytes())?;
+        self.writer.flush()?;
+        self.bytes_written += line.len() + 1;
+        Ok(true)
+    }
+}
+
+#[test]
+fn history_test() {
+    use crate::into_cards;
+    use super::states::{STARTING_STACK, SMALL_BLIND, BIG_BLIND};
+    let hand = |cards: &str| { let cards = into_cards!(cards); CardHand([cards[0], cards[1]]) };
+    let gs = GameState { bankroll: 0, game_clock: 29.87, round_num: 3 };
+    let start = RoundState {
+        button: 0,
+        street: 0,
+        pips: [SMALL_BLIND, BIG_BLIND],
+        stacks: [STARTING_STACK - SMALL_BLIND, STARTING_STACK - BIG_BLIND],
+        hands: [None, Some(hand("Ah,Kd"))],
+        deck: CardDeck(vec![]),
+        previous: None,
+    };
+
+    // The example from the module documentation: our raise gets called, then we fold to a raise on the turn
+    let mut log = HistoryLog::new(vec![], DEFAULT_MAX_BYTES).unwrap();
+    log.start_round(&gs, &start, 1);
+    let mut state = start;
+    let mut terminal = None;
+    for (action, board) in [
+        (Action::Call, None), (Action::Raise(6), None), (Action::Call, Some("2c,5d,9h")),
+        (Action::Check, None), (Action::Check, Some("2c,5d,9h,Js")),
+        (Action::Check, None), (Action::Raise(10), None), (Action::Fold, None),
+    ] {
+        let result = state.proceed(action);
+        log.record_action(&state, action, &result);
+        match result {
+            StateResult::Round(next) => state = next,
+            StateResult::Terminal(ts) => terminal = Some(ts),
+        }
+        if let Some(board) = board {
+            // The engine deals the street after the action that ended the last one
+            state.deck = CardDeck(into_cards!(board));
+            state.street = state.deck.0.len() as u32;
+            log.record_board(&state.deck);
+        }
+    }
+    log.finish_round(&terminal.unwrap()).unwrap();
+    let output = String::from_utf8(log.into_inner()).unwrap();
+    let lines: Vec<_> = output.lines().collect();
+    assert_eq!(lines[0], HISTORY_HEADER);
+    assert_eq!(lines[1], "round=3 player=1 clock=29.87 hand=Ah,Kd 0:C/2,2/198,198 1:R6/2,6/198,194 0:C/6,6/194,194 \
+        board=2c,5d,9h 1:K/0,0/194,194 0:K/0,0/194,194 board=2c,5d,9h,Js 1:K/0,0/194,194 0:R10/10,0/184,194 1:F/10,0/184,194 \
+        opponent=- delta=-6");
+
+    // Lines read back into the same history
+    let history = lines[1].parse::<HandHistory>().unwrap();
+    assert_eq!(history.to_string(), lines[1]);
+    assert_eq!(history.boards().len(), 2);
+    let shown = "round=9 player=0 clock=12.5 hand=2c,2d board=3s,4s,5s,6s,7s opponent=Ah,As delta=0".parse::<HandHistory>().unwrap();
+    assert_eq!(shown.opponent_hand.map(|hand| hand.0[1]), Some(into_cards!("As")[0]));
+    assert!(matches!("round=1 player=0 hand=2c,2d opponent=- delta=0".parse::<HandHistory>(), Err(HistoryParseError::MissingField("clock"))));
+    assert!("round=1 player=0 clock=1 hand=2c,2d 0:X/0,0/0,0 opponent=- delta=0".parse::<HandHistory>().is_err());
+    assert!("round=1 player=0 clock=1 hand=2c,Zd opponent=- delta=0".parse::<HandHistory>().is_err());
+
+    // Nothing gets past the cap, and once a round doesn't fit the log stops
+    let cap = HISTORY_HEADER.len() + 1 + lines[1].len() + 1 + 10;
+    let mut log = HistoryLog::new(vec![], cap).unwrap();
+    assert!(log.write(&history).unwrap());
+    assert!(!log.write(&history).unwrap());
+    assert!(log.is_full());
+    assert!(!log.write(&shown).unwrap());
+    assert!(log.bytes_written() <= cap);
+    assert_eq!(log.into_inner().len(), cap - 10);
+}
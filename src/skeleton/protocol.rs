@@ -0,0 +1,302 @@
+//! The engine's wire protocol. The engine sends lines of space-separated clauses, each a command letter followed by
+//! its argument, and a bot answers every line with a single action clause:
+//!
+//! ```text
+//! T29.870 P0 HAh,Kd        engine: clock, our seat, our hand
+//! R6                       bot: raise to 6
+//! T29.812 C B2c,5d,9h      engine: they called, then the flop
+//! ```
+//!
+//! The commands are `T` (game clock), `P` (player index), `H` (our hand), `F`, `C`, `K`, `R` (fold, call, check and
+//! raise to), `B` (the board), `O` (the opponent's hand), `D` (our delta for the round) and `Q` (the match is over).
+//! `Clause` parses one of them and displays as exactly what goes over the wire, and `Decoder` turns bytes, however
+//! they happen to arrive, into clauses.
+
+use super::actions::Action;
+use super::cards::{Card, CardDeck, CardHand, CardConversionError};
+use std::error::Error;
+use std::fmt;
+use std::str::FromStr;
+use itertools::Itertools;
+
+/// One clause of the protocol
+#[derive(Debug, Clone, PartialEq)]
+pub enum Clause {
+    /// Seconds left on our game clock. Sent to the millisecond, like the engine does.
+    GameClock(f32), // T
+    /// Our seat this round, 0 being the small blind
+    PlayerIndex(usize), // P
+    /// Our hand, which starts a round
+    Hand(CardHand), // H
+    Fold, // F
+    Call, // C
+    Check, // K
+    /// A raise to the amount, not by it
+    Raise(u32), // R
+    /// The whole board, whenever a street is dealt
+    Board(CardDeck), // B
+    /// The opponent's hand, when it is shown
+    Reveal(CardHand), // O
+    /// How much we won or lost this round
+    Delta(i32), // D
+    Quit, // Q
+}
+
+impl Clause {
+    /// The action this clause plays, if it plays one
+    pub fn action(&self) -> Option<Action> {
+        match *self {
+            Clause::Fold => Some(Action::Fold),
+            Clause::Call => Some(Action::Call),
+            Clause::Check => Some(Action::Check),
+            Clause::Raise(amount) => Some(Action::Raise(amount)),
+            _ => None,
+        }
+    }
+}
+
+impl From<Action> for Clause {
+    fn from(action: Action) -> Clause {
+        match action {
+            Action::Fold => Clause::Fold,
+            Action::Call => Clause::Call,
+            Action::Check => Clause::Check,
+            Action::Raise(amount) => Clause::Raise(amount),
+        }
+    }
+}
+
+impl fmt::Display for Clause {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Clause::GameClock(clock) => write!(fmt, "T{:.3}", clock),
+            Clause::PlayerIndex(index) => write!(fmt, "P{}", index),
+            Clause::Hand(hand) => write!(fmt, "H{},{}", hand.0[0], hand.0[1]),
+            Clause::Fold => write!(fmt, "F"),
+            Clause::Call => write!(fmt, "C"),
+            Clause::Check => write!(fmt, "K"),
+            Clause::Raise(amount) => write!(fmt, "R{}", amount),
+            Clause::Board(deck) => write!(fmt, "B{}", deck.0.iter().format(",")),
+            Clause::Reveal(hand) => write!(fmt, "O{},{}", hand.0[0], hand.0[1]),
+            Clause::Delta(delta) => write!(fmt, "D{}", delta),
+            Clause::Quit => write!(fmt, "Q"),
+        }
+    }
+}
+
+/// Encodes an action the way the engine expects a bot to reply
+pub fn encode_action(action: Action) -> String {
+    Clause::from(action).to_string()
+}
+
+/// What was wrong with a clause
+#[derive(Debug, PartialEq)]
+pub enum ProtocolErrorKind {
+    Empty,
+    UnknownCommand(char),
+    /// A command that doesn't take an argument got one
+    UnexpectedArgument,
+    InvalidNumber,
+    /// A player index other than 0 or 1
+    InvalidPlayerIndex(usize),
+    Card(CardConversionError),
+    /// The wrong number of cards for a hand or a board
+    CardCount(usize),
+}
+
+/// A clause that couldn't be decoded
+#[derive(Debug, PartialEq)]
+pub struct ProtocolError {
+    pub clause: String,
+    pub kind: ProtocolErrorKind,
+}
+
+impl ProtocolError {
+    /// The command letter of the clause, if it had one
+    pub fn command(&self) -> Option<char> {
+        self.clause.chars().next()
+    }
+}
+
+impl Error for ProtocolError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self.kind {
+            ProtocolErrorKind::Card(ref e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for ProtocolError {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        write!(fmt, "Invalid clause {:?}: ", self.clause)?;
+        match self.kind {
+            ProtocolErrorKind::Empty => write!(fmt, "empty clause"),
+            ProtocolErrorKind::UnknownCommand(command) => write!(fmt, "unknown command {:?}", command),
+            ProtocolErrorKind::UnexpectedArgument => write!(fmt, "command takes no argument"),
+            ProtocolErrorKind::InvalidNumber => write!(fmt, "invalid number"),
+            ProtocolErrorKind::InvalidPlayerIndex(index) => write!(fmt, "player index {} isn't 0 or 1", index),
+            ProtocolErrorKind::Card(ref e) => write!(fmt, "{}", e),
+            ProtocolErrorKind::CardCount(count) => write!(fmt, "wrong number of cards ({})", count),
+        }
+    }
+}
+
+impl FromStr for Clause {
+    type Err = ProtocolError;
+    fn from_str(clause: &str) -> Result<Self, Self::Err> {
+        let error = |kind| ProtocolError { clause: clause.to_string(), kind };
+        let mut chars = clause.chars();
+        let command = chars.next().ok_or_else(|| error(ProtocolErrorKind::Empty))?;
+        let arg = chars.as_str();
+        let cards = || arg.split(',').map(|card| card.parse::<Card>()).collect::<Result<Vec<_>, _>>()
+            .map_err(|e| error(ProtocolErrorKind::Card(e)));
+        let hand = || match cards()?[..] {
+            [first, second] => Ok(CardHand([first, second])),
+            ref other => Err(error(ProtocolErrorKind::CardCount(other.len()))),
+        };
+        let number = || error(ProtocolErrorKind::InvalidNumber);
+        let no_arg = |parsed| if arg.is_empty() { Ok(parsed) } else { Err(error(ProtocolErrorKind::UnexpectedArgument)) };
+        match command {
+            'T' => match arg.parse::<f32>() {
+                Ok(clock) if clock.is_finite() => Ok(Clause::GameClock(clock)),
+                _ => Err(number()),
+            },
+            'P' => match arg.parse::<usize>().map_err(|_| number())? {
+                index if index < 2 => Ok(Clause::PlayerIndex(index)),
+                index => Err(error(ProtocolErrorKind::InvalidPlayerIndex(index))),
+            },
+            'H' => hand().map(Clause::Hand),
+            'F' => no_arg(Clause::Fold),
+            'C' => no_arg(Clause::Call),
+            'K' => no_arg(Clause::Check),
+            'R' => arg.parse::<u32>().map(Clause::Raise).map_err(|_| number()),
+            'B' => match cards()? {
+                deck if deck.len() >= 3 && deck.len() <= 5 => Ok(Clause::Board(CardDeck(deck))),
+                deck => Err(error(ProtocolErrorKind::CardCount(deck.len()))),
+            },
+            'O' => hand().map(Clause::Reveal),
+            'D' => arg.parse::<i32>().map(Clause::Delta).map_err(|_| number()),
+            'Q' => no_arg(Clause::Quit),
+            command => Err(error(ProtocolErrorKind::UnknownCommand(command))),
+        }
+    }
+}
+
+/// Decodes one whole line into its clauses
+pub fn decode_line(line: &str) -> Vec<Result<Clause, ProtocolError>> {
+    line.split_whitespace().map(str::parse).collect()
+}
+
+/// Turns bytes from the engine into clauses, however they happen to be split up. Only finished lines are decoded,
+/// and the start of an unfinished one is kept until the rest of it arrives.
+#[derive(Debug, Default)]
+pub struct Decoder {
+    buffer: Vec<u8>,
+}
+
+impl Decoder {
+    pub fn new() -> Decoder {
+        Decoder::default()
+    }
+
+    /// Takes in more bytes, and decodes every line they finish
+    pub fn feed(&mut self, bytes: &[u8]) -> Vec<Result<Clause, ProtocolError>> {
+        self.buffer.extend_from_slice(bytes);
+        let mut clauses = vec![];
+        while let Some(end) = self.buffer.iter().position(|byte| *byte == b'\n') {
+            let line: Vec<u8> = self.buffer.drain(..=end).collect();
+            clauses.extend(decode_line(&String::from_utf8_lossy(&line)));
+        }
+        clauses
+    }
+
+    /// The start of a line that hasn't finished yet
+    pub fn pending(&self) -> &[u8] {
+        &self.buffer
+    }
+
+    /// Decodes whatever is left, for when the engine hangs up in the middle of a line
+    pub fn finish(&mut self) -> Vec<Result<Clause, ProtocolError>> {
+        let line: Vec<u8> = self.buffer.drain(..).collect();
+        decode_line(&String::from_utf8_lossy(&line))
+    }
+}
+
+#[test]
+fn protocol_test() {
+    use crate::into_cards;
+    let cards = into_cards!("Ah,Kd,2c,5d,9h,Js,Ts");
+    let hand = CardHand([cards[0], cards[1]]);
+    let clauses = vec![
+        (Clause::GameClock(29.5), "T29.500"),
+        (Clause::GameClock(0.0), "T0.000"),
+        (Clause::PlayerIndex(0), "P0"),
+        (Clause::PlayerIndex(1), "P1"),
+        (Clause::Hand(hand), "HAh,Kd"),
+        (Clause::Fold, "F"),
+        (Clause::Call, "C"),
+        (Clause::Check, "K"),
+        (Clause::Raise(0), "R0"),
+        (Clause::Raise(200), "R200"),
+        (Clause::Board(CardDeck(cards[2..5].to_vec())), "B2c,5d,9h"),
+        (Clause::Board(CardDeck(cards[2..6].to_vec())), "B2c,5d,9h,Js"),
+        (Clause::Board(CardDeck(cards[2..7].to_vec())), "B2c,5d,9h,Js,Ts"),
+        (Clause::Reveal(hand), "OAh,Kd"),
+        (Clause::Delta(-200), "D-200"),
+        (Clause::Delta(0), "D0"),
+        (Clause::Delta(14), "D14"),
+        (Clause::Quit, "Q"),
+    ];
+    for (clause, code) in clauses.iter() {
+        assert_eq!(clause.to_string(), *code);
+        assert_eq!(code.parse::<Clause>().as_ref(), Ok(clause));
+    }
+    for action in [Action::Fold, Action::Call, Action::Check, Action::Raise(6)] {
+        assert_eq!(encode_action(action).parse::<Clause>().unwrap().action(), Some(action));
+    }
+    assert_eq!(Clause::Quit.action(), None);
+
+    let kind = |clause: &str| clause.parse::<Clause>().unwrap_err().kind;
+    assert_eq!(kind(""), ProtocolErrorKind::Empty);
+    assert_eq!(kind("Z12"), ProtocolErrorKind::UnknownCommand('Z'));
+    assert_eq!(kind("t30"), ProtocolErrorKind::UnknownCommand('t'));
+    for &clause in &["F1", "CK", "K0", "Q!"] {
+        assert_eq!(kind(clause), ProtocolErrorKind::UnexpectedArgument);
+    }
+    for &clause in &["T", "Tnan", "Tinf", "P", "P-1", "R", "R-6", "R1.5", "Rten", "D", "D1e3"] {
+        assert_eq!(kind(clause), ProtocolErrorKind::InvalidNumber, "{}", clause);
+    }
+    assert_eq!(kind("P2"), ProtocolErrorKind::InvalidPlayerIndex(2));
+    assert_eq!(kind("HAh"), ProtocolErrorKind::CardCount(1));
+    assert_eq!(kind("HAh,Kd,Qs"), ProtocolErrorKind::CardCount(3));
+    assert_eq!(kind("B2c,5d"), ProtocolErrorKind::CardCount(2));
+    assert_eq!(kind("B2c,5d,9h,Js,Ts,3s"), ProtocolErrorKind::CardCount(6));
+    for &clause in &["H", "HAh,Xd", "HAh,Kx", "OAh,Kdd", "B2c,,9h"] {
+        match kind(clause) {
+            ProtocolErrorKind::Card(_) => {},
+            other => panic!("{} isn't a card error: {:?}", clause, other),
+        }
+    }
+    assert_eq!("R10x".parse::<Clause>().unwrap_err().command(), Some('R'));
+
+    // Lines can arrive in any number of pieces, with any number of clauses on them
+    let stream = b"T29.870 P0 HAh,Kd\nT29.812 C B2c,5d,9h\r\nT29.700  R8 X D-8\nQ";
+    for split in 0..=stream.len() {
+        let mut decoder = Decoder::new();
+        let mut decoded = decoder.feed(&stream[..split]);
+        decoded.extend(decoder.feed(&stream[split..]));
+        assert_eq!(decoder.pending(), b"Q");
+        decoded.extend(decoder.finish());
+        assert!(decoder.pending().is_empty());
+        let codes: Vec<String> = decoded.iter().map(|clause| match clause {
+            Ok(clause) => clause.to_string(),
+            Err(e) => format!("!{}", e.clause),
+        }).collect();
+        assert_eq!(codes, ["T29.870", "P0", "HAh,Kd", "T29.812", "C", "B2c,5d,9h", "T29.700", "R8", "!X", "D-8", "Q"]);
+    }
+    let mut decoder = Decoder::new();
+    assert!(decoder.feed(b"T1.000 P").is_empty());
+    assert_eq!(decoder.feed(b"1\n\n \n").len(), 2);
+    assert_eq!(decoder.feed(b"\xffK\n")[0].as_ref().unwrap_err().kind, ProtocolErrorKind::UnknownCommand('\u{fffd}'));
+}
@@ -0,0 +1,143 @@
+//! Replays recorded rounds through a PokerBot offline, to see where it would have played differently.
+//! Every round is fed to the bot clause by clause, the same way the Runner does it, so the bot sees exactly the
+//! states it would have seen in the match. Whenever it is our turn, the bot is asked for an action, which is made
+//! legal like the Runner would before sending it, and compared with what was actually played. The recorded action
+//! is always the one that gets played, so the rest of the round stays the same. A round that doesn't add up is dropped
+//! from the clause that breaks it on, the same way the Runner resyncs on the next round.
+
+use super::actions::Action;
+use super::bot::PokerBot;
+use super::clock::Deadline;
+use super::history::{HandHistory, HistoryEvent};
+use super::runner::{Runner, RunnerError, RunnerState, PreservedOrdering};
+use std::fmt;
+use std::io;
+use crate::debug_println;
+
+/// A decision where the bot would have played something else than what was recorded
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Divergence {
+    pub round_num: u32,
+    pub street: u32,
+    pub recorded: Action,
+    pub chosen: Action,
+}
+
+impl fmt::Display for Divergence {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        write!(fmt, "Round #{} street {}: recorded {:?}, bot chose {:?}", self.round_num, self.street, self.recorded, self.chosen)
+    }
+}
+
+/// What a replay found
+#[derive(Debug, Clone, Default)]
+pub struct ReplayReport {
+    pub rounds: u32,
+    /// How many times the bot was asked for an action
+    pub decisions: u32,
+    pub divergences: Vec<Divergence>,
+    /// The bankroll the recorded rounds add up to
+    pub bankroll: i64,
+}
+
+impl ReplayReport {
+    /// Share of decisions where the bot chose what was recorded
+    pub fn agreement(&self) -> f64 {
+        if self.decisions == 0 {
+            1.0
+        } else {
+            1.0 - self.divergences.len() as f64 / self.decisions as f64
+        }
+    }
+}
+
+impl fmt::Display for ReplayReport {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        for divergence in self.divergences.iter() {
+            writeln!(fmt, "{}", divergence)?;
+        }
+        write!(fmt, "Replayed {} rounds ({} bankroll): {} of {} decisions diverged ({:.2}% agreement)",
+            self.rounds, self.bankroll, self.divergences.len(), self.decisions, 100.0 * self.agreement())
+    }
+}
+
+// The state the Runner keeps between clauses, with nothing to record the rounds to
+struct ReplayState(RunnerState<io::Sink>);
+
+impl ReplayState {
+    fn apply<B: PokerBot + ?Sized>(&mut self, bot: &mut B, clause: PreservedOrdering) -> Result<(), RunnerError> {
+        Runner::handle_clause(clause, &mut self.0, bot)
+    }
+
+    fn replay_round<B: PokerBot + ?Sized>(&mut self, bot: &mut B, round: &HandHistory, report: &mut ReplayReport) -> Result<(), RunnerError> {
+        // Every round starts with the engine setting the clock, our index and our hand
+        self.0.game_state.game_clock = round.game_clock;
+        self.0.game_state.round_num = round.round_num;
+        self.apply(bot, PreservedOrdering::SetPlayerIndex(round.player_index))?;
+        self.apply(bot, PreservedOrdering::StartRound(round.hand))?;
+        for event in round.events.iter() {
+            match event {
+                HistoryEvent::Action { player, action, .. } => {
+                    if *player == self.0.player_index {
+                        if let Some(ref rs) = self.0.round_state {
+                            // The clock is the one that was recorded, so the bot is under the same time pressure it was then
+                            let deadline = Deadline::new(self.0.game_state.game_clock, self.0.game_state.round_num);
+                            let chosen = Runner::legal_action(rs, bot.get_action_within(&self.0.game_state, rs, *player, &deadline));
+                            report.decisions += 1;
+                            if chosen != *action {
+                                report.divergences.push(Divergence { round_num: round.round_num, street: rs.street, recorded: *action, chosen });
+                            }
+                        }
+                    }
+                    self.apply(bot, PreservedOrdering::Action(*action))?;
+                },
+                HistoryEvent::Board(deck) => self.apply(bot, PreservedOrdering::UpdateDeck(deck.clone()))?,
+            }
+        }
+        if let Some(hand) = round.opponent_hand {
+            self.apply(bot, PreservedOrdering::Reveal(hand))?;
+        }
+        self.apply(bot, PreservedOrdering::Delta(round.delta))
+    }
+}
+
+/// Replays `rounds` (in order) through `bot`
+pub fn replay<B: PokerBot + ?Sized>(bot: &mut B, rounds: &[HandHistory]) -> ReplayReport {
+    let mut state = ReplayState(RunnerState::new(None));
+    let mut report = ReplayReport::default();
+    for round in rounds {
+        if let Err(e) = state.replay_round(bot, round, &mut report) {
+            debug_println!("[Replay] Skipping the rest of round #{} ({})", round.round_num, e);
+            state.apply(bot, PreservedOrdering::Resync).unwrap();
+            state.0.game_state.bankroll += round.delta as i64;
+        }
+        report.rounds += 1;
+    }
+    report.bankroll = state.0.game_state.bankroll;
+    report
+}
+
+#[test]
+fn replay_test() {
+    use super::bot::CallingStation;
+    use super::history::HISTORY_HEADER;
+    let log = format!("{}\n{}\n{}\n", HISTORY_HEADER,
+        "round=3 player=1 clock=29.87 hand=Ah,Kd 0:C/2,2/198,198 1:R6/2,6/198,194 0:C/6,6/194,194 board=2c,5d,9h \
+            1:K/0,0/194,194 0:K/0,0/194,194 board=2c,5d,9h,Js 1:K/0,0/194,194 0:R10/10,0/184,194 1:F/10,0/184,194 opponent=- delta=-6",
+        "round=4 player=0 clock=29.5 hand=2c,2d 0:C/2,2/198,198 1:K/2,2/198,198 board=3s,4s,5s 1:K/0,0/198,198 0:K/0,0/198,198 \
+            board=3s,4s,5s,6s 1:K/0,0/198,198 0:K/0,0/198,198 board=3s,4s,5s,6s,7s 1:K/0,0/198,198 0:K/0,0/198,198 opponent=Ah,As delta=0");
+    let rounds = super::history::read_histories(log.as_bytes()).unwrap();
+    let mut bot = CallingStation::default();
+    let report = replay(&mut bot, &rounds);
+    assert_eq!(bot.rounds, [(3, 1), (4, 0)]);
+    assert_eq!(bot.deltas, [-6, 0]);
+    assert_eq!(report.rounds, 2);
+    assert_eq!(report.bankroll, -6);
+    // We raised where the bot checks, and folded where it calls. The second round it plays exactly like we did.
+    assert_eq!(report.decisions, 4 + 4);
+    assert_eq!(report.divergences, [
+        Divergence { round_num: 3, street: 0, recorded: Action::Raise(6), chosen: Action::Check },
+        Divergence { round_num: 3, street: 4, recorded: Action::Fold, chosen: Action::Call },
+    ]);
+    assert_eq!(report.agreement(), 0.75);
+}
@@ -1,37 +1,37 @@
-use std::net::{TcpStream, Shutdown, ToSocketAddrs};
+use std::net::ToSocketAddrs;
 use super::bot::PokerBot;
-use std::io::{prelude::*, BufReader, ErrorKind};
-use crate::into_cards;
+use std::io::{self, prelude::*, BufReader, ErrorKind};
 use super::actions::{Action, ActionType};
-use super::states::{SMALL_BLIND, BIG_BLIND, STARTING_STACK, GameState, RoundState, TerminalState, StateResult};
-use super::cards::{Card, CardHand, CardDeck};
+use super::states::{SMALL_BLIND, BIG_BLIND, STARTING_STACK, NUM_ROUNDS, GameState, RoundState, TerminalState, StateResult};
+use super::cards::{CardHand, CardDeck};
+use super::protocol::{self, Clause, Decoder, ProtocolError};
+use super::transport::{self, Transport};
+use super::clock::{Deadline, TimeBank};
+use super::history::HistoryLog;
+use std::fs::File;
+use std::error::Error;
+use std::fmt;
 use std::time::{Duration, Instant};
 use crate::debug_println;
 use super::thread_pool::ThreadPool;
 use std::sync::{
-    atomic::{AtomicUsize, AtomicBool, Ordering},
+    atomic::{AtomicBool, Ordering},
     Arc, Mutex, RwLock,
     TryLockError,
     RwLockReadGuard, RwLockWriteGuard,
     MutexGuard,
     mpsc::channel,
 };
-#[cfg(feature = "debug_print")]
-use itertools::Itertools;
 use std::thread;
 use approx::relative_eq;
 
-const CONNECT_TIMEOUT: u64 = 10; // seconds
 // const READ_TIMEOUT: u64 = 1; // microseconds
 const WRITE_TIMEOUT: u64 = 1; // microseconds
 // Pretty important due to how fast we are running.
 // If a sent packet doesn't arrive in time, throw it away.
 // const PACKET_TTL: u32 = 2; // seconds
-const PLAYER_INDEX_LOAD_ORDERING: Ordering = Ordering::SeqCst;
-const PLAYER_INDEX_STOR_ORDERING: Ordering = Ordering::SeqCst;
 const MAX_THREAD_COUNT: usize = 16;
 const SLEEP_DURATION: u64 = 1; // milliseconds
-const COMP_TIME: u64 = 60; // microseconds
 
 pub struct Runner {
     socket: Arc<Mutex<Socket>>,
@@ -39,71 +39,166 @@ pub struct Runner {
     thread_count: usize,
 }
 
+// Everything the Runner keeps track of between clauses, along with where the rounds it plays get recorded
+pub(super) struct RunnerState<W: Write> {
+    pub(super) game_state: GameState,
+    pub(super) round_state: Option<RoundState>,
+    pub(super) terminal_state: Option<TerminalState>,
+    pub(super) player_index: usize,
+    // Set while we wait for the next round after losing track of this one
+    pub(super) resyncing: bool,
+    pub(super) history: Option<HistoryLog<W>>,
+}
+
+impl<W: Write> RunnerState<W> {
+    pub(super) fn new(history: Option<HistoryLog<W>>) -> RunnerState<W> {
+        RunnerState {
+            game_state: GameState {
+                bankroll: 0,
+                game_clock: 0.0,
+                round_num: 1
+            },
+            round_state: None,
+            terminal_state: None,
+            player_index: 0,
+            resyncing: false,
+            history,
+        }
+    }
+}
+
+/// Everything that can go wrong while the Runner plays a match
 #[derive(Debug)]
+pub enum RunnerError {
+    /// The engine sent a clause we couldn't make sense of
+    Protocol(ProtocolError),
+    /// Talking to the engine failed, or it hung up on us
+    Io(io::Error),
+    /// A clause that doesn't fit the state we are in, like an action before any round started
+    State(String),
+    /// We were asked to act, but as far as we know it isn't our turn
+    OutOfTurn { round_num: u32 },
+    /// We ran out of time, on the game clock or our own
+    Timeout,
+}
+
+/// What the Runner does about an error, so one bad packet doesn't cost us the rest of the match
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Recovery {
+    /// Ignore the clause and carry on
+    SkipClause,
+    /// Our round can't be trusted anymore, so drop everything the engine says until the next `H`
+    Resync,
+    /// Fold whatever we are asked to act on, then resync
+    SafeFold,
+    /// Stop playing, and return the error from `Runner::run_bot`
+    Abort,
+}
+
+impl RunnerError {
+    /// How the Runner recovers from this error
+    pub fn recovery(&self) -> Recovery {
+        match self {
+            // Clauses that don't touch the round (or that we don't even know) cost us nothing to lose
+            RunnerError::Protocol(e) => match e.command() {
+                Some(c) if "PHFCKRBODQ".contains(c) => Recovery::Resync,
+                _ => Recovery::SkipClause,
+            },
+            RunnerError::State(_) => Recovery::Resync,
+            RunnerError::OutOfTurn { .. } => Recovery::SafeFold,
+            RunnerError::Io(_) | RunnerError::Timeout => Recovery::Abort,
+        }
+    }
+}
+
+impl fmt::Display for RunnerError {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            RunnerError::Protocol(e) => write!(fmt, "Protocol error: {}", e),
+            RunnerError::Io(e) => write!(fmt, "I/O error: {}", e),
+            RunnerError::State(reason) => write!(fmt, "Invalid state: {}", reason),
+            RunnerError::OutOfTurn { round_num } => write!(fmt, "Asked to act out of turn in round #{}", round_num),
+            RunnerError::Timeout => write!(fmt, "Ran out of time"),
+        }
+    }
+}
+
+impl Error for RunnerError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            RunnerError::Io(e) => Some(e),
+            RunnerError::Protocol(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for RunnerError {
+    fn from(e: io::Error) -> RunnerError {
+        RunnerError::Io(e)
+    }
+}
+
+impl From<ProtocolError> for RunnerError {
+    fn from(e: ProtocolError) -> RunnerError {
+        RunnerError::Protocol(e)
+    }
+}
+
 struct Socket {
-    stream: BufReader<TcpStream>,
-    read_queue: Vec<ServerAction>,
+    stream: BufReader<Box<dyn Transport>>,
+    decoder: Decoder,
+    read_queue: Vec<Result<Clause, ProtocolError>>,
     write_action: Vec<Action>,
     round_sent: AtomicBool,
-}
-
-#[derive(Debug, Clone)]
-enum ServerAction {
-    SetGameClock(f32), // T
-    SetPlayerIndex(usize), // P
-    SetPlayerHand(CardHand), // H
-    PlayFold, // F
-    PlayCall, // C
-    PlayCheck, // K
-    PlayRaise(u32), // R
-    UpdateDeck(CardDeck), // B
-    RevealOpponentHand(CardHand), // O
-    Delta(i32), // D
-    Quit // Q
+    // Set from reading a line until we answer it. The engine sends nothing else in the meantime, so reading would block.
+    awaiting_answer: bool,
+    // Our game clock, which runs from when a line comes in until we answer it
+    time_bank: TimeBank,
 }
 
 // Actions that we should preserve the ordering for, so we
 // push them into a queue, and have only one thread that controls them
 #[derive(Debug)]
-enum PreservedOrdering {
+pub(super) enum PreservedOrdering {
     Action(Action),
     Delta(i32),
     StartRound(CardHand),
     Reveal(CardHand),
     UpdateDeck(CardDeck),
     SetPlayerIndex(usize),
+    // Something went wrong, so forget the round we are in
+    Resync,
 }
 
 impl Socket {
-    fn new(stream: BufReader<TcpStream>) -> Socket {
+    fn new(stream: BufReader<Box<dyn Transport>>) -> Socket {
         Socket {
             stream,
+            decoder: Decoder::new(),
             read_queue: vec![],
             write_action: vec![], // We always start off with checking to ack the server
             round_sent: AtomicBool::new(false),
+            awaiting_answer: false,
+            time_bank: TimeBank::new(),
         }
     }
 
     /// Returns an incoming message from the engine.
-    fn receive(&mut self) -> Vec<ServerAction> {
+    fn receive(&mut self) -> Vec<Result<Clause, ProtocolError>> {
         self.read_queue.drain(..).collect()
     }
 
-    fn ping(&mut self) {
+    fn ping(&mut self) -> Result<(), RunnerError> {
         debug_println!("[Socket] Will ping server");
-        self.send(Action::Check);
+        self.send(Action::Check)
     }
 
     /// Send an action message to the engine
-    fn send(&mut self, action: Action) {
+    fn send(&mut self, action: Action) -> Result<(), RunnerError> {
         let ref mut socket = self.stream;
 
-        let code = match action {
-            Action::Fold => "F".into(),
-            Action::Call => "C".into(),
-            Action::Check => "K".into(),
-            Action::Raise(amt) => format!("R{}", amt)
-        };
+        let code = protocol::encode_action(action);
 
         let mut retries = 10;
         while self.round_sent.load(Ordering::SeqCst) {
@@ -113,97 +208,71 @@ impl Socket {
                     debug_println!("[Socket] Send error {}. Retrying...", e);
                     retries -= 1;
                 } else {
-                    panic!("[Socket] Server unresponsive. Panicing...")
+                    debug_println!("[Socket] Server unresponsive");
+                    self.round_sent.store(false, Ordering::SeqCst);
+                    return Err(RunnerError::Io(e));
                 }
             }
-            socket.get_mut().flush().unwrap();
+            socket.get_mut().flush()?;
         }
 
         self.round_sent.store(false, Ordering::SeqCst);
+        self.awaiting_answer = false;
+        self.time_bank.answered();
 
-        Socket::check_for_socket_errors(socket.get_ref());
+        Socket::check_for_socket_errors(socket.get_ref().as_ref())
     }
 
-    fn check_for_socket_errors(socket: &TcpStream) {
+    fn check_for_socket_errors(socket: &dyn Transport) -> Result<(), RunnerError> {
         // Check stream for errors. If there is one, disconnect.
         match socket.take_error() {
-            Ok(Some(error)) => panic!("[Socket] Disconnecting because of stream error {}", error),
-            Ok(None) => {}, // No stream error detected
+            Ok(Some(error)) => Err(RunnerError::Io(error)),
+            Ok(None) => Ok(()), // No stream error detected
             Err(e) => match e.kind() {
-                ErrorKind::TimedOut | ErrorKind::WouldBlock => {}, // We don't care about these errors,
-                kind => panic!("[Socket] Unexpected error when checking for socket errors ({:?}) {}", kind, e)
+                ErrorKind::TimedOut | ErrorKind::WouldBlock => Ok(()), // We don't care about these errors,
+                _ => Err(RunnerError::Io(e))
             }
         }
     }
 
-    // Do all I/O processing here
-    fn sync(&mut self) {
-        let mut server_process = vec![];
+    // Do all I/O processing here. Clauses we can't parse are queued as errors, so they are dealt with in order.
+    fn sync(&mut self) -> Result<(), RunnerError> {
         // Write as much as we can, then read all the actions we can
         let ref mut socket = self.stream;
 
-        // Read
-        let mut s = String::new();
-
-        // Make read and write non-blocking, so we don't run out of time if our opponent does
-        match socket.read_line(&mut s) {
-            Ok(_) => {},
+        // Make read and write non-blocking, so we don't run out of time if our opponent does.
+        // Half a line stays in the decoder until the rest of it comes in.
+        let clauses = match socket.fill_buf() {
+            Ok([]) => return Err(RunnerError::Io(io::Error::new(ErrorKind::UnexpectedEof, "engine closed the connection"))),
+            Ok(bytes) => {
+                let read = bytes.len();
+                let clauses = self.decoder.feed(bytes);
+                socket.consume(read);
+                clauses
+            },
             Err(e) => match e.kind() {
-                ErrorKind::WouldBlock => {},
-                kind => panic!("[Socket] Unexpected read error ({:?}) {}", kind, e)
-            }
-        }
-
-        for action in s.trim().split(" ").map(|x| x.trim().to_string()) {
-            if !action.is_empty() {
-                server_process.push(action);
+                ErrorKind::WouldBlock => vec![],
+                _ => return Err(RunnerError::Io(e))
             }
-        }
+        };
 
-        Socket::check_for_socket_errors(socket.get_ref());
+        Socket::check_for_socket_errors(socket.get_ref().as_ref())?;
 
-        if server_process.len() > 0 {
+        if clauses.len() > 0 {
             // self.round_sent = false;
-            debug_println!("[Socket] Read actions from socket [{}]", server_process.iter().format(", "));
+            self.awaiting_answer = true;
+            debug_println!("[Socket] Read clauses from socket {:?}", clauses);
         }
 
-        Socket::check_for_socket_errors(socket.get_ref());
-
-        // Process server strings into ServerAction objects
-        for action in server_process.into_iter() {
-            let act = action.chars().nth(0).unwrap();
-            let arg = action.chars().skip(1).collect::<String>();
-            let server_action = match act {
-                'T' => ServerAction::SetGameClock(arg.parse::<f32>().expect("Expected float for game clock")),
-                'P' => ServerAction::SetPlayerIndex(arg.parse::<usize>().expect("Expected positive integer for player index")),
-                'H' => {
-                    let cards = into_cards!(arg);
-                    assert!(cards.len() == 2, "Server sent too many cards for player hand");
-                    ServerAction::SetPlayerHand(CardHand([cards[0], cards[1]]))
-                },
-                'F' => ServerAction::PlayFold,
-                'C' => ServerAction::PlayCall,
-                'K' => ServerAction::PlayCheck,
-                'R' => ServerAction::PlayRaise(arg.parse::<u32>().expect("Expected positive integer for raise amount")),
-                'B' => ServerAction::UpdateDeck(CardDeck(into_cards!(arg))),
-                'O' => {
-                    let cards = into_cards!(arg);
-                    assert!(cards.len() == 2, "Server sent too many cards for player hand");
-                    ServerAction::RevealOpponentHand(CardHand([cards[0], cards[1]]))
-                },
-                'D' => ServerAction::Delta(arg.parse::<i32>().expect("Expected integer for delta")),
-                'Q' => ServerAction::Quit,
-                c => panic!("[Socket] Unknown server command {} with arg {}", c, arg)
-            };
-            self.read_queue.push(server_action);
-        }
+        self.read_queue.extend(clauses);
+        Ok(())
     }
 }
 
 // Shutdown the socket even if we panic, and right when we panic
 impl Drop for Socket {
     fn drop(&mut self) {
-        match self.stream.get_mut().shutdown(Shutdown::Both) {
+        match self.stream.get_mut().shutdown() {
             Ok(()) => debug_println!("Successfully shut down socket"),
             // We don't really care about errors here, as our goal is simply to end the socket
             Err(_) => {}
@@ -213,22 +282,106 @@ impl Drop for Socket {
 
 impl Runner {
     /// Runs a PokerBot using the Runner
-    pub fn run_bot<TS>(bot: Box<dyn PokerBot + Send + Sync>, addr: TS, thread_count: usize) -> std::io::Result<()> where TS: ToSocketAddrs {
-        if let Some(addr) = addr.to_socket_addrs()?.nth(0) {
-            let stream = TcpStream::connect_timeout(&addr, Duration::from_secs(CONNECT_TIMEOUT))?;
-            stream.set_nodelay(true).expect("set_nodelay call failed");
-            // stream.set_read_timeout(Some(Duration::from_micros(READ_TIMEOUT))).expect("read_timeout call failed");
-            stream.set_write_timeout(Some(Duration::from_micros(WRITE_TIMEOUT))).expect("write_timeout call failed");
-            // stream.set_ttl(PACKET_TTL).expect("set_ttl call failed");
-            // stream.set_nonblocking(true).expect("set_nonblocking call failed");
-            let mut runner = Runner {
-                socket: Arc::new(Mutex::new(Socket::new(BufReader::new(stream)))),
-                runner_start: Instant::now(),
-                thread_count,
+    pub fn run_bot<TS>(bot: Box<dyn PokerBot + Send + Sync>, addr: TS, thread_count: usize) -> Result<(), RunnerError> where TS: ToSocketAddrs {
+        Runner::run_bot_with_history(bot, addr, thread_count, None)
+    }
+
+    /// Runs a PokerBot using the Runner, and records every round it plays to `history`
+    pub fn run_bot_with_history<TS>(bot: Box<dyn PokerBot + Send + Sync>, addr: TS, thread_count: usize, history: Option<HistoryLog<File>>) -> Result<(), RunnerError> where TS: ToSocketAddrs {
+        Runner::run_bot_over(bot, transport::connect_tcp(addr)?, thread_count, history)
+    }
+
+    /// Runs a PokerBot using the Runner over any transport, and records every round it plays to `history`
+    pub fn run_bot_over<T, W>(bot: Box<dyn PokerBot + Send + Sync>, transport: T, thread_count: usize, history: Option<HistoryLog<W>>) -> Result<(), RunnerError>
+        where T: Transport, W: Write + Send + Sync + 'static {
+        // transport.set_read_timeout(Some(Duration::from_micros(READ_TIMEOUT))).expect("read_timeout call failed");
+        transport.set_write_timeout(Some(Duration::from_micros(WRITE_TIMEOUT)))?;
+        // transport.set_ttl(PACKET_TTL).expect("set_ttl call failed");
+        // transport.set_nonblocking(true).expect("set_nonblocking call failed");
+        let mut runner = Runner {
+            socket: Arc::new(Mutex::new(Socket::new(BufReader::new(Box::new(transport))))),
+            runner_start: Instant::now(),
+            thread_count,
+        };
+        runner.run(bot, history)
+    }
+
+    /// Runs a PokerBot on the calling thread instead of the thread pool, see `Runner::play_sequential`
+    pub fn run_bot_sequential<TS>(mut bot: Box<dyn PokerBot + Send + Sync>, addr: TS, history: Option<HistoryLog<File>>) -> Result<(), RunnerError> where TS: ToSocketAddrs {
+        Runner::play_sequential(&mut *bot, transport::connect_tcp(addr)?, history)
+    }
+
+    /// Plays a match on the calling thread, reading the engine's lines from `transport` and answering each one.
+    /// Every clause is handled in order as soon as its line is read, and nothing else is read until we have answered,
+    /// so nothing depends on timing: the same lines always get the same answers, as long as the bot itself is
+    /// deterministic.
+    pub fn play_sequential<B, T, H>(bot: &mut B, transport: T, history: Option<HistoryLog<H>>) -> Result<(), RunnerError>
+        where B: PokerBot + ?Sized, T: Transport, H: Write {
+        let mut stream = BufReader::new(transport);
+        let mut state = RunnerState::new(history);
+        let mut time_bank = TimeBank::new();
+        let mut line = String::new();
+        loop {
+            line.clear();
+            if stream.read_line(&mut line)? == 0 {
+                // The engine hanging up is only fine once every round has been played
+                return if state.game_state.round_num > NUM_ROUNDS {
+                    Ok(())
+                } else {
+                    Err(RunnerError::Io(io::Error::new(ErrorKind::UnexpectedEof, "engine closed the connection")))
+                };
+            }
+            for clause in protocol::decode_line(&line) {
+                let clause = match clause {
+                    Ok(Clause::GameClock(clock)) => {
+                        state.game_state.game_clock = clock;
+                        time_bank.update(clock);
+                        continue;
+                    },
+                    Ok(Clause::Quit) => return Ok(()),
+                    Ok(Clause::PlayerIndex(index)) => PreservedOrdering::SetPlayerIndex(index),
+                    Ok(Clause::Hand(hand)) => PreservedOrdering::StartRound(hand),
+                    Ok(Clause::Fold) => PreservedOrdering::Action(Action::Fold),
+                    Ok(Clause::Call) => PreservedOrdering::Action(Action::Call),
+                    Ok(Clause::Check) => PreservedOrdering::Action(Action::Check),
+                    Ok(Clause::Raise(by)) => PreservedOrdering::Action(Action::Raise(by)),
+                    Ok(Clause::Board(deck)) => PreservedOrdering::UpdateDeck(deck),
+                    Ok(Clause::Reveal(hand)) => PreservedOrdering::Reveal(hand),
+                    Ok(Clause::Delta(delta)) => PreservedOrdering::Delta(delta),
+                    Err(error) => {
+                        let error = RunnerError::from(error);
+                        debug_println!("[Runner] {} ({:?})", error, error.recovery());
+                        if error.recovery() != Recovery::Resync {
+                            continue;
+                        }
+                        PreservedOrdering::Resync
+                    },
+                };
+                if let Err(error) = Runner::apply_in_order(clause, &mut state, bot) {
+                    debug_println!("[Runner] {} ({:?})", error, error.recovery());
+                }
+            }
+            if relative_eq!(state.game_state.game_clock, 0.0, epsilon = 0.001) && state.game_state.round_num > 1 {
+                debug_println!("Out of time. Check for errors.");
+                return Err(RunnerError::Timeout);
+            }
+            // The engine waits on an answer to every line, which is just a check when we aren't acting
+            let reply = match state.round_state {
+                Some(ref rs) => Runner::bot_action(&state.game_state, rs, state.player_index, &time_bank.deadline(state.game_state.round_num), bot),
+                None => Ok(Action::Check),
             };
-            Ok(runner.run(bot))
-        } else {
-            panic!("No addresses were sent to run on");
+            let action = match reply {
+                Ok(action) => action,
+                Err(error) => {
+                    // Fold our way out of a round we lost track of, and wait for the next one
+                    debug_println!("[Runner] {} ({:?})", error, error.recovery());
+                    let _ = Runner::apply_in_order(PreservedOrdering::Resync, &mut state, bot);
+                    Action::Fold
+                },
+            };
+            writeln!(stream.get_mut(), "{}", protocol::encode_action(action))?;
+            stream.get_mut().flush()?;
+            time_bank.answered();
         }
     }
 
@@ -275,17 +428,223 @@ impl Runner {
         }
     }
 
-    /// Processes actions from the engine and never returns when called
-    fn run(&mut self, bot: Box<dyn PokerBot + Send + Sync>) {
-        let game_state = Arc::new(RwLock::new(GameState {
-            bankroll: 0,
-            game_clock: 0.0,
-            round_num: 1
-        }));
-        let round_state: Arc<RwLock<Option<RoundState>>> = Arc::new(RwLock::new(None));
-        let terminal_state: Arc<RwLock<Option<TerminalState>>> = Arc::new(RwLock::new(None));
+    // Applies one clause to our state, letting the bot know when a round starts or ends.
+    // Replays go through here too, so they see exactly what the Runner would have.
+    // Clauses that don't fit our state are left alone and returned as errors.
+    pub(super) fn handle_clause<B, W>(clause: PreservedOrdering, state: &mut RunnerState<W>, bot: &mut B) -> Result<(), RunnerError>
+        where B: PokerBot + ?Sized, W: Write {
+        let RunnerState { game_state, round_state, terminal_state, player_index, history, .. } = state;
+        match clause {
+            PreservedOrdering::Action(act) => {
+                if let Some(ref rs) = *round_state {
+                    if !rs.is_legal(act) {
+                        return Err(RunnerError::State(format!("{:?} isn't legal on street {}", act, rs.street)));
+                    }
+                    let result = rs.proceed(act);
+                    if let Some(ref mut history) = *history {
+                        history.record_action(rs, act, &result);
+                    }
+                    match result {
+                        StateResult::Round(r) => *round_state = Some(r),
+                        StateResult::Terminal(t) => {
+                            *terminal_state = Some(t);
+                        }
+                    }
+                } else {
+                    return Err(RunnerError::State(format!("{:?} without a round", act)));
+                }
+            },
+            PreservedOrdering::Delta(delta) => {
+                debug_println!("[Runner] Setting player deltas and ending round");
+                if terminal_state.is_none() {
+                    return Err(RunnerError::State(format!("Delta {} before the round ended", delta)));
+                }
+                let player_index_ = *player_index;
+                if let Some(ref tstate) = *terminal_state {
+                    let mut deltas = [-delta, -delta];
+                    deltas[player_index_] = delta;
+                    let term = TerminalState{
+                        deltas,
+                        previous: tstate.previous.clone()
+                    };
+                    *game_state = GameState {
+                        bankroll: game_state.bankroll + delta as i64,
+                        game_clock: game_state.game_clock,
+                        round_num: game_state.round_num
+                    };
+                    bot.handle_round_over(game_state, &term, player_index_);
+                    if let Some(ref mut history) = *history {
+                        if let Err(e) = history.finish_round(&term) {
+                            debug_println!("[Runner] Could not write hand history ({})", e);
+                        }
+                    }
+                    *terminal_state = Some(term);
+                    *game_state = GameState {
+                        bankroll: game_state.bankroll,
+                        game_clock: game_state.game_clock,
+                        round_num: game_state.round_num + 1
+                    };
+                    *round_state = None;
+                }
+            },
+            PreservedOrdering::StartRound(hand) => {
+                let player_index_ = *player_index;
+                let mut hands = [None, None];
+                hands[player_index_] = Some(hand);
+                let pips = [SMALL_BLIND, BIG_BLIND];
+                let stacks = [STARTING_STACK - SMALL_BLIND, STARTING_STACK - BIG_BLIND];
+                // debug_println!("[Runner] Setting player's hand and starting round");
+                let round = RoundState {
+                    button: 0,
+                    street: 0,
+                    pips,
+                    stacks,
+                    hands,
+                    deck: CardDeck(vec![]),
+                    previous: None
+                };
+                bot.handle_new_round(game_state, &round, player_index_);
+                if let Some(ref mut history) = *history {
+                    history.start_round(game_state, &round, player_index_);
+                }
+                *round_state = Some(round);
+            },
+            PreservedOrdering::Reveal(hand) => {
+                let player_index_ = *player_index;
+                if let Some(ref prs) = *round_state {
+                    let mut revised_hands = prs.hands;
+                    revised_hands[1 - player_index_] = Some(hand);
+                    // rebuild history
+                    let new_round_state = RoundState {
+                        button: prs.button,
+                        street: prs.street,
+                        pips: prs.pips,
+                        stacks: prs.stacks,
+                        hands: revised_hands,
+                        deck: prs.deck.clone(),
+                        previous: prs.previous.clone()
+                    };
+                    *terminal_state = Some(TerminalState{
+                        deltas: [0, 0],
+                        previous: new_round_state
+                    });
+                } else {
+                    return Err(RunnerError::State("Opponent's hand revealed without a round".into()));
+                }
+            },
+            PreservedOrdering::UpdateDeck(deck) => {
+                if let Some(ref rs) = *round_state {
+                    if let Some(ref mut history) = *history {
+                        history.record_board(&deck);
+                    }
+                    *round_state = Some(RoundState {
+                        button: rs.button,
+                        street: deck.0.len() as u32,
+                        pips: rs.pips,
+                        stacks: rs.stacks,
+                        hands: rs.hands,
+                        deck,
+                        previous: rs.previous.clone()
+                    })
+                } else {
+                    return Err(RunnerError::State("Board update without a round".into()));
+                }
+            },
+            PreservedOrdering::SetPlayerIndex(index) => {
+                *player_index = index
+            },
+            PreservedOrdering::Resync => {
+                *round_state = None;
+                *terminal_state = None;
+            },
+        }
+        Ok(())
+    }
+
+    // Applies clauses strictly in the order the engine sent them. After an error, we wait for the next round,
+    // and until it starts only keep track of the bankroll and round number.
+    fn apply_in_order<B, W>(clause: PreservedOrdering, state: &mut RunnerState<W>, bot: &mut B) -> Result<(), RunnerError>
+        where B: PokerBot + ?Sized, W: Write {
+        match clause {
+            PreservedOrdering::Resync => state.resyncing = true,
+            PreservedOrdering::StartRound(_) => state.resyncing = false,
+            PreservedOrdering::Delta(delta) if state.resyncing => {
+                state.game_state.bankroll += delta as i64;
+                state.game_state.round_num += 1;
+                return Ok(());
+            },
+            PreservedOrdering::SetPlayerIndex(_) => {},
+            _ if state.resyncing => return Ok(()),
+            _ => {},
+        }
+        let result = Runner::handle_clause(clause, state, bot);
+        if let Err(ref error) = result {
+            if error.recovery() == Recovery::Resync {
+                state.resyncing = true;
+                state.round_state = None;
+                state.terminal_state = None;
+            }
+        }
+        result
+    }
+
+    // Asks the bot what to do by `deadline`, unless it isn't our turn as far as we know, in which case we should fold
+    fn bot_action<B: PokerBot + ?Sized>(game_state: &GameState, round_state: &RoundState, player_index: usize, deadline: &Deadline, bot: &mut B) -> Result<Action, RunnerError> {
+        if player_index == round_state.button as usize % 2 {
+            let bot_action = bot.get_action_within(game_state, round_state, player_index, deadline);
+            if deadline.expired() {
+                debug_println!("[Runner] Bot went over its {:?} budget in round #{}", deadline.budget(), game_state.round_num);
+            }
+            Ok(Runner::legal_action(round_state, bot_action))
+        } else {
+            Err(RunnerError::OutOfTurn { round_num: game_state.round_num })
+        }
+    }
+
+    // Turns whatever the bot wants to do into something legal to send
+    pub(super) fn legal_action(round_state: &RoundState, action: Action) -> Action {
+        let legal_actions = round_state.legal_actions();
+        match action {
+            Action::Raise(raise) => if (legal_actions & ActionType::RAISE) == ActionType::RAISE {
+                let [rb_min, rb_max] = round_state.raise_bounds();
+                if raise > rb_min && raise < rb_max {
+                    Action::Raise(raise)
+                } else {
+                    if(legal_actions & ActionType::CHECK) == ActionType::CHECK {
+                        Action::Check
+                    } else {
+                        Action::Call
+                    }
+                }
+            } else {
+                if(legal_actions & ActionType::CHECK) == ActionType::CHECK {
+                    Action::Check
+                } else {
+                    Action::Call
+                }
+            },
+            Action::Check => if (legal_actions & ActionType::CHECK) == ActionType::CHECK {
+                Action::Check
+            } else {
+                Action::Fold
+            },
+            Action::Call => if (legal_actions & ActionType::CHECK) == ActionType::CHECK {
+                Action::Check
+            } else {
+                Action::Call
+            },
+            Action::Fold => if (legal_actions & ActionType::CHECK) == ActionType::CHECK {
+                Action::Check
+            } else {
+                Action::Fold
+            }
+    }
+    }
+
+    /// Processes actions from the engine until the match ends, or until something goes wrong that we can't recover from
+    fn run<W: Write + Send + Sync + 'static>(&mut self, bot: Box<dyn PokerBot + Send + Sync>, history: Option<HistoryLog<W>>) -> Result<(), RunnerError> {
+        let state = Arc::new(RwLock::new(RunnerState::new(history)));
         let bot = Arc::new(Mutex::new(bot)); // Wrap the bot in a read-write lock
-        let player_index = Arc::new(AtomicUsize::new(0usize));
         let mut pool = if self.thread_count <= MAX_THREAD_COUNT {
             ThreadPool::new(self.thread_count).unwrap()
         } else {
@@ -294,15 +653,35 @@ impl Runner {
 
         let (action_sender, action_receiver) = channel();
         let action_receiver = Arc::new(Mutex::new(action_receiver));
+        // Errors from the pool come back here, so the main thread can decide what to do about them
+        let (error_sender, error_receiver) = channel::<RunnerError>();
         //let barrier = Arc::new(Barrier::new(2));
         let mut state_change = false;
 
         loop {
+            // Reading blocks until the engine sends something, which it only does once we answered its last line.
+            // Reading while an answer is still on its way would hold on to the socket, and nothing would get sent.
             {
-                let socket = self.socket.clone();
-                pool.execute(88, move || {
-                    Runner::lock_device(&socket, "socket").sync();
-                });
+                let mut socket = Runner::lock_device(&self.socket, "socket");
+                if !socket.awaiting_answer {
+                    if let Err(e) = socket.sync() {
+                        let _ = error_sender.send(e);
+                    }
+                }
+            }
+
+            // Deal with whatever went wrong since we last checked
+            while let Ok(error) = error_receiver.try_recv() {
+                match error.recovery() {
+                    Recovery::Abort => {
+                        debug_println!("[Runner] Giving up after {}", error);
+                        pool.shutdown();
+                        return Err(error);
+                    },
+                    // We already folded, so just forget the round
+                    Recovery::SafeFold => action_sender.send(PreservedOrdering::Resync).unwrap(),
+                    recovery => debug_println!("[Runner] Recovering from {} ({:?})", error, recovery),
+                }
             }
 
             // Read from the server
@@ -316,44 +695,56 @@ impl Runner {
                     //let chr = clause.chars().nth(0).unwrap();
                     //let arg = clause.chars().skip(1).collect::<String>();
                     // Get our current state.
-                    let (game_state, round_state, terminal_state, bot, player_index) = (game_state.clone(), round_state.clone(), terminal_state.clone(), bot.clone(), player_index.clone());
+                    let state = state.clone();
                     // The main runner code is entirely run in thread pools! We reserve the main thread for getting the action from our bot and
                     // either sending or receiving it, otherwise we should update entirely asyncrously
                     let action_sender = action_sender.clone();
-                    match clause.clone() {
+                    let clause = match clause {
+                        Ok(clause) => clause,
+                        Err(error) => {
+                            let error = RunnerError::from(error);
+                            debug_println!("[Runner] {} ({:?})", error, error.recovery());
+                            if error.recovery() == Recovery::Resync {
+                                action_sender.send(PreservedOrdering::Resync).unwrap();
+                            }
+                            continue;
+                        }
+                    };
+                    match clause {
                         // Set game clock
-                        ServerAction::SetGameClock(clock) => {
-                            let mut game_state = Runner::poll_until_write(&game_state, "game");
+                        Clause::GameClock(clock) => {
+                            let mut state = Runner::poll_until_write(&state, "state");
                             debug_println!("[Runner] Setting game clock to {:.3}", clock);
-                            *game_state = GameState {
-                                bankroll: game_state.bankroll,
+                            socket.time_bank.update(clock);
+                            state.game_state = GameState {
+                                bankroll: state.game_state.bankroll,
                                 game_clock: clock,
-                                round_num: game_state.round_num
+                                round_num: state.game_state.round_num
                             };
                         },
                         // Set player index (also referred to as "active")
-                        ServerAction::SetPlayerIndex(index) => action_sender.send(PreservedOrdering::SetPlayerIndex(index)).unwrap(),
+                        Clause::PlayerIndex(index) => action_sender.send(PreservedOrdering::SetPlayerIndex(index)).unwrap(),
                         // Set our hand
-                        ServerAction::SetPlayerHand(hand) => action_sender.send(PreservedOrdering::StartRound(hand)).unwrap(),
+                        Clause::Hand(hand) => action_sender.send(PreservedOrdering::StartRound(hand)).unwrap(),
                         // Since the server doesn't tell us who did what, we have to preserve that information
                         // By preserving the order of actions, so we push them to a queue and run them all in order
 
                         // A fold action
-                        ServerAction::PlayFold => action_sender.send(PreservedOrdering::Action(Action::Fold)).unwrap(),
+                        Clause::Fold => action_sender.send(PreservedOrdering::Action(Action::Fold)).unwrap(),
                         // A call action
-                        ServerAction::PlayCall => action_sender.send(PreservedOrdering::Action(Action::Call)).unwrap(),
+                        Clause::Call => action_sender.send(PreservedOrdering::Action(Action::Call)).unwrap(),
                         // A check action
-                        ServerAction::PlayCheck => action_sender.send(PreservedOrdering::Action(Action::Check)).unwrap(),
+                        Clause::Check => action_sender.send(PreservedOrdering::Action(Action::Check)).unwrap(),
                         // A raise action
-                        ServerAction::PlayRaise(by) => action_sender.send(PreservedOrdering::Action(Action::Raise(by))).unwrap(),
+                        Clause::Raise(by) => action_sender.send(PreservedOrdering::Action(Action::Raise(by))).unwrap(),
                         // The deck was updated
-                        ServerAction::UpdateDeck(deck) => action_sender.send(PreservedOrdering::UpdateDeck(deck)).unwrap(),
+                        Clause::Board(deck) => action_sender.send(PreservedOrdering::UpdateDeck(deck)).unwrap(),
                         // Reveal the opponent's hand
-                        ServerAction::RevealOpponentHand(hand) => action_sender.send(PreservedOrdering::Reveal(hand)).unwrap(),
+                        Clause::Reveal(hand) => action_sender.send(PreservedOrdering::Reveal(hand)).unwrap(),
                         // Delta has been calculated
-                        ServerAction::Delta(delta) => action_sender.send(PreservedOrdering::Delta(delta)).unwrap(),
+                        Clause::Delta(delta) => action_sender.send(PreservedOrdering::Delta(delta)).unwrap(),
                         // End the game
-                        ServerAction::Quit => {pool.shutdown(); return},
+                        Clause::Quit => {pool.shutdown(); return Ok(())},
                     }
                 }
             }
@@ -365,116 +756,16 @@ impl Runner {
                 {
                     // let barrier = barrier.clone();
                     let action_receiver = action_receiver.clone();
-                    let (game_state, round_state, terminal_state, bot, player_index, socket) =
-                        (game_state.clone(), round_state.clone(), terminal_state.clone(), bot.clone(), player_index.clone(), self.socket.clone());
+                    let (state, bot, error_sender) = (state.clone(), bot.clone(), error_sender.clone());
                     pool.execute(69, move || {
-                        let mut round_state = Runner::poll_until_write(&round_state, "round");
-                        let mut game_state = Runner::poll_until_write(&game_state, "game");
-                        let mut terminal_state = Runner::poll_until_write(&terminal_state, "terminal");
+                        let mut state = Runner::poll_until_write(&state, "state");
                         let mut bot = Runner::lock_device(&bot, "bot");
                         let action_queue = Runner::lock_device(&action_receiver, "actions");
                         // Receive as many actions as possible, but don't block on it.
                         while let Ok(action) = action_queue.try_recv() {
                             debug_println!("[Runner] Running action {:?}", action);
-                            match action {
-                                PreservedOrdering::Action(act) => {
-                                    if let Some(ref rs) = *round_state {
-                                        match rs.proceed(act) {
-                                            StateResult::Round(r) => *round_state = Some(r),
-                                            StateResult::Terminal(t) => {
-                                                *terminal_state = Some(t);
-                                            }
-                                        }
-                                    } else {
-                                        panic!("Round state must exist for action {:?}", action);
-                                    }
-                                },
-                                PreservedOrdering::Delta(delta) => {
-                                    debug_println!("[Runner] Setting player deltas and ending round");
-                                    assert!(terminal_state.is_some());
-                                    let player_index_ = player_index.load(PLAYER_INDEX_LOAD_ORDERING);
-                                    if let Some(ref tstate) = *terminal_state {
-                                        let mut deltas = [-delta, -delta];
-                                        deltas[player_index_] = delta;
-                                        let term = TerminalState{
-                                            deltas,
-                                            previous: tstate.previous.clone()
-                                        };
-                                        *game_state = GameState {
-                                            bankroll: game_state.bankroll + delta as i64,
-                                            game_clock: game_state.game_clock,
-                                            round_num: game_state.round_num
-                                        };
-                                        bot.handle_round_over(&*game_state, &term, player_index_);
-                                        *terminal_state = Some(term);
-                                        *game_state = GameState {
-                                            bankroll: game_state.bankroll,
-                                            game_clock: game_state.game_clock,
-                                            round_num: game_state.round_num + 1
-                                        };
-                                        *round_state = None;
-                                    }
-                                },
-                                PreservedOrdering::StartRound(hand) => {
-                                    let player_index_ = player_index.load(PLAYER_INDEX_LOAD_ORDERING);
-                                    let mut hands = [None, None];
-                                    hands[player_index_] = Some(hand);
-                                    let pips = [SMALL_BLIND, BIG_BLIND];
-                                    let stacks = [STARTING_STACK - SMALL_BLIND, STARTING_STACK - BIG_BLIND];
-                                    // debug_println!("[Runner] Setting player's hand and starting round");
-                                    let round = RoundState {
-                                        button: 0,
-                                        street: 0,
-                                        pips,
-                                        stacks,
-                                        hands,
-                                        deck: CardDeck(vec![]),
-                                        previous: None
-                                    };
-                                    bot.handle_new_round(&*game_state, &round, player_index_);
-                                    *round_state = Some(round);
-                                },
-                                PreservedOrdering::Reveal(hand) => {
-                                    let player_index_ = player_index.load(PLAYER_INDEX_LOAD_ORDERING);
-                                    if let Some(ref prs) = *round_state {
-                                        let mut revised_hands = prs.hands;
-                                        revised_hands[1 - player_index_] = Some(hand);
-                                        // rebuild history
-                                        let new_round_state = RoundState {
-                                            button: prs.button,
-                                            street: prs.street,
-                                            pips: prs.pips,
-                                            stacks: prs.stacks,
-                                            hands: revised_hands,
-                                            deck: prs.deck.clone(),
-                                            previous: prs.previous.clone()
-                                        };
-                                        *terminal_state = Some(TerminalState{
-                                            deltas: [0, 0],
-                                            previous: new_round_state
-                                        });
-                                    } else {
-                                        panic!("Round state must exists for reveal")
-                                    }
-                                },
-                                PreservedOrdering::UpdateDeck(deck) => {
-                                    if let Some(ref rs) = *round_state {
-                                        *round_state = Some(RoundState {
-                                            button: rs.button,
-                                            street: deck.0.len() as u32,
-                                            pips: rs.pips,
-                                            stacks: rs.stacks,
-                                            hands: rs.hands,
-                                            deck,
-                                            previous: rs.previous.clone()
-                                        })
-                                    } else {
-                                        panic!("Round state must exist for this action")
-                                    }
-                                },
-                                PreservedOrdering::SetPlayerIndex(index) => {
-                                    player_index.store(index, PLAYER_INDEX_STOR_ORDERING)
-                                },
+                            if let Err(error) = Runner::apply_in_order(action, &mut state, &mut **bot) {
+                                let _ = error_sender.send(error);
                             }
                         }
 
@@ -484,68 +775,40 @@ impl Runner {
                 {
                     let socket = self.socket.clone();
                     // let barrier = barrier.clone();
-                    let (game_state, round_state, bot, player_index) = (game_state.clone(), round_state.clone(), bot.clone(), player_index.clone());
+                    let (state, bot, error_sender) = (state.clone(), bot.clone(), error_sender.clone());
                     pool.execute(9, move || {
                         // Acquire the round state if it is available, but DO NOT BLOCK ( but maybe block the socket for a bit... )
                         // let mut socket = socket.lock().unwrap();
                         //barrier.wait();
                         let mut socket = Runner::lock_device(&socket, "socket");
-                        let round_state = Runner::poll_until_read(&round_state, "round");
-                        let game_state = Runner::poll_until_read(&game_state, "game");
+                        let state = Runner::poll_until_read(&state, "state");
                         // We haven't tried to send anything
 
 
-                        if let Some(ref round_state) = *round_state {
-                            let player_index = player_index.load(PLAYER_INDEX_LOAD_ORDERING);
-                            assert!(player_index == round_state.button as usize % 2);
+                        let sent = if let Some(ref round_state) = state.round_state {
                             // if we can make an action, do so, unless we already have done so.
                             if !socket.round_sent.load(Ordering::SeqCst) {
                                 socket.round_sent.store(true, Ordering::Relaxed);
                                 let mut bot = Runner::lock_device(&bot, "bot");
-                                let bot_action = bot.get_action(&*game_state, round_state, player_index);
-                                let legal_actions = round_state.legal_actions();
-                                let action = match bot_action {
-                                    Action::Raise(raise) => if (legal_actions & ActionType::RAISE) == ActionType::RAISE {
-                                        let [rb_min, rb_max] = round_state.raise_bounds();
-                                        if raise > rb_min && raise < rb_max {
-                                            Action::Raise(raise)
-                                        } else {
-                                            if(legal_actions & ActionType::CHECK) == ActionType::CHECK {
-                                                Action::Check
-                                            } else {
-                                                Action::Call
-                                            }
-                                        }
-                                    } else {
-                                        if(legal_actions & ActionType::CHECK) == ActionType::CHECK {
-                                            Action::Check
-                                        } else {
-                                            Action::Call
-                                        }
-                                    },
-                                    Action::Check => if (legal_actions & ActionType::CHECK) == ActionType::CHECK {
-                                        Action::Check
-                                    } else {
-                                        Action::Fold
-                                    },
-                                    Action::Call => if (legal_actions & ActionType::CHECK) == ActionType::CHECK {
-                                        Action::Check
-                                    } else {
-                                        Action::Call
-                                    },
-                                    Action::Fold => if (legal_actions & ActionType::CHECK) == ActionType::CHECK {
-                                        Action::Check
-                                    } else {
-                                        Action::Fold
-                                    }
-                                };
-                                socket.send(action);
+                                let deadline = socket.time_bank.deadline(state.game_state.round_num);
+                                let action = Runner::bot_action(&state.game_state, round_state, state.player_index, &deadline, &mut **bot).unwrap_or_else(|error| {
+                                    let _ = error_sender.send(error);
+                                    Action::Fold
+                                });
+                                socket.send(action)
+                            } else {
+                                Ok(())
                             }
                         } else {
                             if !socket.round_sent.load(Ordering::SeqCst) {
                                 socket.round_sent.store(true, Ordering::SeqCst);
-                                socket.ping();
+                                socket.ping()
+                            } else {
+                                Ok(())
                             }
+                        };
+                        if let Err(e) = sent {
+                            let _ = error_sender.send(e);
                         }
                     });
                 }
@@ -555,13 +818,16 @@ impl Runner {
 
             // Check of we timed out before letting anything lock or poll_until_write
             {
-                let game_state = Runner::poll_until_read(&game_state, "game");
-                let round_state = Runner::poll_until_read(&round_state, "round");
-                if (relative_eq!(game_state.game_clock, 0.0, epsilon = 0.001)  && game_state.round_num > 1)
-                    || Instant::now() - self.runner_start > Duration::from_secs(COMP_TIME)
-                    || game_state.round_num == 1001 && round_state.is_none() {
+                // Keeping the engine waiting for longer than our clock had left means it already gave up on us
+                let spent = Runner::lock_device(&self.socket, "socket").time_bank.is_spent();
+                let state = Runner::poll_until_read(&state, "state");
+                if (relative_eq!(state.game_state.game_clock, 0.0, epsilon = 0.001)  && state.game_state.round_num > 1) || spent {
+                    debug_println!("Out of time. Check for errors.");
+                    return Err(RunnerError::Timeout);
+                }
+                if state.game_state.round_num == 1001 && state.round_state.is_none() {
                     debug_println!("Game over. Check for errors.");
-                    return;
+                    return Ok(());
                 }
             }
 
@@ -577,3 +843,116 @@ impl Drop for Runner {
         println!("[Runner] Ran for {:?}", runtime);
     }
 }
+
+#[test]
+fn runner_error_test() {
+    use crate::into_cards;
+    use super::cards::Card;
+    // Anything that doesn't touch the round can just be skipped, anything else means we lost track of it
+    let recovery = |clause: &str| clause.parse::<Clause>().err().map(|e| RunnerError::from(e).recovery());
+    assert_eq!(recovery("T"), Some(Recovery::SkipClause));
+    assert_eq!(recovery("Z12"), Some(Recovery::SkipClause));
+    assert_eq!(recovery("P2"), Some(Recovery::Resync));
+    assert_eq!(recovery("HAh,Kd,Qs"), Some(Recovery::Resync));
+    assert_eq!(recovery("HAh,Xd"), Some(Recovery::Resync));
+    assert_eq!(recovery("Rten"), Some(Recovery::Resync));
+    assert_eq!(recovery("B2c"), Some(Recovery::Resync));
+    assert_eq!(RunnerError::OutOfTurn { round_num: 1 }.recovery(), Recovery::SafeFold);
+    assert_eq!(RunnerError::Timeout.recovery(), Recovery::Abort);
+
+    // Clauses out of place leave the state alone instead of panicking
+    struct Idle;
+    impl PokerBot for Idle {
+        fn handle_new_round(&mut self, _: &GameState, _: &RoundState, _: usize) {}
+        fn handle_round_over(&mut self, _: &GameState, _: &TerminalState, _: usize) {}
+        fn get_action(&mut self, _: &GameState, _: &RoundState, _: usize) -> Action { Action::Check }
+    }
+    let mut state = RunnerState::new(None::<HistoryLog<io::Sink>>);
+    let mut apply = |clause| Runner::handle_clause(clause, &mut state, &mut Idle);
+    assert_eq!(apply(PreservedOrdering::Action(Action::Call)).unwrap_err().recovery(), Recovery::Resync);
+    assert_eq!(apply(PreservedOrdering::Delta(2)).unwrap_err().recovery(), Recovery::Resync);
+    let cards = into_cards!("Ah,Kd");
+    apply(PreservedOrdering::StartRound(CardHand([cards[0], cards[1]]))).unwrap();
+    assert_eq!(apply(PreservedOrdering::Action(Action::Raise(1000))).unwrap_err().recovery(), Recovery::Resync);
+    apply(PreservedOrdering::Action(Action::Fold)).unwrap();
+    apply(PreservedOrdering::Delta(-1)).unwrap();
+    assert_eq!((state.game_state.bankroll, state.game_state.round_num), (-1, 2));
+}
+
+#[test]
+fn sequential_runner_test() {
+    use super::bot::CallingStation;
+    use super::transport::ChannelTransport;
+    // We are the small blind, and call down a river bet. Then we are the big blind, and the small blind folds.
+    // The clause the engine would never send is skipped.
+    let engine = "T30.000 P0 HAh,Kd
+T29.990 C K B2c,5d,9h K
+T29.980 K Z9 B2c,5d,9h,Js K
+T29.970 K B2c,5d,9h,Js,Ts R20
+T29.960 C OQs,Qd D-22
+T29.950 P1 H2c,7d F D1
+Q
+";
+    // The engine's end gets every line up front, and hangs up once it has sent them
+    let play = |engine: &str| {
+        let (mut engine_end, bot_end) = ChannelTransport::pair();
+        engine_end.write_all(engine.as_bytes()).unwrap();
+        Transport::shutdown(&mut engine_end).unwrap();
+        let mut bot = CallingStation::default();
+        let result = Runner::play_sequential(&mut bot, bot_end, None::<HistoryLog<io::Sink>>);
+        let mut replies = String::new();
+        engine_end.read_to_string(&mut replies).unwrap();
+        (result, replies, bot.decisions, bot.deltas)
+    };
+    let (result, replies, decisions, deltas) = play(engine);
+    assert!(result.is_ok());
+    assert_eq!(replies, "C\nK\nK\nC\nK\nK\n");
+    assert_eq!(decisions, [(1, 0, 0), (1, 3, 0), (1, 4, 0), (1, 5, 0)]);
+    assert_eq!(deltas, [-22, 1]);
+    let (_, replies_again, decisions_again, deltas_again) = play(engine);
+    assert_eq!((replies_again, decisions_again, deltas_again), (replies, decisions, deltas));
+
+    // A broken round gets folded, and the next one is played as usual
+    let engine = "T30.000 P0 HAh,Kd
+T29.990 C K B2c,5d K
+T29.980 K Z9 B2c,5d,9h,Js K
+T29.970 F D-2
+T29.960 P0 H2c,7d
+Q
+";
+    let (result, replies, _, _) = play(engine);
+    assert!(result.is_ok());
+    assert_eq!(replies, "C\nK\nK\nK\nC\n");
+    // Without a Q, the engine hanging up early is an error
+    let (result, replies, _, _) = play("T30.000 P0 HAh,Kd\n");
+    assert_eq!(replies, "C\n");
+    assert_eq!(result.unwrap_err().recovery(), Recovery::Abort);
+}
+
+#[test]
+fn threaded_runner_test() {
+    use super::bot::CallingStation;
+    use super::transport::ChannelTransport;
+    // Same match as in sequential_runner_test, but the engine sends one line at a time and waits for the answer
+    let engine = "T30.000 P0 HAh,Kd
+T29.990 C K B2c,5d,9h K
+T29.980 K Z9 B2c,5d,9h,Js K
+T29.970 K B2c,5d,9h,Js,Ts R20
+T29.960 C OQs,Qd D-22
+T29.950 P1 H2c,7d F D1
+Q";
+    let (engine_end, bot_end) = ChannelTransport::pair();
+    let runner = thread::spawn(move || {
+        Runner::run_bot_over(Box::new(CallingStation::default()), bot_end, 1, Some(HistoryLog::new(vec![], super::history::DEFAULT_MAX_BYTES).unwrap()))
+    });
+    let mut engine_end = BufReader::new(engine_end);
+    let mut replies = String::new();
+    for line in engine.lines() {
+        writeln!(engine_end.get_mut(), "{}", line).unwrap();
+        if line != "Q" {
+            engine_end.read_line(&mut replies).unwrap();
+        }
+    }
+    assert!(runner.join().unwrap().is_ok());
+    assert_eq!(replies, "C\nK\nK\nC\nK\nK\n");
+}
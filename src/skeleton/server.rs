@@ -0,0 +1,208 @@
+//! A local stand-in for the engine server. Bots connect over TCP and get exactly the clauses the
+//! engine would send them (T, P, H, F, C, K, R, B, O, D and Q), while a Dealer runs the match.
+
+use std::net::{TcpListener, TcpStream, SocketAddr, ToSocketAddrs, Shutdown};
+use std::io::{prelude::*, BufReader};
+use std::time::Duration;
+use super::dealer::{Dealer, MatchResult, Seat};
+use super::actions::Action;
+use super::states::{GameState, RoundState, TerminalState};
+use super::cards::CardDeck;
+use super::protocol::Clause;
+use crate::debug_println;
+use itertools::Itertools;
+
+// Give a bot at least this long to answer, even when its game clock is nearly gone
+const MIN_READ_TIMEOUT: u64 = 1; // milliseconds
+
+/// Waits for two bots to connect and deals a match between them
+pub struct Server {
+    listener: TcpListener,
+    seed: u64,
+}
+
+impl Server {
+    pub fn bind<A: ToSocketAddrs>(addr: A, seed: u64) -> std::io::Result<Server> {
+        Ok(Server {
+            listener: TcpListener::bind(addr)?,
+            seed,
+        })
+    }
+
+    pub fn local_addr(&self) -> std::io::Result<SocketAddr> {
+        self.listener.local_addr()
+    }
+
+    /// Accepts two connections and plays `rounds` rounds between them.
+    /// The first bot to connect is the small blind on the first round.
+    pub fn run(&self, rounds: u32) -> std::io::Result<MatchResult> {
+        let mut first = self.accept()?;
+        let mut second = self.accept()?;
+        Ok(Dealer::new(self.seed).play_match(&mut first, &mut second, rounds))
+    }
+
+    fn accept(&self) -> std::io::Result<RemoteSeat> {
+        let (stream, addr) = self.listener.accept()?;
+        stream.set_nodelay(true)?;
+        debug_println!("[Server] Bot connected from {}", addr);
+        Ok(RemoteSeat::new(stream))
+    }
+}
+
+/// A bot on the other end of a socket. Like the engine, it queues up clauses and only sends them
+/// when the bot has to respond, either with an action or an acknowledgement at the end of a round.
+struct RemoteSeat {
+    stream: BufReader<TcpStream>,
+    clauses: Vec<Clause>,
+    responsive: bool,
+}
+
+impl RemoteSeat {
+    fn new(stream: TcpStream) -> RemoteSeat {
+        RemoteSeat {
+            stream: BufReader::new(stream),
+            clauses: vec![],
+            responsive: true,
+        }
+    }
+
+    // Sends every pending clause after the game clock and waits (at most as long as the clock allows) for a reply.
+    // Any kind of failure, including timing out, means the bot won't be asked anything again.
+    fn query(&mut self, game_clock: f32) -> Option<String> {
+        if !self.responsive {
+            return None;
+        }
+        let message = Some(Clause::GameClock(game_clock)).into_iter().chain(self.clauses.drain(..)).join(" ");
+        let timeout = Duration::from_secs_f32(game_clock).max(Duration::from_millis(MIN_READ_TIMEOUT));
+        let mut reply = String::new();
+        let result = self.stream.get_ref().set_read_timeout(Some(timeout))
+            .and_then(|_| writeln!(self.stream.get_mut(), "{}", message))
+            .and_then(|_| self.stream.get_mut().flush())
+            .and_then(|_| self.stream.read_line(&mut reply));
+        match result {
+            Ok(0) => {
+                debug_println!("[Server] Bot disconnected");
+                self.responsive = false;
+                None
+            },
+            Ok(_) => Some(reply.trim().to_string()),
+            Err(e) => {
+                debug_println!("[Server] Bot stopped responding ({})", e);
+                self.responsive = false;
+                None
+            }
+        }
+    }
+}
+
+// Turns a bot's reply into an action. Anything we can't read comes back as a fold,
+// which the dealer turns into a check when that is legal, just like the engine does.
+fn parse_action(reply: &str) -> Action {
+    match reply.parse::<Clause>().map(|clause| clause.action()) {
+        Ok(Some(action)) => action,
+        _ => {
+            debug_println!("[Server] Misformatted reply {:?}", reply);
+            Action::Fold
+        }
+    }
+}
+
+impl Seat for RemoteSeat {
+    fn handle_new_round(&mut self, _: &GameState, rs: &RoundState, player_index: usize) {
+        self.clauses.push(Clause::PlayerIndex(player_index));
+        if let Some(hand) = rs.hands[player_index] {
+            self.clauses.push(Clause::Hand(hand));
+        }
+    }
+
+    fn handle_round_over(&mut self, gs: &GameState, ts: &TerminalState, player_index: usize) {
+        if let Some(hand) = ts.previous.hands[1 - player_index] {
+            self.clauses.push(Clause::Reveal(hand));
+        }
+        self.clauses.push(Clause::Delta(ts.deltas[player_index]));
+        // The reply is only an acknowledgement
+        self.query(gs.game_clock);
+    }
+
+    fn get_action(&mut self, gs: &GameState, _: &RoundState, _: usize) -> Action {
+        self.query(gs.game_clock).map(|reply| parse_action(&reply)).unwrap_or(Action::Fold)
+    }
+
+    fn observe_action(&mut self, action: Action) {
+        self.clauses.push(Clause::from(action));
+    }
+
+    fn observe_board(&mut self, board: &CardDeck) {
+        self.clauses.push(Clause::Board(board.clone()));
+    }
+
+    fn handle_match_over(&mut self) {
+        if self.responsive {
+            let _ = writeln!(self.stream.get_mut(), "{}", Clause::Quit);
+        }
+        let _ = self.stream.get_mut().shutdown(Shutdown::Both);
+    }
+
+    fn is_responsive(&self) -> bool {
+        self.responsive
+    }
+}
+
+#[test]
+fn server_protocol_test() {
+    use std::thread;
+    let server = Server::bind("127.0.0.1:0", 176).unwrap();
+    let addr = server.local_addr().unwrap();
+    let dealer = thread::spawn(move || server.run(2).unwrap());
+    // Both bots fold whenever they are asked to act, and record everything the server sends
+    let bots: Vec<_> = (0..2).map(|_| TcpStream::connect(addr).unwrap()).collect();
+    let bots: Vec<_> = bots.into_iter().map(|stream| thread::spawn(move || {
+        let mut stream = BufReader::new(stream);
+        let mut messages = vec![];
+        loop {
+            let mut line = String::new();
+            if stream.read_line(&mut line).unwrap() == 0 {
+                break messages;
+            }
+            let clauses: Vec<String> = line.split_whitespace().map(|clause| clause[..1].to_string()).collect();
+            messages.push(clauses.concat());
+            if clauses.contains(&"Q".to_string()) {
+                break messages;
+            }
+            writeln!(stream.get_mut(), "{}", if clauses.contains(&"D".to_string()) { "K" } else { "F" }).unwrap();
+        }
+    })).collect();
+    let messages: Vec<Vec<String>> = bots.into_iter().map(|bot| bot.join().unwrap()).collect();
+    // Small blind folds straight away, big blind learns about it at the end of the round
+    assert_eq!(messages[0], ["TPH", "TFD", "TPHFD", "Q"]);
+    assert_eq!(messages[1], ["TPHFD", "TPH", "TFD", "Q"]);
+    let result = dealer.join().unwrap();
+    assert_eq!(result.bankrolls, [0, 0]);
+}
+
+#[test]
+fn server_runner_test() {
+    use super::bot::CallingStation;
+    use super::history::HistoryLog;
+    use super::runner::Runner;
+    use std::{io, thread};
+    let server = Server::bind("127.0.0.1:0", 176).unwrap();
+    let addr = server.local_addr().unwrap();
+    let dealer = thread::spawn(move || server.run(50).unwrap());
+    // Connect in order, so the first bot is the first seat
+    let streams: Vec<_> = (0..2).map(|_| super::transport::connect_tcp(addr).unwrap()).collect();
+    let bots: Vec<_> = streams.into_iter().map(|stream| thread::spawn(move || {
+        let mut bot = CallingStation::default();
+        let result = Runner::play_sequential(&mut bot, stream, None::<HistoryLog<io::Sink>>);
+        (result, bot)
+    })).collect();
+    let bots: Vec<_> = bots.into_iter().map(|bot| bot.join().unwrap()).collect();
+    let result = dealer.join().unwrap();
+    for (seat, (played, bot)) in bots.iter().enumerate() {
+        assert!(played.is_ok(), "seat {} failed: {:?}", seat, played);
+        assert_eq!(bot.rounds.len(), 50);
+        assert_eq!(bot.deltas.iter().map(|&delta| delta as i64).sum::<i64>(), result.bankrolls[seat]);
+        assert!(result.game_clocks[seat] > 0.0);
+    }
+    assert_eq!(result.bankrolls[0] + result.bankrolls[1], 0);
+}
@@ -1,16 +1,21 @@
 use super::{
     actions::{ActionType, Action},
-    cards::{CardDeck, CardHand}
+    cards::{Card, CardDeck, CardHand}
 };
-use std::cmp::{min, max};
+use crate::engine::showdown::ShowdownEngine;
+use std::cmp::{min, max, Ordering};
+#[cfg(feature = "serde")]
+use serde::{Serialize, Deserialize};
 
 pub const NUM_ROUNDS: u32 = 1000;
 pub const STARTING_STACK: u32 = 200;
 pub const BIG_BLIND: u32 = 2;
 pub const SMALL_BLIND: u32 = 1;
+pub const STARTING_GAME_CLOCK: f32 = 30.0;
 
 /// Encodes overall game progress
 #[derive(Debug, Clone, Copy)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub struct GameState {
     pub bankroll: i64,
     pub game_clock: f32,
@@ -19,13 +24,16 @@ pub struct GameState {
 
 /// Final state of a poker round corresponding to payoffs
 #[derive(Debug)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub struct TerminalState {
     pub deltas: [i32; 2],
     pub previous: RoundState,
 }
 
-/// Encodes the game tree for one round of poker
+/// Encodes the game tree for one round of poker. With the `serde` feature, the whole `previous` chain is serialized
+/// along with it.
 #[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub struct RoundState {
     pub button: u32,
     pub street: u32,
@@ -44,9 +52,28 @@ pub enum StateResult {
 }
 
 impl RoundState {
-    /// Compares the players' hands and computes payoffs.
-    pub fn showdown(&self) -> TerminalState {
-        TerminalState { previous: self.clone(), deltas: [0, 0] }
+    /// Compares the players' hands (kickers included) and computes payoffs.
+    /// Both hands and the whole board have to be known to settle the pot, so if either hand is hidden or the
+    /// river hasn't been dealt yet, the deltas are left at zero.
+    pub fn showdown(&self, engine: &ShowdownEngine) -> TerminalState {
+        let board = &self.deck.0[..min(self.street as usize, self.deck.0.len())];
+        let delta = match self.hands {
+            [Some(first), Some(second)] if board.len() == 5 => {
+                let best_hand = |hand: CardHand| {
+                    let cards: Vec<Card> = hand.0.iter().chain(board.iter()).copied().collect();
+                    engine.rank_hand(&cards)
+                };
+                match best_hand(first).cmp(&best_hand(second)) {
+                    // The winner takes what the loser put in the pot
+                    Ordering::Greater => STARTING_STACK as i32 - self.stacks[1] as i32,
+                    Ordering::Less => self.stacks[0] as i32 - STARTING_STACK as i32,
+                    // Split the pot
+                    Ordering::Equal => (self.stacks[0] as i32 - self.stacks[1] as i32) / 2,
+                }
+            },
+            _ => 0
+        };
+        TerminalState { previous: self.clone(), deltas: [delta, -delta] }
     }
 
     /// Returns a mask which corresponds to the active player's legal moves.
@@ -66,6 +93,20 @@ impl RoundState {
         return ActionType::FOLD | ActionType::CALL | ActionType::RAISE;
     }
 
+    /// Whether the active player is allowed to take `action`
+    pub fn is_legal(&self, action: Action) -> bool {
+        let legal_actions = self.legal_actions();
+        match action {
+            Action::Raise(amount) => {
+                let [min_raise, max_raise] = self.raise_bounds();
+                (legal_actions & ActionType::RAISE) == ActionType::RAISE && amount >= min_raise && amount <= max_raise
+            },
+            Action::Fold => (legal_actions & ActionType::FOLD) == ActionType::FOLD,
+            Action::Call => (legal_actions & ActionType::CALL) == ActionType::CALL,
+            Action::Check => (legal_actions & ActionType::CHECK) == ActionType::CHECK,
+        }
+    }
+
     /// Returns an array of the minimum and maximum legal raises
     pub fn raise_bounds(&self) -> [u32; 2] {
         let active: usize = self.button as usize % 2;
@@ -80,7 +121,9 @@ impl RoundState {
         // Don't rely on street's value as that update could happen before or after updating the deck,
         // Look at decks length, however, before showdown, make sure decks length is synced with streets length
         if self.street == 5 {
-            return StateResult::Terminal(self.showdown());
+            // Nobody folded, so the cards decide who gets the pot. That needs the secret value ordering,
+            // so the deltas stay at zero until they are settled with `showdown` (or by the engine's D clause).
+            return StateResult::Terminal(TerminalState { previous: self.clone(), deltas: [0, 0] });
         }
         let new_street = self.deck.0.len() as u32;
         StateResult::Round(RoundState {
@@ -99,14 +142,14 @@ impl RoundState {
         let active: usize = self.button as usize % 2;
         match action {
             Action::Fold => {
-                let delta;
-                if active == 0 {
-                    delta = self.stacks[0].saturating_sub(STARTING_STACK)
+                // The folding player forfeits whatever they have already put in the pot
+                let delta = if active == 0 {
+                    self.stacks[0] as i32 - STARTING_STACK as i32
                 } else {
-                    delta = STARTING_STACK - self.stacks[1]
-                }
+                    STARTING_STACK as i32 - self.stacks[1] as i32
+                };
                 StateResult::Terminal(TerminalState{
-                    deltas: [delta as i32, -(delta as i32)],
+                    deltas: [delta, -delta],
                     previous: self.clone()
                 })
             },
@@ -173,3 +216,82 @@ impl RoundState {
         }
     }
 }
+
+#[test]
+fn showdown_settlement_test() {
+    use crate::{into_cards, into_ordering, skeleton::cards::CardValue};
+    let engine = ShowdownEngine::new(into_ordering!("2,3,4,5,6,7,8,9,T,J,Q,K,A"));
+    let round = |first: &str, second: &str, board: &str| {
+        let (first, second) = (into_cards!(first), into_cards!(second));
+        RoundState {
+            button: 3,
+            street: 5,
+            pips: [0, 0],
+            stacks: [STARTING_STACK - 20, STARTING_STACK - 20],
+            hands: [Some(CardHand([first[0], first[1]])), Some(CardHand([second[0], second[1]]))],
+            deck: CardDeck(into_cards!(board)),
+            previous: None,
+        }
+    };
+    assert_eq!(round("Ah,Ad", "2c,7d", "3s,5h,9c,Jd,Kh").showdown(&engine).deltas, [20, -20]);
+    assert_eq!(round("2c,7d", "Ah,Ad", "3s,5h,9c,Jd,Kh").showdown(&engine).deltas, [-20, 20]);
+    // The board plays for both players
+    assert_eq!(round("2c,3d", "2h,3h", "Ts,Jh,Qc,Kd,Ac").showdown(&engine).deltas, [0, 0]);
+    // Same pair, the kicker decides
+    assert_eq!(round("Ah,Kd", "Ad,Qc", "As,5h,9c,2d,3h").showdown(&engine).deltas, [20, -20]);
+    // Nothing to settle while a hand is still hidden
+    let mut hidden = round("Ah,Ad", "2c,7d", "3s,5h,9c,Jd,Kh");
+    hidden.hands[1] = None;
+    assert_eq!(hidden.showdown(&engine).deltas, [0, 0]);
+    // or before the river
+    let mut turn = round("Ah,Ad", "2c,7d", "3s,5h,9c,Jd");
+    turn.street = 4;
+    assert_eq!(turn.showdown(&engine).deltas, [0, 0]);
+    turn.street = 0;
+    assert_eq!(turn.showdown(&engine).deltas, [0, 0]);
+}
+
+#[cfg(feature = "serde")]
+#[test]
+fn state_serde_test() {
+    use crate::into_cards;
+    let hand = |cards: &str| { let cards = into_cards!(cards); CardHand([cards[0], cards[1]]) };
+    let start = RoundState {
+        button: 0,
+        street: 0,
+        pips: [SMALL_BLIND, BIG_BLIND],
+        stacks: [STARTING_STACK - SMALL_BLIND, STARTING_STACK - BIG_BLIND],
+        hands: [Some(hand("Ah,Ad")), None],
+        deck: CardDeck(into_cards!("2c,5d,9h")),
+        previous: None,
+    };
+    let round = |state: StateResult| match state {
+        StateResult::Round(state) => state,
+        StateResult::Terminal(_) => panic!("round ended early"),
+    };
+    let state = round(start.proceed(Action::Call));
+    let state = round(state.proceed(Action::Raise(6)));
+    let state = round(state.proceed(Action::Call));
+    let ts = match state.proceed(Action::Fold) {
+        StateResult::Terminal(ts) => ts,
+        StateResult::Round(_) => panic!("fold didn't end the round"),
+    };
+    // Debug prints every field (and the whole chain), so equal prints mean an exact round-trip
+    let json = serde_json::to_string(&ts).unwrap();
+    let from_json: TerminalState = serde_json::from_str(&json).unwrap();
+    assert_eq!(format!("{:?}", from_json), format!("{:?}", ts));
+    assert_eq!(serde_json::to_string(&from_json).unwrap(), json);
+    let bytes = bincode::serialize(&ts).unwrap();
+    let from_bytes: TerminalState = bincode::deserialize(&bytes).unwrap();
+    assert_eq!(format!("{:?}", from_bytes), format!("{:?}", ts));
+    assert_eq!(bincode::serialize(&from_bytes).unwrap(), bytes);
+    assert!(json.len() > bytes.len());
+
+    let gs = GameState { bankroll: -12, game_clock: 29.5, round_num: 3 };
+    let gs: GameState = serde_json::from_str(&serde_json::to_string(&gs).unwrap()).unwrap();
+    assert_eq!((gs.bankroll, gs.game_clock, gs.round_num), (-12, 29.5, 3));
+    for legal in [ActionType::FOLD | ActionType::CALL, ActionType::CHECK | ActionType::RAISE].iter() {
+        let bytes = bincode::serialize(legal).unwrap();
+        assert_eq!(bincode::deserialize::<ActionType>(&bytes).unwrap(), *legal);
+    }
+}
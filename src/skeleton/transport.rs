@@ -0,0 +1,218 @@
+//! What the Runner talks to the engine over. The engine itself connects over TCP, but anything that can read and
+//! write bytes works: Unix domain sockets, our own stdin and stdout, or a pair of in-memory channels, which lets tests
+//! and scripted opponents drive the Runner in-process without opening any ports.
+
+use std::io::{self, Read, Write, ErrorKind};
+use std::net::{TcpStream, Shutdown, ToSocketAddrs};
+#[cfg(unix)]
+use std::os::unix::net::UnixStream;
+use std::sync::mpsc::{channel, Receiver, Sender};
+use std::time::Duration;
+
+const CONNECT_TIMEOUT: u64 = 10; // seconds
+
+/// A connection to the engine
+pub trait Transport: Read + Write + Send + 'static {
+    /// Stops sending, so the other end sees the connection close
+    fn shutdown(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+
+    /// Takes an error the connection has been holding on to, if there is one
+    fn take_error(&self) -> io::Result<Option<io::Error>> {
+        Ok(None)
+    }
+
+    /// Limits how long a write is allowed to block, if the connection supports it
+    fn set_write_timeout(&self, _: Option<Duration>) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl Transport for TcpStream {
+    fn shutdown(&mut self) -> io::Result<()> {
+        TcpStream::shutdown(self, Shutdown::Both)
+    }
+
+    fn take_error(&self) -> io::Result<Option<io::Error>> {
+        TcpStream::take_error(self)
+    }
+
+    fn set_write_timeout(&self, timeout: Option<Duration>) -> io::Result<()> {
+        TcpStream::set_write_timeout(self, timeout)
+    }
+}
+
+#[cfg(unix)]
+impl Transport for UnixStream {
+    fn shutdown(&mut self) -> io::Result<()> {
+        UnixStream::shutdown(self, Shutdown::Both)
+    }
+
+    fn take_error(&self) -> io::Result<Option<io::Error>> {
+        UnixStream::take_error(self)
+    }
+
+    fn set_write_timeout(&self, timeout: Option<Duration>) -> io::Result<()> {
+        UnixStream::set_write_timeout(self, timeout)
+    }
+}
+
+/// Connects to the engine the way it expects, over TCP with Nagle's algorithm turned off
+pub fn connect_tcp<A: ToSocketAddrs>(addr: A) -> io::Result<TcpStream> {
+    let addr = addr.to_socket_addrs()?.next()
+        .ok_or_else(|| io::Error::new(ErrorKind::AddrNotAvailable, "no addresses were sent to run on"))?;
+    let stream = TcpStream::connect_timeout(&addr, Duration::from_secs(CONNECT_TIMEOUT))?;
+    stream.set_nodelay(true)?;
+    Ok(stream)
+}
+
+/// Reads clauses from stdin and answers on stdout, for running under something that pipes the engine through.
+/// Anything else the bot prints to stdout ends up in front of the engine, so debug output has to go somewhere else.
+#[derive(Debug)]
+pub struct StdioTransport {
+    stdin: io::Stdin,
+    stdout: io::Stdout,
+}
+
+impl StdioTransport {
+    pub fn new() -> StdioTransport {
+        StdioTransport {
+            stdin: io::stdin(),
+            stdout: io::stdout(),
+        }
+    }
+}
+
+impl Default for StdioTransport {
+    fn default() -> StdioTransport {
+        StdioTransport::new()
+    }
+}
+
+impl Read for StdioTransport {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.stdin.read(buf)
+    }
+}
+
+impl Write for StdioTransport {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.stdout.write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.stdout.flush()
+    }
+}
+
+impl Transport for StdioTransport {}
+
+/// One end of an in-memory connection. Whatever is written to one end of a pair is read from the other, and reads
+/// block until something arrives, or until the other end is shut down or dropped.
+#[derive(Debug)]
+pub struct ChannelTransport {
+    incoming: Receiver<Vec<u8>>,
+    outgoing: Option<Sender<Vec<u8>>>,
+    // What is left of the last chunk we received
+    buffer: Vec<u8>,
+}
+
+impl ChannelTransport {
+    /// Makes both ends of a connection
+    pub fn pair() -> (ChannelTransport, ChannelTransport) {
+        let (first_sender, first_receiver) = channel();
+        let (second_sender, second_receiver) = channel();
+        (ChannelTransport::new(first_receiver, second_sender), ChannelTransport::new(second_receiver, first_sender))
+    }
+
+    fn new(incoming: Receiver<Vec<u8>>, outgoing: Sender<Vec<u8>>) -> ChannelTransport {
+        ChannelTransport {
+            incoming,
+            outgoing: Some(outgoing),
+            buffer: vec![],
+        }
+    }
+}
+
+impl Read for ChannelTransport {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        while self.buffer.is_empty() {
+            match self.incoming.recv() {
+                Ok(bytes) => self.buffer = bytes,
+                // The other end hung up
+                Err(_) => return Ok(0),
+            }
+        }
+        let read = buf.len().min(self.buffer.len());
+        buf[..read].copy_from_slice(&self.buffer[..read]);
+        self.buffer.drain(..read);
+        Ok(read)
+    }
+}
+
+impl Write for ChannelTransport {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let outgoing = self.outgoing.as_ref().ok_or_else(|| io::Error::new(ErrorKind::NotConnected, "transport was shut down"))?;
+        outgoing.send(buf.to_vec()).map_err(|_| io::Error::new(ErrorKind::BrokenPipe, "other end hung up"))?;
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl Transport for ChannelTransport {
+    fn shutdown(&mut self) -> io::Result<()> {
+        self.outgoing = None;
+        Ok(())
+    }
+}
+
+#[test]
+fn transport_test() {
+    use std::io::{BufRead, BufReader};
+    use std::thread;
+    let (mut engine, bot) = ChannelTransport::pair();
+    // An echo bot on another thread, which stops once the engine hangs up
+    let echo = thread::spawn(move || {
+        let mut bot = BufReader::new(bot);
+        let mut lines = 0;
+        loop {
+            let mut line = String::new();
+            if bot.read_line(&mut line).unwrap() == 0 {
+                break lines;
+            }
+            bot.get_mut().write_all(line.to_uppercase().as_bytes()).unwrap();
+            lines += 1;
+        }
+    });
+    // Lines can be split up however
+    engine.write_all(b"t29.5 p0 ").unwrap();
+    engine.write_all(b"hah,kd\nq").unwrap();
+    engine.write_all(b"\n").unwrap();
+    let mut replies = BufReader::new(engine);
+    let mut reply = String::new();
+    replies.read_line(&mut reply).unwrap();
+    assert_eq!(reply, "T29.5 P0 HAH,KD\n");
+    reply.clear();
+    replies.read_line(&mut reply).unwrap();
+    assert_eq!(reply, "Q\n");
+    Transport::shutdown(replies.get_mut()).unwrap();
+    assert!(replies.get_mut().write_all(b"K\n").is_err());
+    assert_eq!(echo.join().unwrap(), 2);
+    // The bot is gone, so there is nothing left to read
+    assert_eq!(replies.read_line(&mut reply).unwrap(), 0);
+
+    #[cfg(unix)]
+    {
+        let (mut engine, mut bot) = UnixStream::pair().unwrap();
+        engine.write_all(b"Q\n").unwrap();
+        Transport::shutdown(&mut engine).unwrap();
+        let mut received = String::new();
+        bot.read_to_string(&mut received).unwrap();
+        assert_eq!(received, "Q\n");
+        assert!(Transport::take_error(&bot).unwrap().is_none());
+    }
+}